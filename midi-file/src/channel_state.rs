@@ -0,0 +1,341 @@
+use crate::{MidiEvent, MidiTrack};
+use midly::{MidiMessage, PitchBend, num::u7};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+
+/// RPN/NRPN number used to deselect the parameter
+const NULL_PARAMETER: (u8, u8) = (127, 127);
+
+/// Value set through data entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParameterValue {
+    pub msb: u8,
+    pub lsb: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelectedParameter {
+    None,
+    Rpn,
+    Nrpn,
+}
+
+/// State of a single MIDI channel at a point in time
+#[derive(Debug, Clone)]
+pub struct ChannelState {
+    pub bank_msb: Option<u8>,
+    pub bank_lsb: Option<u8>,
+    pub program: u8,
+    /// Last value of every controller, except bank select, data entry, (N)RPN selection
+    /// and channel mode messages.
+    ///
+    /// Starts with GM defaults for volume, pan and expression
+    pub controllers: BTreeMap<u8, u8>,
+    pub pitch_bend: Option<PitchBend>,
+    /// Registered parameters, starts with GM default pitch bend range
+    pub rpn: BTreeMap<(u8, u8), ParameterValue>,
+    /// Non-registered parameters
+    pub nrpn: BTreeMap<(u8, u8), ParameterValue>,
+
+    rpn_select: (u8, u8),
+    nrpn_select: (u8, u8),
+    selected: SelectedParameter,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            bank_msb: None,
+            bank_lsb: None,
+            program: 0,
+            controllers: [(7, 100), (10, 64), (11, 127)].into_iter().collect(),
+            pitch_bend: None,
+            rpn: [(
+                (0, 0),
+                ParameterValue {
+                    msb: 2,
+                    lsb: Some(0),
+                },
+            )]
+            .into_iter()
+            .collect(),
+            nrpn: BTreeMap::new(),
+            rpn_select: NULL_PARAMETER,
+            nrpn_select: NULL_PARAMETER,
+            selected: SelectedParameter::None,
+        }
+    }
+}
+
+impl ChannelState {
+    pub fn apply(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::ProgramChange { program } => {
+                self.program = program.as_int();
+            }
+            MidiMessage::PitchBend { bend } => {
+                self.pitch_bend = Some(bend);
+            }
+            MidiMessage::Controller { controller, value } => {
+                self.apply_controller(controller.as_int(), value.as_int());
+            }
+            _ => {}
+        }
+    }
+
+    fn apply_controller(&mut self, controller: u8, value: u8) {
+        match controller {
+            BANK_SELECT_MSB => self.bank_msb = Some(value),
+            BANK_SELECT_LSB => self.bank_lsb = Some(value),
+            RPN_MSB => {
+                self.rpn_select.0 = value;
+                self.selected = SelectedParameter::Rpn;
+            }
+            RPN_LSB => {
+                self.rpn_select.1 = value;
+                self.selected = SelectedParameter::Rpn;
+            }
+            NRPN_MSB => {
+                self.nrpn_select.0 = value;
+                self.selected = SelectedParameter::Nrpn;
+            }
+            NRPN_LSB => {
+                self.nrpn_select.1 = value;
+                self.selected = SelectedParameter::Nrpn;
+            }
+            DATA_ENTRY_MSB | DATA_ENTRY_LSB => {
+                let (params, number) = match self.selected {
+                    SelectedParameter::Rpn => (&mut self.rpn, self.rpn_select),
+                    SelectedParameter::Nrpn => (&mut self.nrpn, self.nrpn_select),
+                    SelectedParameter::None => return,
+                };
+
+                if number == NULL_PARAMETER {
+                    return;
+                }
+
+                if controller == DATA_ENTRY_MSB {
+                    params.insert(
+                        number,
+                        ParameterValue {
+                            msb: value,
+                            lsb: None,
+                        },
+                    );
+                } else {
+                    params
+                        .entry(number)
+                        .or_insert(ParameterValue { msb: 0, lsb: None })
+                        .lsb = Some(value);
+                }
+            }
+            // Reset controllers listed in RP-015, volume, pan and the rest are kept
+            RESET_ALL_CONTROLLERS => {
+                for (&controller, value) in self.controllers.iter_mut() {
+                    match controller {
+                        1 | 64..=67 => *value = 0,
+                        11 => *value = 127,
+                        _ => {}
+                    }
+                }
+                self.pitch_bend = None;
+                self.rpn_select = NULL_PARAMETER;
+                self.nrpn_select = NULL_PARAMETER;
+                self.selected = SelectedParameter::None;
+            }
+            // Channel mode messages are not a state
+            120..=127 => {}
+            _ => {
+                self.controllers.insert(controller, value);
+            }
+        }
+    }
+
+    /// Messages that bring a channel to this state, no matter what state it was in before
+    pub fn messages(&self) -> Vec<MidiMessage> {
+        fn cc(controller: u8, value: u8) -> MidiMessage {
+            MidiMessage::Controller {
+                controller: u7::new(controller),
+                value: u7::new(value),
+            }
+        }
+
+        let mut out = vec![cc(RESET_ALL_CONTROLLERS, 0)];
+
+        if let Some(msb) = self.bank_msb {
+            out.push(cc(BANK_SELECT_MSB, msb));
+        }
+        if let Some(lsb) = self.bank_lsb {
+            out.push(cc(BANK_SELECT_LSB, lsb));
+        }
+        out.push(MidiMessage::ProgramChange {
+            program: u7::new(self.program),
+        });
+
+        for (&controller, &value) in self.controllers.iter() {
+            out.push(cc(controller, value));
+        }
+
+        let params = [
+            (RPN_MSB, RPN_LSB, &self.rpn),
+            (NRPN_MSB, NRPN_LSB, &self.nrpn),
+        ];
+        for (select_msb, select_lsb, params) in params {
+            for (&(msb, lsb), value) in params.iter() {
+                out.push(cc(select_msb, msb));
+                out.push(cc(select_lsb, lsb));
+                out.push(cc(DATA_ENTRY_MSB, value.msb));
+                if let Some(value) = value.lsb {
+                    out.push(cc(DATA_ENTRY_LSB, value));
+                }
+            }
+        }
+
+        // Restore the parameter selection, so that following data entry events land in the right place
+        let (select_msb, select_lsb, (msb, lsb)) = match self.selected {
+            SelectedParameter::Nrpn => (NRPN_MSB, NRPN_LSB, self.nrpn_select),
+            SelectedParameter::Rpn => (RPN_MSB, RPN_LSB, self.rpn_select),
+            SelectedParameter::None => (RPN_MSB, RPN_LSB, NULL_PARAMETER),
+        };
+        out.push(cc(select_msb, msb));
+        out.push(cc(select_lsb, lsb));
+
+        if let Some(bend) = self.pitch_bend {
+            out.push(MidiMessage::PitchBend { bend });
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StateEvent {
+    timestamp: Duration,
+    channel: u8,
+    message: MidiMessage,
+}
+
+/// Channel state changing events of all tracks, used to restore channel state after seeking
+#[derive(Debug, Clone)]
+pub struct ChannelStateTrack {
+    events: Arc<[StateEvent]>,
+}
+
+impl ChannelStateTrack {
+    pub fn new(tracks: &[MidiTrack]) -> Self {
+        Self::with_channel_map(tracks, |event| event.channel)
+    }
+
+    /// Same as [`Self::new`], but lets the caller decide which channel an event ends up on
+    pub fn with_channel_map(tracks: &[MidiTrack], channel: impl Fn(&MidiEvent) -> u8) -> Self {
+        let mut events: Vec<_> = tracks
+            .iter()
+            .flat_map(|track| track.events.iter())
+            .filter_map(|event| match event.message.as_midi() {
+                Some(
+                    message @ (MidiMessage::Controller { .. }
+                    | MidiMessage::ProgramChange { .. }
+                    | MidiMessage::PitchBend { .. }),
+                ) => Some(StateEvent {
+                    timestamp: event.timestamp,
+                    channel: channel(event) % 16,
+                    message: *message,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        // Stable sort, so that events with the same timestamp keep the file order
+        events.sort_by_key(|e| e.timestamp);
+
+        Self {
+            events: events.into(),
+        }
+    }
+
+    /// State of every channel after all events up to (and including) `timestamp` were applied
+    pub fn state_for_timestamp(&self, timestamp: &Duration) -> [ChannelState; 16] {
+        let mut channels: [ChannelState; 16] = std::array::from_fn(|_| ChannelState::default());
+
+        let end = self.events.partition_point(|e| e.timestamp <= *timestamp);
+        for event in self.events[..end].iter() {
+            channels[event.channel as usize].apply(&event.message);
+        }
+
+        channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc(controller: u8, value: u8) -> MidiMessage {
+        MidiMessage::Controller {
+            controller: u7::new(controller),
+            value: u7::new(value),
+        }
+    }
+
+    #[test]
+    fn rpn_data_entry() {
+        let mut state = ChannelState::default();
+        // Pitch bend range: 12 semitones
+        for msg in [cc(101, 0), cc(100, 0), cc(6, 12), cc(38, 0)] {
+            state.apply(&msg);
+        }
+        // Data entry with null parameter selected is ignored
+        for msg in [cc(101, 127), cc(100, 127), cc(6, 50)] {
+            state.apply(&msg);
+        }
+
+        assert_eq!(
+            state.rpn.get(&(0, 0)),
+            Some(&ParameterValue {
+                msb: 12,
+                lsb: Some(0)
+            })
+        );
+        assert_eq!(state.rpn.len(), 1);
+    }
+
+    #[test]
+    fn state_for_timestamp() {
+        let event = |ms: u64, message: MidiMessage| StateEvent {
+            timestamp: Duration::from_millis(ms),
+            channel: 1,
+            message,
+        };
+
+        let track = ChannelStateTrack {
+            events: vec![
+                event(0, cc(64, 127)),
+                event(
+                    10,
+                    MidiMessage::ProgramChange {
+                        program: u7::new(5),
+                    },
+                ),
+                event(20, cc(64, 0)),
+            ]
+            .into(),
+        };
+
+        let state = track.state_for_timestamp(&Duration::from_millis(10));
+        assert_eq!(state[1].controllers.get(&64), Some(&127));
+        assert_eq!(state[1].program, 5);
+        assert_eq!(state[0].program, 0);
+
+        let state = track.state_for_timestamp(&Duration::from_millis(20));
+        assert_eq!(state[1].controllers.get(&64), Some(&0));
+    }
+}
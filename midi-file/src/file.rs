@@ -53,7 +53,7 @@ impl MidiFile {
         let tempo_track = TempoTrack::build(&smf.tracks, u_per_quarter_note);
 
         let mut track_color_id = 0;
-        let tracks: Vec<MidiTrack> = smf
+        let mut tracks: Vec<MidiTrack> = smf
             .tracks
             .iter()
             .enumerate()
@@ -92,6 +92,9 @@ impl MidiFile {
         };
 
         let program_track = ProgramTrack::new(&tracks);
+        for track in tracks.iter_mut() {
+            track.detect_drums(&program_track);
+        }
 
         Ok(Self {
             name,
@@ -1,3 +1,4 @@
+pub mod channel_state;
 mod file;
 pub mod playback;
 pub mod program_track;
@@ -5,16 +5,111 @@ use std::{
     time::Duration,
 };
 
+/// Channel that is a rhythm part after a GM/GS/XG reset
+pub const DEFAULT_DRUM_CHANNEL: u8 = 9;
+
+/// Program selected on a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelProgram {
+    pub bank_msb: u8,
+    pub bank_lsb: u8,
+    pub program: u8,
+    /// Channel plays a drum kit, selected either through bank select or a rhythm part SysEx
+    pub is_drum: bool,
+}
+
+impl ChannelProgram {
+    fn new(channel: u8) -> Self {
+        Self {
+            bank_msb: 0,
+            bank_lsb: 0,
+            program: 0,
+            is_drum: channel == DEFAULT_DRUM_CHANNEL,
+        }
+    }
+}
+
+/// Change of a channel mode, requested through SysEx
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartMode {
+    /// GM/GS/XG system reset, only the default drum channel is a rhythm part
+    Reset,
+    /// GS "Use for rhythm part" or XG "Part mode"
+    Channel { channel: u8, is_drum: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct PartModeEvent {
+    pub timestamp: Duration,
+    pub mode: PartMode,
+}
+
+/// Bank MSB values that select a drum kit (XG drum/SFX kits, GM2 rhythm), or a melodic bank (GM2)
+///
+/// Returns `None` for banks that don't decide by themselves
+pub fn is_drum_bank(bank_msb: u8) -> Option<bool> {
+    match bank_msb {
+        120 | 126 | 127 => Some(true),
+        121 => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse GM/GS/XG SysEx messages that change which channels are rhythm parts.
+///
+/// `data` is the SysEx payload without the leading `0xF0`, the trailing `0xF7` is optional.
+pub fn parse_part_mode_sysex(data: &[u8]) -> Option<PartMode> {
+    let data = data.strip_suffix(&[0xF7]).unwrap_or(data);
+
+    match data {
+        // GM System On/Off, GM2 System On
+        [0x7E, _, 0x09, 0x01..=0x03] => Some(PartMode::Reset),
+        // GS Reset
+        [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, _] => Some(PartMode::Reset),
+        // XG System On
+        [0x43, _, 0x4C, 0x00, 0x00, 0x7E, 0x00] => Some(PartMode::Reset),
+        // GS Use For Rhythm Part
+        [0x41, _, 0x42, 0x12, 0x40, block, 0x15, value, _] if block & 0xF0 == 0x10 => {
+            // GS parts are numbered 1-16, with part 10 at block 0
+            let channel = match block & 0x0F {
+                0 => 9,
+                part @ 1..=9 => part - 1,
+                part => part,
+            };
+            Some(PartMode::Channel {
+                channel,
+                is_drum: *value != 0,
+            })
+        }
+        // XG Part Mode
+        [0x43, _, 0x4C, 0x08, part, 0x07, value] if *part < 16 => Some(PartMode::Channel {
+            channel: *part,
+            is_drum: *value != 0,
+        }),
+        _ => None,
+    }
+}
+
 /// HashMap<Channel, Program>
-fn default_programs() -> &'static HashMap<u8, u8> {
-    static DEFAULT_PROGRAMS: OnceLock<HashMap<u8, u8>> = OnceLock::new();
-    DEFAULT_PROGRAMS.get_or_init(|| (0..16).map(|ch| (ch, 0)).collect())
+fn default_programs() -> &'static HashMap<u8, ChannelProgram> {
+    static DEFAULT_PROGRAMS: OnceLock<HashMap<u8, ChannelProgram>> = OnceLock::new();
+    DEFAULT_PROGRAMS.get_or_init(|| (0..16).map(|ch| (ch, ChannelProgram::new(ch))).collect())
 }
 
 #[derive(Debug, Clone)]
 struct Bucket {
     timestamp: Duration,
-    map: HashMap<u8, u8>,
+    map: HashMap<u8, ChannelProgram>,
+}
+
+enum Change {
+    Program {
+        channel: u8,
+        bank_msb: u8,
+        bank_lsb: u8,
+        program: u8,
+    },
+    PartMode(PartMode),
 }
 
 #[derive(Debug, Clone)]
@@ -24,25 +119,71 @@ pub struct ProgramTrack {
 
 impl ProgramTrack {
     pub fn new(tracks: &[MidiTrack]) -> Self {
+        let mut changes: Vec<(Duration, Change)> = Vec::new();
+        for track in tracks {
+            changes.extend(
+                track
+                    .part_modes
+                    .iter()
+                    .map(|e| (e.timestamp, Change::PartMode(e.mode))),
+            );
+            changes.extend(track.programs.iter().map(|e| {
+                (
+                    e.timestamp,
+                    Change::Program {
+                        channel: e.channel,
+                        bank_msb: e.bank_msb,
+                        bank_lsb: e.bank_lsb,
+                        program: e.program,
+                    },
+                )
+            }));
+        }
+        // Part mode SysEx usually comes before the program changes it affects
+        changes.sort_by_key(|(timestamp, change)| {
+            (*timestamp, matches!(change, Change::Program { .. }))
+        });
+
         let mut map = default_programs().clone();
+        let mut rhythm_parts: [bool; 16] =
+            std::array::from_fn(|ch| ch as u8 == DEFAULT_DRUM_CHANNEL);
 
         // This map will help us get rid of duplicate events
         let mut program_events: HashMap<Duration, Bucket> = HashMap::new();
 
-        for track in tracks {
-            for event in track.events.iter() {
-                if let midly::MidiMessage::ProgramChange { program } = event.message {
-                    *map.entry(event.channel).or_default() = program.as_int();
-
-                    program_events.insert(
-                        event.timestamp,
-                        Bucket {
-                            timestamp: event.timestamp,
-                            map: map.clone(),
-                        },
-                    );
+        for (timestamp, change) in changes {
+            match change {
+                Change::Program {
+                    channel,
+                    bank_msb,
+                    bank_lsb,
+                    program,
+                } => {
+                    let entry = map.entry(channel).or_insert(ChannelProgram::new(channel));
+                    entry.bank_msb = bank_msb;
+                    entry.bank_lsb = bank_lsb;
+                    entry.program = program;
+                    entry.is_drum =
+                        is_drum_bank(bank_msb).unwrap_or(rhythm_parts[channel as usize % 16]);
+                }
+                Change::PartMode(PartMode::Reset) => {
+                    map = default_programs().clone();
+                    rhythm_parts = std::array::from_fn(|ch| ch as u8 == DEFAULT_DRUM_CHANNEL);
+                }
+                Change::PartMode(PartMode::Channel { channel, is_drum }) => {
+                    rhythm_parts[channel as usize % 16] = is_drum;
+                    let entry = map.entry(channel).or_insert(ChannelProgram::new(channel));
+                    entry.is_drum = is_drum_bank(entry.bank_msb).unwrap_or(is_drum);
                 }
             }
+
+            program_events.insert(
+                timestamp,
+                Bucket {
+                    timestamp,
+                    map: map.clone(),
+                },
+            );
         }
 
         let mut program_events: Vec<_> = program_events.into_values().collect();
@@ -54,7 +195,7 @@ impl ProgramTrack {
     }
 
     /// Search for program at certain timestamp
-    pub fn program_for_timestamp(&self, timestamp: &Duration) -> &HashMap<u8, u8> {
+    pub fn program_for_timestamp(&self, timestamp: &Duration) -> &HashMap<u8, ChannelProgram> {
         let res = self
             .events
             .binary_search_by_key(timestamp, |bucket| bucket.timestamp);
@@ -67,4 +208,45 @@ impl ProgramTrack {
         id.map(|id| &self.events[id].map)
             .unwrap_or_else(|| default_programs())
     }
+
+    /// Check if the channel plays a drum kit at certain timestamp
+    pub fn is_drum(&self, channel: u8, timestamp: &Duration) -> bool {
+        self.program_for_timestamp(timestamp)
+            .get(&channel)
+            .map(|p| p.is_drum)
+            .unwrap_or(channel == DEFAULT_DRUM_CHANNEL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_mode_sysex() {
+        // GS: part 11 (channel 10) used for rhythm part 1
+        let gs = [0x41, 0x10, 0x42, 0x12, 0x40, 0x1A, 0x15, 0x01, 0x10, 0xF7];
+        assert_eq!(
+            parse_part_mode_sysex(&gs),
+            Some(PartMode::Channel {
+                channel: 10,
+                is_drum: true
+            })
+        );
+
+        // XG: part 1 (channel 0) set to drum mode
+        let xg = [0x43, 0x10, 0x4C, 0x08, 0x00, 0x07, 0x02, 0xF7];
+        assert_eq!(
+            parse_part_mode_sysex(&xg),
+            Some(PartMode::Channel {
+                channel: 0,
+                is_drum: true
+            })
+        );
+
+        let gm_on = [0x7E, 0x7F, 0x09, 0x01, 0xF7];
+        assert_eq!(parse_part_mode_sysex(&gm_on), Some(PartMode::Reset));
+
+        assert_eq!(parse_part_mode_sysex(&[0x43, 0x10, 0x4C]), None);
+    }
 }
@@ -43,7 +43,7 @@ impl TempoTrack {
         tempo_events.sort_by_key(|e| e.absolute_pulses);
 
         let mut previous_absolute_pulses = 0u64;
-        let mut running_tempo = 500_000;
+        let mut running_tempo = DEFAULT_TEMPO;
         let mut res = Duration::ZERO;
 
         for tempo_event in tempo_events.iter_mut() {
@@ -84,16 +84,41 @@ impl TempoTrack {
         let (res, previous_absolute_pulses, tempo) = if let Some(event) = tempo_event {
             (event.timestamp, event.absolute_pulses, event.tempo)
         } else {
-            // 120 BPM
-            let default_tempo = 500_000;
-            (Duration::ZERO, 0, default_tempo)
+            (Duration::ZERO, 0, DEFAULT_TEMPO)
         };
 
         let delta_pulses = event_pulses - previous_absolute_pulses;
         res + pulse_to_duration(delta_pulses, tempo, self.pulses_per_quarter_note)
     }
+
+    /// Position in quarter notes of a point in time
+    pub fn duration_to_quarters(&self, time: Duration) -> f64 {
+        let id = self.events.partition_point(|e| e.timestamp <= time);
+        let (timestamp, pulses, tempo) = match id.checked_sub(1).map(|id| &self.events[id]) {
+            Some(event) => (event.timestamp, event.absolute_pulses, event.tempo),
+            None => (Duration::ZERO, 0, DEFAULT_TEMPO),
+        };
+
+        let quarters = pulses as f64 / self.pulses_per_quarter_note as f64;
+        quarters + (time - timestamp).as_micros() as f64 / tempo as f64
+    }
+
+    /// Point in time of a position in quarter notes
+    pub fn quarters_to_duration(&self, quarters: f64) -> Duration {
+        let pulses = quarters.max(0.0) * self.pulses_per_quarter_note as f64;
+        let (timestamp, event_pulses, tempo) = match self.tempo_event_for_pulses(pulses as u64) {
+            Some(event) => (event.timestamp, event.absolute_pulses, event.tempo),
+            None => (Duration::ZERO, 0, DEFAULT_TEMPO),
+        };
+
+        let delta = (pulses - event_pulses as f64) / self.pulses_per_quarter_note as f64;
+        timestamp + Duration::from_secs_f64(delta * tempo as f64 / 1_000_000.0)
+    }
 }
 
+/// 120 BPM, in microseconds per quarter note
+const DEFAULT_TEMPO: u32 = 500_000;
+
 fn pulse_to_duration(pulses: u64, tempo: u32, pulses_per_quarter_note: u16) -> Duration {
     let u_time = pulses as f64 / pulses_per_quarter_note as f64;
     // We floor only because Synthesia floors,
@@ -101,3 +126,27 @@ fn pulse_to_duration(pulses: u64, tempo: u32, pulses_per_quarter_note: u16) -> D
     let time = (u_time * tempo as f64).floor() as u64;
     Duration::from_micros(time)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarters_round_trip() {
+        // 120 BPM, then 60 BPM from the 4th quarter note
+        let track = TempoTrack {
+            pulses_per_quarter_note: 96,
+            events: [TempoEvent {
+                absolute_pulses: 4 * 96,
+                timestamp: Duration::from_secs(2),
+                tempo: 1_000_000,
+            }]
+            .into(),
+        };
+
+        assert_eq!(track.quarters_to_duration(1.0), Duration::from_millis(500));
+        assert_eq!(track.quarters_to_duration(5.5), Duration::from_millis(3500));
+        assert_eq!(track.duration_to_quarters(Duration::from_millis(3500)), 5.5);
+        assert_eq!(track.duration_to_quarters(Duration::from_millis(250)), 0.5);
+    }
+}
@@ -1,13 +1,35 @@
 use midly::{MidiMessage, TrackEvent, TrackEventKind, num::u4};
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use crate::tempo_track::TempoTrack;
+use crate::{
+    program_track::{PartModeEvent, ProgramTrack, parse_part_mode_sysex},
+    tempo_track::TempoTrack,
+};
+
+#[derive(Debug, Clone)]
+pub enum EventMessage {
+    Midi(MidiMessage),
+    /// SysEx payload, without the leading `0xF0`
+    SysEx(Arc<[u8]>),
+    /// Raw bytes of an escape event, usually realtime or system common messages
+    Escape(Arc<[u8]>),
+}
+
+impl EventMessage {
+    pub fn as_midi(&self) -> Option<&MidiMessage> {
+        match self {
+            Self::Midi(message) => Some(message),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct MidiEvent {
+    /// Channel of the message, always 0 for SysEx and escape events
     pub channel: u8,
     pub timestamp: Duration,
-    pub message: MidiMessage,
+    pub message: EventMessage,
     pub track_id: usize,
     pub track_color_id: usize,
 }
@@ -16,6 +38,8 @@ pub struct MidiEvent {
 pub struct ProgramEvent {
     pub channel: u8,
     pub timestamp: Duration,
+    pub bank_msb: u8,
+    pub bank_lsb: u8,
     pub program: u8,
 }
 
@@ -42,6 +66,9 @@ pub struct MidiTrack {
     pub track_color_id: usize,
 
     pub programs: Arc<[ProgramEvent]>,
+    /// Rhythm part changes requested through SysEx
+    pub part_modes: Arc<[PartModeEvent]>,
+    /// Filled in by [`MidiTrack::detect_drums`], as drum kits can be selected from other tracks
     pub has_drums: bool,
     pub has_other_than_drums: bool,
 }
@@ -57,9 +84,8 @@ impl MidiTrack {
             events,
             EventsBuilder {
                 programs,
+                part_modes,
                 notes,
-                has_drums,
-                has_other_than_drums,
                 ..
             },
         ) = build(track_id, track_color_id, tempo_track, track_events);
@@ -70,8 +96,23 @@ impl MidiTrack {
             notes: notes.into(),
             events: events.into(),
             programs: programs.into(),
-            has_drums,
-            has_other_than_drums,
+            part_modes: part_modes.into(),
+            has_drums: false,
+            has_other_than_drums: false,
+        }
+    }
+
+    /// Check which notes are played on drum kits
+    pub fn detect_drums(&mut self, program_track: &ProgramTrack) {
+        self.has_drums = false;
+        self.has_other_than_drums = false;
+
+        for note in self.notes.iter() {
+            if program_track.is_drum(note.channel, &note.start) {
+                self.has_drums = true;
+            } else {
+                self.has_other_than_drums = true;
+            }
         }
     }
 }
@@ -85,8 +126,9 @@ struct NoteInfo {
 #[derive(Default)]
 struct EventsBuilder {
     programs: Vec<ProgramEvent>,
-    has_drums: bool,
-    has_other_than_drums: bool,
+    part_modes: Vec<PartModeEvent>,
+    /// Bank select (MSB, LSB) of each channel
+    banks: [(u8, u8); 16],
 
     active_notes: HashMap<u8, NoteInfo>,
     notes: Vec<MidiNote>,
@@ -138,14 +180,6 @@ impl EventsBuilder {
         }
     }
 
-    fn check_for_drums(&mut self, channel: u4) {
-        if channel == 9 || channel == 15 {
-            self.has_drums = true;
-        } else {
-            self.has_other_than_drums = true;
-        }
-    }
-
     fn on_event(
         &mut self,
         channel: u4,
@@ -156,18 +190,28 @@ impl EventsBuilder {
     ) -> MidiEvent {
         let message = match message {
             midly::MidiMessage::NoteOn { key, vel } => {
-                self.check_for_drums(channel);
-
                 if vel.as_int() > 0 {
                     message
                 } else {
                     midly::MidiMessage::NoteOff { key, vel }
                 }
             }
+            midly::MidiMessage::Controller { controller, value } => {
+                let bank = &mut self.banks[channel.as_int() as usize];
+                match controller.as_int() {
+                    0 => bank.0 = value.as_int(),
+                    32 => bank.1 = value.as_int(),
+                    _ => {}
+                }
+                message
+            }
             midly::MidiMessage::ProgramChange { program } => {
+                let (bank_msb, bank_lsb) = self.banks[channel.as_int() as usize];
                 self.programs.push(ProgramEvent {
                     timestamp,
                     channel: channel.as_int(),
+                    bank_msb,
+                    bank_lsb,
                     program: program.as_int(),
                 });
                 message
@@ -180,7 +224,7 @@ impl EventsBuilder {
         MidiEvent {
             channel: channel.as_int(),
             timestamp,
-            message,
+            message: EventMessage::Midi(message),
             track_id,
             track_color_id,
         }
@@ -205,6 +249,29 @@ fn build(
                     let timestamp = tempo_track.pulses_to_duration(pulses);
                     Some(builder.on_event(channel, message, timestamp, track_id, track_color_id))
                 }
+                TrackEventKind::SysEx(data) => {
+                    let timestamp = tempo_track.pulses_to_duration(pulses);
+                    if let Some(mode) = parse_part_mode_sysex(data) {
+                        builder.part_modes.push(PartModeEvent { timestamp, mode });
+                    }
+                    Some(MidiEvent {
+                        channel: 0,
+                        timestamp,
+                        message: EventMessage::SysEx(data.into()),
+                        track_id,
+                        track_color_id,
+                    })
+                }
+                TrackEventKind::Escape(data) => {
+                    let timestamp = tempo_track.pulses_to_duration(pulses);
+                    Some(MidiEvent {
+                        channel: 0,
+                        timestamp,
+                        message: EventMessage::Escape(data.into()),
+                        track_id,
+                        track_color_id,
+                    })
+                }
                 _ => None,
             }
         })
@@ -1,4 +1,10 @@
-use std::{error::Error, fmt};
+use std::{error::Error, fmt, net::SocketAddr};
+
+mod network;
+use network::{NetworkInput, NetworkOutput};
+
+mod watcher;
+pub use watcher::{PortEvent, PortWatcher};
 
 /// An error that can occur during initialization (i.e., while
 /// creating a `MidiInput` or `MidiOutput` object).
@@ -13,6 +19,20 @@ impl fmt::Display for InitError {
     }
 }
 
+/// Virtual ports are only created on Linux, through ALSA or JACK
+const HAS_VIRTUAL_PORTS: bool = cfg!(target_os = "linux");
+
+/// Client that the virtual ports belong to, as other apps see it
+const VIRTUAL_CLIENT: &str = "Neothesia";
+
+/// Ports of our own client, they are listed as virtual ports rather than as system ones
+fn is_own_port(name: &str) -> bool {
+    HAS_VIRTUAL_PORTS
+        && name
+            .strip_prefix(VIRTUAL_CLIENT)
+            .is_some_and(|rest| rest.starts_with(':'))
+}
+
 impl From<midir::InitError> for InitError {
     fn from(_: midir::InitError) -> Self {
         Self
@@ -30,28 +50,50 @@ impl MidiOutputManager {
         Ok(Self { output })
     }
 
+    /// Ports of the system, followed by the virtual port that other apps can listen to
     pub fn outputs(&self) -> Vec<MidiOutputPort> {
         self.output
             .ports()
             .iter()
             .filter_map(|p| self.output.port_name(p).ok())
-            .map(MidiOutputPort)
+            .filter(|name| !is_own_port(name))
+            .map(MidiOutputPort::system)
+            .chain(MidiOutputPort::virtual_port())
             .collect()
     }
 
     pub fn connect_output(port: MidiOutputPort) -> Option<MidiOutputConnection> {
+        if let PortKind::Network(peer) = port.kind {
+            return NetworkOutput::connect(peer)
+                .inspect_err(|err| log::error!("Network MIDI-out fail: {err}"))
+                .ok()
+                .map(|conn| MidiOutputConnection(OutputConnectionKind::Network(conn)));
+        }
+
+        #[cfg(target_os = "linux")]
+        if port.kind == PortKind::Virtual {
+            use midir::os::unix::VirtualOutput;
+
+            return midir::MidiOutput::new(VIRTUAL_CLIENT)
+                .ok()?
+                .create_virtual(&port.name)
+                .inspect_err(|err| log::error!("Virtual MIDI-out port fail: {err}"))
+                .ok()
+                .map(|conn| MidiOutputConnection(OutputConnectionKind::Midir(conn)));
+        }
+
         let output = midir::MidiOutput::new("MidiIo-out").unwrap();
 
         let port = output.ports().into_iter().find(|info| {
             output
                 .port_name(info)
                 .ok()
-                .map(|name| name == port.0)
+                .map(|name| name == port.name)
                 .unwrap_or(false)
         });
 
         port.and_then(move |port| output.connect(&port, "MidiIo-in-conn").ok())
-            .map(MidiOutputConnection)
+            .map(|conn| MidiOutputConnection(OutputConnectionKind::Midir(conn)))
     }
 }
 
@@ -66,26 +108,55 @@ impl MidiInputManager {
         Ok(Self { input })
     }
 
+    /// Ports of the system, followed by the virtual port that other apps can send to
     pub fn inputs(&self) -> Vec<MidiInputPort> {
         self.input
             .ports()
             .iter()
             .filter_map(|p| self.input.port_name(p).ok())
-            .map(MidiInputPort)
+            .filter(|name| !is_own_port(name))
+            .map(MidiInputPort::system)
+            .chain(MidiInputPort::virtual_port())
             .collect()
     }
 
+    /// Listen to a port, the callback gets the device timestamp in microseconds and the raw bytes
     pub fn connect_input<F>(port: MidiInputPort, mut callback: F) -> Option<MidiInputConnection>
     where
-        F: FnMut(&[u8]) + Send + 'static,
+        F: FnMut(u64, &[u8]) + Send + 'static,
     {
+        if let PortKind::Network(addr) = port.kind {
+            return NetworkInput::listen(addr, callback)
+                .inspect_err(|err| log::error!("Network MIDI-in fail: {err}"))
+                .ok()
+                .map(|conn| MidiInputConnection(InputConnectionKind::Network(conn)));
+        }
+
+        #[cfg(target_os = "linux")]
+        if port.kind == PortKind::Virtual {
+            use midir::os::unix::VirtualInput;
+
+            return midir::MidiInput::new(VIRTUAL_CLIENT)
+                .ok()?
+                .create_virtual(
+                    &port.name,
+                    move |stamp, data, _| {
+                        callback(stamp, data);
+                    },
+                    (),
+                )
+                .inspect_err(|err| log::error!("Virtual MIDI-in port fail: {err}"))
+                .ok()
+                .map(|conn| MidiInputConnection(InputConnectionKind::Midir(conn)));
+        }
+
         let input = midir::MidiInput::new("MidiIo-in").unwrap();
 
         let port = input.ports().into_iter().find(|info| {
             input
                 .port_name(info)
                 .ok()
-                .map(|name| name == port.0)
+                .map(|name| name == port.name)
                 .unwrap_or(false)
         });
 
@@ -94,46 +165,133 @@ impl MidiInputManager {
                 .connect(
                     &port,
                     "MidiIo-in-conn",
-                    move |_, data, _| {
-                        callback(data);
-                        //
+                    move |stamp, data, _| {
+                        callback(stamp, data);
                     },
                     (),
                 )
                 .inspect_err(|err| log::error!("MIDI-in connection fail: {err}"))
                 .ok()
         })
-        .map(MidiInputConnection)
+        .map(|conn| MidiInputConnection(InputConnectionKind::Midir(conn)))
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PortKind {
+    /// Hardware port, or a port of another app
+    System,
+    /// Port created by us, for other apps to connect to
+    Virtual,
+    /// OSC over UDP, to or from this address
+    Network(SocketAddr),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct MidiOutputPort(String);
+pub struct MidiOutputPort {
+    name: String,
+    kind: PortKind,
+}
+
+impl MidiOutputPort {
+    fn system(name: String) -> Self {
+        Self {
+            name,
+            kind: PortKind::System,
+        }
+    }
+
+    fn virtual_port() -> Option<Self> {
+        HAS_VIRTUAL_PORTS.then(|| Self {
+            name: format!("{VIRTUAL_CLIENT} Virtual Out"),
+            kind: PortKind::Virtual,
+        })
+    }
+
+    /// Sends OSC messages to `peer`, see the `network` module for how MIDI is mapped
+    pub fn network(peer: SocketAddr) -> Self {
+        Self {
+            name: format!("Network ({peer})"),
+            kind: PortKind::Network(peer),
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.kind == PortKind::Virtual
+    }
+}
 
 impl std::fmt::Display for MidiOutputPort {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.name)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct MidiInputPort(String);
+pub struct MidiInputPort {
+    name: String,
+    kind: PortKind,
+}
+
+impl MidiInputPort {
+    fn system(name: String) -> Self {
+        Self {
+            name,
+            kind: PortKind::System,
+        }
+    }
+
+    fn virtual_port() -> Option<Self> {
+        HAS_VIRTUAL_PORTS.then(|| Self {
+            name: format!("{VIRTUAL_CLIENT} Virtual In"),
+            kind: PortKind::Virtual,
+        })
+    }
+
+    /// Listens for OSC messages on `addr`, see the `network` module for how MIDI is mapped
+    pub fn network(addr: SocketAddr) -> Self {
+        Self {
+            name: format!("Network (port {})", addr.port()),
+            kind: PortKind::Network(addr),
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.kind == PortKind::Virtual
+    }
+}
 
 impl std::fmt::Display for MidiInputPort {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.name)
     }
 }
 
-#[allow(unused)]
-pub struct MidiInputConnection(midir::MidiInputConnection<()>);
-pub struct MidiOutputConnection(midir::MidiOutputConnection);
+/// Open input, it's closed on drop
+pub struct MidiInputConnection(#[allow(unused)] InputConnectionKind);
+
+enum InputConnectionKind {
+    Midir(#[allow(unused)] midir::MidiInputConnection<()>),
+    Network(#[allow(unused)] NetworkInput),
+}
+
+pub struct MidiOutputConnection(OutputConnectionKind);
+
+enum OutputConnectionKind {
+    Midir(midir::MidiOutputConnection),
+    Network(NetworkOutput),
+}
 
 impl MidiOutputConnection {
     /// Send a message to the port that this output connection is connected to.
     /// The message must be a valid MIDI message (see https://www.midi.org/specifications-old/item/table-1-summary-of-midi-message).
     pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
-        self.0.send(message)?;
+        match &mut self.0 {
+            OutputConnectionKind::Midir(conn) => conn.send(message)?,
+            OutputConnectionKind::Network(conn) => conn
+                .send(message)
+                .map_err(|_| SendError::Other("network send failed"))?,
+        }
         Ok(())
     }
 }
@@ -163,3 +321,16 @@ impl From<midir::SendError> for SendError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn own_ports_are_hidden() {
+        assert!(is_own_port("Neothesia:Neothesia Virtual Out 128:0"));
+        assert!(!is_own_port("Neothesia Keys:Neothesia Keys MIDI 1 20:0"));
+        assert!(!is_own_port("Midi Through:Midi Through Port-0 14:0"));
+    }
+}
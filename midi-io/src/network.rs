@@ -0,0 +1,342 @@
+//! MIDI over OSC/UDP, so that Neothesia can be played from another machine.
+//!
+//! Channel messages are mapped to these OSC messages, channels are counted from 1:
+//! - `/midi/noteon channel key velocity`
+//! - `/midi/noteoff channel key velocity`
+//! - `/midi/cc channel controller value`
+//! - `/midi m` for every other message, with the standard OSC MIDI argument
+//!
+//! Arguments can be sent as ints or floats. Bundles are unpacked, their time tags are ignored.
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+const NOTE_ON: &str = "/midi/noteon";
+const NOTE_OFF: &str = "/midi/noteoff";
+const CONTROL_CHANGE: &str = "/midi/cc";
+const MIDI: &str = "/midi";
+
+/// How often the listener checks if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub(crate) struct NetworkOutput {
+    socket: UdpSocket,
+    peer: SocketAddr,
+    buf: Vec<u8>,
+}
+
+impl NetworkOutput {
+    pub fn connect(peer: SocketAddr) -> io::Result<Self> {
+        let bind: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        // Lets a whole classroom listen, when sent to a broadcast address
+        socket.set_broadcast(true).ok();
+
+        Ok(Self {
+            socket,
+            peer,
+            buf: Vec::with_capacity(32),
+        })
+    }
+
+    /// Send a MIDI message, the ones that OSC has no mapping for are skipped
+    pub fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.buf.clear();
+        if encode(message, &mut self.buf) {
+            self.socket.send_to(&self.buf, self.peer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives OSC packets on a background thread, and hands out the MIDI messages in them
+pub(crate) struct NetworkInput {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NetworkInput {
+    pub fn listen<F>(addr: SocketAddr, callback: F) -> io::Result<Self>
+    where
+        F: FnMut(u64, &[u8]) + Send + 'static,
+    {
+        Self::from_socket(UdpSocket::bind(addr)?, callback)
+    }
+
+    fn from_socket<F>(socket: UdpSocket, mut callback: F) -> io::Result<Self>
+    where
+        F: FnMut(u64, &[u8]) + Send + 'static,
+    {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("midi-network-in".into())
+            .spawn({
+                let stop = stop.clone();
+                move || {
+                    let started_at = Instant::now();
+                    let mut buf = [0; 1536];
+
+                    while !stop.load(Ordering::Relaxed) {
+                        let len = match socket.recv(&mut buf) {
+                            Ok(len) => len,
+                            Err(err)
+                                if matches!(
+                                    err.kind(),
+                                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                                ) =>
+                            {
+                                continue;
+                            }
+                            Err(err) => {
+                                log::error!("Network MIDI input failed: {err}");
+                                break;
+                            }
+                        };
+
+                        let stamp = started_at.elapsed().as_micros() as u64;
+                        decode(&buf[..len], &mut |message| callback(stamp, message));
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for NetworkInput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// OSC packet of a MIDI message, returns `false` if it can't be mapped
+fn encode(message: &[u8], out: &mut Vec<u8>) -> bool {
+    let [status, data @ ..] = message else {
+        return false;
+    };
+    // Only channel messages, anything else is meant for the devices on the wire
+    if !(0x80..0xF0).contains(status) {
+        return false;
+    }
+    let channel = (status & 0x0F) as i32 + 1;
+
+    match (status & 0xF0, data) {
+        (0x90, &[key, vel]) => write_ints(out, NOTE_ON, &[channel, key as i32, vel as i32]),
+        (0x80, &[key, vel]) => write_ints(out, NOTE_OFF, &[channel, key as i32, vel as i32]),
+        (0xB0, &[ctrl, value]) => {
+            write_ints(out, CONTROL_CHANGE, &[channel, ctrl as i32, value as i32])
+        }
+        _ => {
+            let mut midi = [0; 4];
+            for (dst, src) in midi[1..].iter_mut().zip(message) {
+                *dst = *src;
+            }
+            write_str(out, MIDI);
+            write_str(out, ",m");
+            out.extend_from_slice(&midi);
+        }
+    }
+    true
+}
+
+/// Calls `f` with every MIDI message of an OSC packet, unknown and malformed ones are skipped
+fn decode(packet: &[u8], f: &mut impl FnMut(&[u8])) {
+    if let Some(mut elements) = packet.strip_prefix(b"#bundle\0") {
+        // Time tag
+        elements = elements.get(8..).unwrap_or_default();
+        while let Some((len, rest)) = read_i32(elements) {
+            let Some(element) = rest.get(..len.max(0) as usize) else {
+                return;
+            };
+            decode(element, f);
+            elements = &rest[element.len()..];
+        }
+        return;
+    }
+
+    let Some((address, rest)) = read_str(packet) else {
+        return;
+    };
+    let Some((tags, mut args)) = read_str(rest) else {
+        return;
+    };
+    let Some(tags) = tags.strip_prefix(',') else {
+        return;
+    };
+
+    if address == MIDI {
+        for tag in tags.chars() {
+            if tag != 'm' {
+                return;
+            }
+            let Some((midi, rest)) = args.split_at_checked(4) else {
+                return;
+            };
+            let len = match midi[1] {
+                0xC0..=0xDF => 2,
+                0x80..=0xEF => 3,
+                _ => return,
+            };
+            f(&midi[1..1 + len]);
+            args = rest;
+        }
+        return;
+    }
+
+    let status = match address {
+        NOTE_ON => 0x90,
+        NOTE_OFF => 0x80,
+        CONTROL_CHANGE => 0xB0,
+        _ => return,
+    };
+
+    let mut values = [0u8; 3];
+    let mut count = 0;
+    for tag in tags.chars() {
+        let Some((value, rest)) = (match tag {
+            'i' => read_i32(args).map(|(v, rest)| (v as f32, rest)),
+            'f' => read_f32(args),
+            _ => None,
+        }) else {
+            return;
+        };
+        if count == values.len() {
+            return;
+        }
+        values[count] = value.round().clamp(0.0, 127.0) as u8;
+        count += 1;
+        args = rest;
+    }
+    if count != values.len() {
+        return;
+    }
+
+    let [channel, data1, data2] = values;
+    let channel = channel.clamp(1, 16) - 1;
+    f(&[status | channel, data1, data2]);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    // Null terminated, padded to 4 bytes
+    let pad = 4 - s.len() % 4;
+    out.extend(std::iter::repeat_n(0, pad));
+}
+
+fn write_ints(out: &mut Vec<u8>, address: &str, values: &[i32]) {
+    write_str(out, address);
+    let tags: String = std::iter::once(',')
+        .chain(values.iter().map(|_| 'i'))
+        .collect();
+    write_str(out, &tags);
+    for value in values {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn read_str(data: &[u8]) -> Option<(&str, &[u8])> {
+    let len = data.iter().position(|b| *b == 0)?;
+    let s = std::str::from_utf8(&data[..len]).ok()?;
+    let padded = (len / 4 + 1) * 4;
+    Some((s, data.get(padded..)?))
+}
+
+fn read_i32(data: &[u8]) -> Option<(i32, &[u8])> {
+    let (value, rest) = data.split_first_chunk::<4>()?;
+    Some((i32::from_be_bytes(*value), rest))
+}
+
+fn read_f32(data: &[u8]) -> Option<(f32, &[u8])> {
+    let (value, rest) = data.split_first_chunk::<4>()?;
+    Some((f32::from_be_bytes(*value), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: &[u8]) -> Vec<Vec<u8>> {
+        let mut packet = Vec::new();
+        assert!(encode(message, &mut packet));
+        assert_eq!(packet.len() % 4, 0);
+
+        let mut out = Vec::new();
+        decode(&packet, &mut |message| out.push(message.to_vec()));
+        out
+    }
+
+    #[test]
+    fn osc_round_trip() {
+        assert_eq!(round_trip(&[0x91, 60, 100]), vec![vec![0x91, 60, 100]]);
+        assert_eq!(round_trip(&[0x80, 60, 0]), vec![vec![0x80, 60, 0]]);
+        assert_eq!(round_trip(&[0xBF, 64, 127]), vec![vec![0xBF, 64, 127]]);
+        assert_eq!(round_trip(&[0xE0, 0, 64]), vec![vec![0xE0, 0, 64]]);
+        assert_eq!(round_trip(&[0xC3, 5]), vec![vec![0xC3, 5]]);
+
+        assert!(!encode(&[0xF8], &mut Vec::new()));
+    }
+
+    #[test]
+    fn osc_floats_and_bundles() {
+        let mut message = Vec::new();
+        write_str(&mut message, NOTE_ON);
+        write_str(&mut message, ",fff");
+        for value in [2.0f32, 61.0, 99.6] {
+            message.extend_from_slice(&value.to_be_bytes());
+        }
+
+        let mut bundle = b"#bundle\0".to_vec();
+        bundle.extend_from_slice(&[0; 8]);
+        for _ in 0..2 {
+            bundle.extend_from_slice(&(message.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(&message);
+        }
+
+        let mut out = Vec::new();
+        decode(&bundle, &mut |message| out.push(message.to_vec()));
+        assert_eq!(out, vec![vec![0x91, 61, 100]; 2]);
+
+        // Cut short
+        decode(&bundle[..bundle.len() - 3], &mut |_| {});
+        decode(&message[..message.len() - 2], &mut |_| panic!());
+    }
+
+    #[test]
+    fn loopback() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let _input = NetworkInput::from_socket(socket, move |_, message| {
+            tx.send(message.to_vec()).ok();
+        })
+        .unwrap();
+
+        let mut output = NetworkOutput::connect(addr).unwrap();
+        output.send(&[0x90, 60, 100]).unwrap();
+        output.send(&[0xB0, 64, 0]).unwrap();
+
+        let timeout = Duration::from_secs(2);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), vec![0x90, 60, 100]);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), vec![0xB0, 64, 0]);
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::{InitError, MidiInputManager, MidiInputPort, MidiOutputManager, MidiOutputPort};
+
+/// A MIDI port that showed up or went away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortEvent {
+    InputAdded(MidiInputPort),
+    InputRemoved(MidiInputPort),
+    OutputAdded(MidiOutputPort),
+    OutputRemoved(MidiOutputPort),
+}
+
+/// Watches the system for MIDI ports being plugged in or out.
+///
+/// Not every backend reports hot-plug events, so the port lists are polled on a background thread.
+pub struct PortWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PortWatcher {
+    pub fn new<F>(interval: Duration, mut callback: F) -> Result<Self, InitError>
+    where
+        F: FnMut(PortEvent) + Send + 'static,
+    {
+        let input = MidiInputManager::new()?;
+        let output = MidiOutputManager::new()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::Builder::new()
+            .name("midi-port-watcher".into())
+            .spawn({
+                let stop = stop.clone();
+                move || {
+                    let mut inputs = input.inputs();
+                    let mut outputs = output.outputs();
+
+                    while !stop.load(Ordering::Relaxed) {
+                        std::thread::sleep(interval);
+
+                        let new_inputs = input.inputs();
+                        let (added, removed) = diff(&inputs, &new_inputs);
+                        removed
+                            .into_iter()
+                            .map(PortEvent::InputRemoved)
+                            .chain(added.into_iter().map(PortEvent::InputAdded))
+                            .for_each(&mut callback);
+                        inputs = new_inputs;
+
+                        let new_outputs = output.outputs();
+                        let (added, removed) = diff(&outputs, &new_outputs);
+                        removed
+                            .into_iter()
+                            .map(PortEvent::OutputRemoved)
+                            .chain(added.into_iter().map(PortEvent::OutputAdded))
+                            .for_each(&mut callback);
+                        outputs = new_outputs;
+                    }
+                }
+            })
+            .map_err(|_| InitError)?;
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for PortWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Ports that were added and removed between two listings
+fn diff<T: PartialEq + Clone>(old: &[T], new: &[T]) -> (Vec<T>, Vec<T>) {
+    let added = new.iter().filter(|p| !old.contains(p)).cloned().collect();
+    let removed = old.iter().filter(|p| !new.contains(p)).cloned().collect();
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_list_diff() {
+        let old = ["Keyboard", "Pedals"];
+        let new = ["Pedals", "Pads"];
+
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(added, vec!["Pads"]);
+        assert_eq!(removed, vec!["Keyboard"]);
+    }
+}
@@ -0,0 +1,348 @@
+//! Minimal 16-bit stereo FLAC encoder, using fixed predictors and Rice coded residuals
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+const BLOCK_SIZE: usize = 4096;
+const CHANNELS: u64 = 2;
+const BITS_PER_SAMPLE: u64 = 16;
+const MAX_FIXED_ORDER: usize = 4;
+/// Rice parameter 15 is reserved for the escape code
+const MAX_RICE_PARAM: u32 = 14;
+/// "fLaC" marker and the STREAMINFO block header
+const STREAMINFO_OFFSET: u64 = 8;
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// Write `bits` lowest bits of `value`, `bits` has to be at most 32
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1 << self.bits) - 1;
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Frame number, coded the same way UTF-8 codes characters
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+
+        let len = match value {
+            0x80..0x800 => 2,
+            0x800..0x10000 => 3,
+            0x10000..0x200000 => 4,
+            0x200000..0x4000000 => 5,
+            0x4000000..0x80000000 => 6,
+            _ => 7,
+        };
+
+        // `len` leading ones, followed by the top bits of the value
+        let prefix = (0xFF00u64 >> len) & 0xFF;
+        self.write(prefix | (value >> (6 * (len - 1))), 8);
+        for i in (0..len - 1).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn fixed_residuals(samples: &[i32], order: usize) -> Vec<i32> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |back: usize| samples[i - back];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+fn zigzag(residual: i32) -> u64 {
+    ((residual << 1) ^ (residual >> 31)) as u32 as u64
+}
+
+/// Best Rice parameter and the amount of bits it takes to code the residuals with it
+fn rice_cost(residuals: &[i32]) -> (u32, u64) {
+    let folded: Vec<u64> = residuals.iter().map(|r| zigzag(*r)).collect();
+    (0..=MAX_RICE_PARAM)
+        .map(|k| {
+            let bits =
+                folded.len() as u64 * (k as u64 + 1) + folded.iter().map(|u| u >> k).sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap_or((0, 0))
+}
+
+fn write_subframe(bw: &mut BitWriter, samples: &[i32]) {
+    // Zero padding bit, subframe type, no wasted bits
+    let header = |bw: &mut BitWriter, kind: u64| {
+        bw.write(0, 1);
+        bw.write(kind, 6);
+        bw.write(0, 1);
+    };
+
+    if samples.iter().all(|s| *s == samples[0]) {
+        header(bw, 0b000000);
+        bw.write(samples[0] as u64, BITS_PER_SAMPLE as u32);
+        return;
+    }
+
+    let best = (0..=MAX_FIXED_ORDER.min(samples.len()))
+        .map(|order| {
+            let residuals = fixed_residuals(samples, order);
+            let (k, bits) = rice_cost(&residuals);
+            (order, residuals, k, bits + order as u64 * BITS_PER_SAMPLE)
+        })
+        .min_by_key(|(_, _, _, bits)| *bits);
+
+    match best {
+        Some((order, residuals, k, bits)) if bits < samples.len() as u64 * BITS_PER_SAMPLE => {
+            header(bw, 0b001000 | order as u64);
+            for sample in &samples[..order] {
+                bw.write(*sample as u64, BITS_PER_SAMPLE as u32);
+            }
+            // Rice coding with 4-bit parameter, single partition
+            bw.write(0b00, 2);
+            bw.write(0, 4);
+            bw.write(k as u64, 4);
+            for residual in residuals {
+                let u = zigzag(residual);
+                bw.write_unary(u >> k);
+                bw.write(u, k);
+            }
+        }
+        _ => {
+            header(bw, 0b000001);
+            for sample in samples {
+                bw.write(*sample as u64, BITS_PER_SAMPLE as u32);
+            }
+        }
+    }
+}
+
+fn encode_frame(frame_number: u64, left: &[i32], right: &[i32]) -> Vec<u8> {
+    let block_size = left.len();
+    let mut bw = BitWriter::default();
+
+    // Sync code, reserved bit, fixed block size
+    bw.write(0b11111111111110, 14);
+    bw.write(0, 1);
+    bw.write(0, 1);
+    // Block size: 4096, or 16-bit value at the end of the header
+    bw.write(
+        if block_size == BLOCK_SIZE {
+            0b1100
+        } else {
+            0b0111
+        },
+        4,
+    );
+    // Sample rate from STREAMINFO
+    bw.write(0b0000, 4);
+    // Independent left and right channels
+    bw.write(0b0001, 4);
+    // 16 bits per sample, reserved bit
+    bw.write(0b100, 3);
+    bw.write(0, 1);
+    bw.write_utf8(frame_number);
+    if block_size != BLOCK_SIZE {
+        bw.write(block_size as u64 - 1, 16);
+    }
+    let crc = crc8(&bw.bytes);
+    bw.write(crc as u64, 8);
+
+    write_subframe(&mut bw, left);
+    write_subframe(&mut bw, right);
+
+    bw.align();
+    let crc = crc16(&bw.bytes);
+    bw.write(crc as u64, 16);
+
+    bw.bytes
+}
+
+/// 16-bit stereo FLAC writer
+pub struct FlacWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+
+    left: Vec<i32>,
+    right: Vec<i32>,
+
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(b"fLaC")?;
+
+        let mut this = Self {
+            out,
+            sample_rate,
+            left: Vec::with_capacity(BLOCK_SIZE),
+            right: Vec::with_capacity(BLOCK_SIZE),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+        };
+
+        // Last metadata block, STREAMINFO, 34 bytes long
+        this.out.write_all(&[0x80, 0x00, 0x00, 34])?;
+        // Filled in by `finish`
+        this.write_streaminfo()?;
+
+        Ok(this)
+    }
+
+    fn write_streaminfo(&mut self) -> io::Result<()> {
+        let block_size = if self.frame_number > 1 {
+            BLOCK_SIZE as u64
+        } else {
+            self.total_samples.clamp(16, BLOCK_SIZE as u64)
+        };
+
+        let mut bw = BitWriter::default();
+        bw.write(block_size, 16);
+        bw.write(BLOCK_SIZE as u64, 16);
+        bw.write(self.min_frame_size as u64, 24);
+        bw.write(self.max_frame_size as u64, 24);
+        bw.write(self.sample_rate as u64, 20);
+        bw.write(CHANNELS - 1, 3);
+        bw.write(BITS_PER_SAMPLE - 1, 5);
+        bw.write(self.total_samples >> 32, 4);
+        bw.write(self.total_samples, 32);
+        // Unknown MD5 signature
+        for _ in 0..4 {
+            bw.write(0, 32);
+        }
+
+        self.out.write_all(&bw.bytes)
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        if self.left.is_empty() {
+            return Ok(());
+        }
+
+        let frame = encode_frame(self.frame_number, &self.left, &self.right);
+        self.out.write_all(&frame)?;
+
+        let size = frame.len() as u32;
+        self.min_frame_size = if self.frame_number == 0 {
+            size
+        } else {
+            self.min_frame_size.min(size)
+        };
+        self.max_frame_size = self.max_frame_size.max(size);
+
+        self.frame_number += 1;
+        self.total_samples += self.left.len() as u64;
+        self.left.clear();
+        self.right.clear();
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, l: i16, r: i16) -> io::Result<()> {
+        self.left.push(l as i32);
+        self.right.push(r as i32);
+
+        if self.left.len() == BLOCK_SIZE {
+            self.write_frame()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.write_frame()?;
+
+        self.out.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.write_streaminfo()?;
+
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn utf8_frame_number() {
+        let mut bw = BitWriter::default();
+        bw.write_utf8(0x7F);
+        bw.write_utf8(0x80);
+        bw.write_utf8(0x1234);
+        assert_eq!(bw.bytes, [0x7F, 0xC2, 0x80, 0xE1, 0x88, 0xB4]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Headless audio-only rendering, runs the song through oxisynth without touching the GPU
+
+mod flac;
+mod wav;
+
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{cli, libmidi_to_oxisynth_event};
+
+/// Samples rendered between event dispatches
+const BLOCK_SIZE: usize = 64;
+/// Time rendered after the last note, so that releases and reverb can fade out
+const TAIL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+}
+
+impl AudioFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "wav" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            _ => None,
+        }
+    }
+}
+
+enum Writer {
+    Wav(wav::WavWriter<BufWriter<File>>),
+    Flac(flac::FlacWriter<BufWriter<File>>),
+}
+
+impl Writer {
+    fn new(path: &Path, format: AudioFormat, sample_rate: u32) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match format {
+            AudioFormat::Wav => Self::Wav(wav::WavWriter::new(file, sample_rate)?),
+            AudioFormat::Flac => Self::Flac(flac::FlacWriter::new(file, sample_rate)?),
+        })
+    }
+
+    fn write(&mut self, l: i16, r: i16) -> io::Result<()> {
+        match self {
+            Self::Wav(w) => w.write(l, r),
+            Self::Flac(w) => w.write(l, r),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Wav(w) => w.finish(),
+            Self::Flac(w) => w.finish(),
+        }
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn new_synth(args: &cli::Args, soundfont: &Path) -> oxisynth::Synth {
+    let mut synth = oxisynth::Synth::new(oxisynth::SynthDescriptor {
+        sample_rate: args.sample_rate as f32,
+        gain: args.gain,
+        reverb_active: args.reverb > 0.0,
+        chorus_active: args.chorus > 0.0,
+        ..Default::default()
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("Failed to create synth: {err:?}");
+        std::process::exit(1);
+    });
+
+    synth.set_reverb_params(&oxisynth::ReverbParams {
+        roomsize: 0.5,
+        damp: 0.3,
+        width: 0.8,
+        level: args.reverb,
+    });
+    synth.set_chorus_params(&oxisynth::ChorusParams {
+        nr: 4,
+        level: args.chorus,
+        speed: 0.36,
+        depth: 3.6,
+        mode: Default::default(),
+    });
+
+    let font = File::open(soundfont)
+        .map_err(|err| format!("{err}"))
+        .and_then(|mut file| oxisynth::SoundFont::load(&mut file).map_err(|err| format!("{err:?}")))
+        .unwrap_or_else(|err| {
+            eprintln!("Error loading SoundFont: {err}");
+            std::process::exit(1);
+        });
+    synth.add_font(font, true);
+
+    synth
+}
+
+/// Render the song to a WAV or FLAC file
+pub fn render(args: &cli::Args, format: AudioFormat) {
+    let Some(soundfont) = args.soundfont.as_ref() else {
+        eprintln!("--soundfont is required for audio rendering");
+        std::process::exit(1);
+    };
+
+    let midi = midi_file::MidiFile::new(&args.midi).unwrap_or_else(|err| {
+        eprintln!("Error loading MIDI file: {err}");
+        std::process::exit(1);
+    });
+
+    let mut synth = new_synth(args, soundfont);
+    let mut playback = midi_file::PlaybackState::new(Duration::ZERO, midi.tracks.clone());
+
+    let mut writer = Writer::new(&args.out, format, args.sample_rate).unwrap_or_else(|err| {
+        eprintln!("Error creating output file: {err}");
+        std::process::exit(1);
+    });
+
+    // Song time advanced with every block, scaled by the playback speed
+    let block_time =
+        Duration::from_secs_f64(BLOCK_SIZE as f64 / args.sample_rate as f64 * args.speed as f64);
+    let end = playback.length() + TAIL.mul_f32(args.speed);
+
+    let start = std::time::Instant::now();
+
+    println!("Rendering started:");
+    while playback.time() < end {
+        for event in playback.update(block_time) {
+            let is_selected = args
+                .tracks
+                .as_ref()
+                .is_none_or(|tracks| tracks.contains(&event.track_id));
+
+            if let (true, Some(&message)) = (is_selected, event.message.as_midi()) {
+                synth
+                    .send_event(libmidi_to_oxisynth_event(event.channel, message))
+                    .ok();
+            }
+        }
+
+        for _ in 0..BLOCK_SIZE {
+            let (l, r) = synth.read_next();
+            if let Err(err) = writer.write(to_i16(l), to_i16(r)) {
+                eprintln!("Error writing output file: {err}");
+                std::process::exit(1);
+            }
+        }
+
+        print!(
+            "\r Rendered {}s ({}%) in {}s",
+            playback.time().as_secs(),
+            (playback.percentage() * 100.0).round().min(100.0),
+            start.elapsed().as_secs()
+        );
+    }
+    println!();
+
+    if let Err(err) = writer.finish() {
+        eprintln!("Error writing output file: {err}");
+        std::process::exit(1);
+    }
+}
@@ -2,25 +2,37 @@ use clap::{Command, arg, value_parser};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "video"), allow(dead_code))]
 pub struct Args {
     pub midi: PathBuf,
     pub out: PathBuf,
     pub soundfont: Option<PathBuf>,
     pub width: u32,
     pub height: u32,
+
+    /// Tracks to render, `None` for all of them (audio only)
+    pub tracks: Option<Vec<usize>>,
+    pub gain: f32,
+    /// Reverb level, `0.0` disables reverb (audio only)
+    pub reverb: f32,
+    /// Chorus level, `0.0` disables chorus (audio only)
+    pub chorus: f32,
+    /// Playback speed multiplier (audio only)
+    pub speed: f32,
+    pub sample_rate: u32,
 }
 
 impl Args {
     pub fn get() -> Self {
         let matches = Command::new("Neothesia")
-            .about("MIDI visualization to video encoder")
+            .about("MIDI visualization to video encoder, or audio-only renderer for .wav/.flac outputs")
             .arg(
                 arg!([MIDI_FILE])
                     .required(true)
                     .value_parser(value_parser!(PathBuf)),
             )
             .arg(
-                arg!([MP4_FILE])
+                arg!([OUT_FILE] "Output file: .mp4 for video, .wav or .flac for audio only")
                     .required(true)
                     .value_parser(value_parser!(PathBuf)),
             )
@@ -31,6 +43,37 @@ impl Args {
             )
             .arg(arg!(--width <PIXELS>).required(false))
             .arg(arg!(--height <PIXELS>).required(false))
+            .arg(
+                arg!(--tracks <IDS> "Comma separated list of track ids to render")
+                    .required(false)
+                    .value_delimiter(',')
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--gain <GAIN>)
+                    .required(false)
+                    .value_parser(value_parser!(f32)),
+            )
+            .arg(
+                arg!(--reverb <LEVEL> "Reverb level from 0.0 to 1.0")
+                    .required(false)
+                    .value_parser(value_parser!(f32)),
+            )
+            .arg(
+                arg!(--chorus <LEVEL> "Chorus level from 0.0 to 10.0")
+                    .required(false)
+                    .value_parser(value_parser!(f32)),
+            )
+            .arg(
+                arg!(--speed <MULTIPLIER>)
+                    .required(false)
+                    .value_parser(value_parser!(f32)),
+            )
+            .arg(
+                arg!(--"sample-rate" <HZ>)
+                    .required(false)
+                    .value_parser(value_parser!(u32).range(1..)),
+            )
             .get_matches();
 
         let width = matches
@@ -47,12 +90,37 @@ impl Args {
             std::process::exit(1);
         }
 
+        let tracks = matches
+            .get_many::<usize>("tracks")
+            .map(|tracks| tracks.copied().collect());
+
+        let parse_f32 =
+            |name: &str, default: f32| matches.get_one::<f32>(name).copied().unwrap_or(default);
+
+        let speed = parse_f32("speed", 1.0);
+        if !(speed.is_finite() && speed > 0.0) {
+            eprintln!("speed must be a number greater than zero");
+            std::process::exit(1);
+        }
+
+        let sample_rate = matches
+            .get_one::<u32>("sample-rate")
+            .copied()
+            .unwrap_or(44100);
+
         Self {
             midi: matches.get_one::<PathBuf>("MIDI_FILE").unwrap().clone(),
-            out: matches.get_one::<PathBuf>("MP4_FILE").unwrap().clone(),
+            out: matches.get_one::<PathBuf>("OUT_FILE").unwrap().clone(),
             soundfont: matches.get_one::<PathBuf>("soundfont").cloned(),
             width,
             height,
+
+            tracks,
+            gain: parse_f32("gain", 0.5),
+            reverb: parse_f32("reverb", 0.0),
+            chorus: parse_f32("chorus", 0.0),
+            speed,
+            sample_rate,
         }
     }
 }
@@ -1,13 +1,19 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 mod model;
 
-pub use model::ColorSchemaV1;
 use model::{
     AppearanceConfig, AppearanceConfigV1, DevicesConfig, DevicesConfigV1, History, HistoryV1,
     LayoutConfig, LayoutConfigV1, Model, PlaybackConfig, PlaybackConfigV1, SynthConfig,
-    SynthConfigV1, WaterfallConfig, WaterfallConfigV1,
+    WaterfallConfig, WaterfallConfigV1,
 };
+pub use model::{
+    ChorusConfigV1, ColorSchemaV1, InterpolationV1, PcKeyboardConfigV1, ReverbConfigV1,
+    SongStateV1, SplitZoneV1, SynthConfigV1, TrackPlayerV1, TrackStateV1, VelocityCurveV1,
+};
+
+/// How many songs keep their playback state in the history
+const SONG_HISTORY_LEN: usize = 100;
 
 fn ron_options() -> ron::Options {
     ron::Options::default()
@@ -158,6 +164,25 @@ impl Config {
         self.history.last_opened_song = last_opened_song;
     }
 
+    pub fn song_state(&self, path: &Path) -> Option<&SongStateV1> {
+        self.history.songs.iter().find(|song| song.path == path)
+    }
+
+    /// Get (or create) the state of a song and mark it as the most recent one
+    pub fn song_state_mut(&mut self, path: &Path) -> &mut SongStateV1 {
+        let songs = &mut self.history.songs;
+
+        let state = match songs.iter().position(|song| song.path == path) {
+            Some(id) => songs.remove(id),
+            None => SongStateV1::new(path.to_path_buf()),
+        };
+
+        songs.insert(0, state);
+        songs.truncate(SONG_HISTORY_LEN);
+
+        &mut songs[0]
+    }
+
     pub fn soundfont_path(&self) -> Option<&PathBuf> {
         self.synth.soundfont_path.as_ref()
     }
@@ -166,6 +191,41 @@ impl Config {
         self.synth.soundfont_path = soundfont_path;
     }
 
+    pub fn soundfont_layers(&self) -> &[PathBuf] {
+        &self.synth.soundfont_layers
+    }
+
+    pub fn add_soundfont_layer(&mut self, path: PathBuf) {
+        self.synth.soundfont_layers.retain(|p| *p != path);
+        self.synth.soundfont_layers.push(path);
+    }
+
+    pub fn remove_soundfont_layer(&mut self, id: usize) {
+        if id < self.synth.soundfont_layers.len() {
+            self.synth.soundfont_layers.remove(id);
+        }
+    }
+
+    /// Move the layer one step up the stack, so that it takes priority over the one above it
+    pub fn raise_soundfont_layer(&mut self, id: usize) {
+        if id + 1 < self.synth.soundfont_layers.len() {
+            self.synth.soundfont_layers.swap(id, id + 1);
+        }
+    }
+
+    /// Every SoundFont the synth loads, lowest priority first.
+    ///
+    /// Falls back to the default SoundFont when none was selected.
+    pub fn soundfont_stack(&self) -> Vec<PathBuf> {
+        self.synth
+            .soundfont_path
+            .clone()
+            .or_else(|| crate::utils::resources::default_sf2().filter(|path| path.exists()))
+            .into_iter()
+            .chain(self.synth.soundfont_layers.iter().cloned())
+            .collect()
+    }
+
     pub fn output(&self) -> Option<&str> {
         self.devices.output.as_deref()
     }
@@ -182,6 +242,117 @@ impl Config {
         self.devices.input = v.map(|v| v.to_string());
     }
 
+    pub fn extra_inputs(&self) -> &[String] {
+        &self.devices.extra_inputs
+    }
+
+    pub fn set_extra_input(&mut self, name: &str, enabled: bool) {
+        self.devices.extra_inputs.retain(|input| input != name);
+        if enabled {
+            self.devices.extra_inputs.push(name.to_string());
+        }
+    }
+
+    pub fn input_channel(&self) -> Option<u8> {
+        // Clamped here too, as the settings file can be edited by hand
+        self.devices.input_channel.map(|channel| channel.min(15))
+    }
+
+    pub fn set_input_channel(&mut self, channel: Option<u8>) {
+        self.devices.input_channel = channel.map(|channel| channel.min(15));
+    }
+
+    pub fn input_transpose(&self) -> i8 {
+        self.devices.input_transpose.clamp(-11, 11)
+    }
+
+    pub fn set_input_transpose(&mut self, semitones: i8) {
+        self.devices.input_transpose = semitones.clamp(-11, 11);
+    }
+
+    pub fn input_octave(&self) -> i8 {
+        self.devices.input_octave.clamp(-4, 4)
+    }
+
+    pub fn set_input_octave(&mut self, octaves: i8) {
+        self.devices.input_octave = octaves.clamp(-4, 4);
+    }
+
+    pub fn velocity_curve(&self) -> VelocityCurveV1 {
+        self.devices.velocity_curve
+    }
+
+    pub fn set_velocity_curve(&mut self, curve: VelocityCurveV1) {
+        self.devices.velocity_curve = curve;
+    }
+
+    pub fn split_zones(&self) -> Vec<SplitZoneV1> {
+        self.devices
+            .split_zones
+            .iter()
+            .map(|zone| SplitZoneV1 {
+                channel: zone.channel.min(15),
+                ..*zone
+            })
+            .collect()
+    }
+
+    pub fn set_split_zones(&mut self, zones: Vec<SplitZoneV1>) {
+        self.devices.split_zones = zones;
+    }
+
+    pub fn clock_output(&self) -> bool {
+        self.devices.clock_output
+    }
+
+    pub fn set_clock_output(&mut self, enabled: bool) {
+        self.devices.clock_output = enabled;
+    }
+
+    pub fn clock_input(&self) -> Option<&str> {
+        self.devices.clock_input.as_deref()
+    }
+
+    pub fn set_clock_input(&mut self, input: Option<String>) {
+        self.devices.clock_input = input;
+    }
+
+    pub fn network_output(&self) -> Option<&str> {
+        self.devices.network_output.as_deref()
+    }
+
+    pub fn set_network_output(&mut self, addr: Option<String>) {
+        self.devices.network_output = addr;
+    }
+
+    pub fn network_input_port(&self) -> Option<u16> {
+        self.devices.network_input_port
+    }
+
+    pub fn set_network_input_port(&mut self, port: Option<u16>) {
+        self.devices.network_input_port = port;
+    }
+
+    pub fn pc_keyboard(&self) -> &PcKeyboardConfigV1 {
+        &self.devices.pc_keyboard
+    }
+
+    pub fn set_pc_keyboard_enabled(&mut self, enabled: bool) {
+        self.devices.pc_keyboard.enabled = enabled;
+    }
+
+    pub fn set_pc_keyboard_hints(&mut self, hints: bool) {
+        self.devices.pc_keyboard.hints = hints;
+    }
+
+    pub fn set_pc_keyboard_base_note(&mut self, note: u8) {
+        self.devices.pc_keyboard.base_note = note.min(127);
+    }
+
+    pub fn set_pc_keyboard_velocity(&mut self, velocity: u8) {
+        self.devices.pc_keyboard.velocity = velocity.clamp(1, 127);
+    }
+
     pub fn background_color(&self) -> (u8, u8, u8) {
         self.appearance.background_color
     }
@@ -206,6 +377,89 @@ impl Config {
         self.synth.audio_gain = gain.max(0.0);
     }
 
+    /// Every setting of the built-in synth
+    pub fn synth(&self) -> &SynthConfigV1 {
+        &self.synth
+    }
+
+    pub fn reverb(&self) -> &ReverbConfigV1 {
+        &self.synth.reverb
+    }
+
+    pub fn set_reverb(&mut self, reverb: ReverbConfigV1) {
+        self.synth.reverb = ReverbConfigV1 {
+            room_size: reverb.room_size.clamp(0.0, 1.0),
+            damp: reverb.damp.clamp(0.0, 1.0),
+            width: reverb.width.clamp(0.0, 100.0),
+            level: reverb.level.clamp(0.0, 1.0),
+        };
+    }
+
+    pub fn chorus(&self) -> &ChorusConfigV1 {
+        &self.synth.chorus
+    }
+
+    pub fn set_chorus(&mut self, chorus: ChorusConfigV1) {
+        self.synth.chorus = ChorusConfigV1 {
+            voices: chorus.voices.clamp(1, 99),
+            level: chorus.level.clamp(0.0, 10.0),
+            speed: chorus.speed.clamp(0.29, 5.0),
+            depth: chorus.depth.clamp(0.0, 21.0),
+        };
+    }
+
+    pub fn polyphony(&self) -> u16 {
+        self.synth.polyphony
+    }
+
+    pub fn set_polyphony(&mut self, polyphony: u16) {
+        self.synth.polyphony = polyphony.max(1);
+    }
+
+    pub fn interpolation(&self) -> InterpolationV1 {
+        self.synth.interpolation
+    }
+
+    pub fn set_interpolation(&mut self, interpolation: InterpolationV1) {
+        self.synth.interpolation = interpolation;
+    }
+
+    pub fn audio_host(&self) -> Option<&str> {
+        self.synth.audio_host.as_deref()
+    }
+
+    /// Switching the host goes back to its default device
+    pub fn set_audio_host(&mut self, host: Option<String>) {
+        if self.synth.audio_host != host {
+            self.synth.audio_device = None;
+        }
+        self.synth.audio_host = host;
+    }
+
+    pub fn audio_device(&self) -> Option<&str> {
+        self.synth.audio_device.as_deref()
+    }
+
+    pub fn set_audio_device(&mut self, device: Option<String>) {
+        self.synth.audio_device = device;
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.synth.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.synth.sample_rate = sample_rate;
+    }
+
+    pub fn buffer_size(&self) -> Option<u32> {
+        self.synth.buffer_size
+    }
+
+    pub fn set_buffer_size(&mut self, buffer_size: Option<u32>) {
+        self.synth.buffer_size = buffer_size;
+    }
+
     pub fn animation_offset(&self) -> f32 {
         self.waterfall.animation_offset
     }
@@ -243,6 +497,30 @@ impl Config {
         self.playback.speed_multiplier = speed_multiplier.max(0.0);
     }
 
+    pub fn loop_practice_target(&self) -> u32 {
+        self.playback.loop_practice_target
+    }
+
+    pub fn set_loop_practice_target(&mut self, target: u32) {
+        self.playback.loop_practice_target = target.max(1);
+    }
+
+    pub fn loop_practice_speed_step(&self) -> f32 {
+        self.playback.loop_practice_speed_step
+    }
+
+    pub fn set_loop_practice_speed_step(&mut self, step: f32) {
+        self.playback.loop_practice_speed_step = step.max(0.0);
+    }
+
+    pub fn loop_practice_advance(&self) -> bool {
+        self.playback.loop_practice_advance
+    }
+
+    pub fn set_loop_practice_advance(&mut self, advance: bool) {
+        self.playback.loop_practice_advance = advance;
+    }
+
     pub fn save(&self) {
         let res = ron_options().to_string_pretty(
             &Model::from_config(self.clone()),
@@ -52,6 +52,15 @@ impl Default for WaterfallConfig {
 pub struct PlaybackConfigV1 {
     #[serde(default = "default_speed_multiplier")]
     pub speed_multiplier: f32,
+
+    #[serde(default = "default_loop_practice_target")]
+    pub loop_practice_target: u32,
+
+    #[serde(default)]
+    pub loop_practice_speed_step: f32,
+
+    #[serde(default)]
+    pub loop_practice_advance: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -63,13 +72,90 @@ impl Default for PlaybackConfig {
     fn default() -> Self {
         Self::V1(PlaybackConfigV1 {
             speed_multiplier: default_speed_multiplier(),
+            loop_practice_target: default_loop_practice_target(),
+            loop_practice_speed_step: 0.0,
+            loop_practice_advance: false,
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackPlayerV1 {
+    Mute,
+    Auto,
+    Human,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackStateV1 {
+    pub track_id: usize,
+    pub player: TrackPlayerV1,
+    pub visible: bool,
+
+    /// Name of the output this track is routed to, `None` for the default output
+    #[serde(default)]
+    pub output: Option<String>,
+
+    /// Velocity multiplier in `0.0..=1.0` range
+    #[serde(default = "default_track_volume")]
+    pub volume: f32,
+
+    /// Program that replaces the one from the file
+    #[serde(default)]
+    pub program: Option<u8>,
+
+    #[serde(default)]
+    pub solo: bool,
+
+    /// SoundFont that the track presets are taken from, `None` to search the whole font stack
+    #[serde(default)]
+    pub soundfont: Option<PathBuf>,
+}
+
+/// Playback state remembered for a single song file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SongStateV1 {
+    pub path: PathBuf,
+
+    /// Playback position in seconds
+    #[serde(default)]
+    pub position: f32,
+
+    #[serde(default = "default_speed_multiplier")]
+    pub speed_multiplier: f32,
+
+    /// Looper start and end in seconds, `None` when the looper was off
+    #[serde(default)]
+    pub loop_region: Option<(f32, f32)>,
+
+    /// Bookmarked positions in seconds
+    #[serde(default)]
+    pub bookmarks: Vec<f32>,
+
+    #[serde(default)]
+    pub tracks: Vec<TrackStateV1>,
+}
+
+impl SongStateV1 {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            position: 0.0,
+            speed_multiplier: default_speed_multiplier(),
+            loop_region: None,
+            bookmarks: Vec::new(),
+            tracks: Vec::new(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct HistoryV1 {
     pub last_opened_song: Option<PathBuf>,
+
+    /// Most recently played songs first
+    #[serde(default)]
+    pub songs: Vec<SongStateV1>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -81,15 +167,92 @@ impl Default for History {
     fn default() -> Self {
         Self::V1(HistoryV1 {
             last_opened_song: None,
+            songs: Vec::new(),
         })
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ReverbConfigV1 {
+    pub room_size: f32,
+    pub damp: f32,
+    pub width: f32,
+    /// `0.0` turns the reverb off
+    pub level: f32,
+}
+
+impl Default for ReverbConfigV1 {
+    fn default() -> Self {
+        Self {
+            room_size: 0.5,
+            damp: 0.3,
+            width: 0.8,
+            level: 0.7,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ChorusConfigV1 {
+    pub voices: u32,
+    /// `0.0` turns the chorus off
+    pub level: f32,
+    /// Modulation speed in Hz
+    pub speed: f32,
+    /// Modulation depth in ms
+    pub depth: f32,
+}
+
+impl Default for ChorusConfigV1 {
+    fn default() -> Self {
+        Self {
+            voices: 4,
+            level: 0.55,
+            speed: 0.36,
+            depth: 3.6,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InterpolationV1 {
+    None,
+    Linear,
+    #[default]
+    FourthOrder,
+    SeventhOrder,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SynthConfigV1 {
     pub soundfont_path: Option<PathBuf>,
+    /// SoundFonts stacked on top of `soundfont_path`, later ones take priority
+    #[serde(default)]
+    pub soundfont_layers: Vec<PathBuf>,
     #[serde(default = "default_audio_gain")]
     pub audio_gain: f32,
+
+    #[serde(default)]
+    pub reverb: ReverbConfigV1,
+    #[serde(default)]
+    pub chorus: ChorusConfigV1,
+    /// Max amount of voices playing at once
+    #[serde(default = "default_polyphony")]
+    pub polyphony: u16,
+    #[serde(default)]
+    pub interpolation: InterpolationV1,
+    /// Audio host (ALSA, JACK, WASAPI, ...), `None` uses the system default
+    #[serde(default)]
+    pub audio_host: Option<String>,
+    /// Output device of the audio host, `None` uses the default device
+    #[serde(default)]
+    pub audio_device: Option<String>,
+    /// Output sample rate, `None` uses the default rate of the audio device
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// Frames rendered per audio callback, `None` lets the audio device decide
+    #[serde(default)]
+    pub buffer_size: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -101,7 +264,16 @@ impl Default for SynthConfig {
     fn default() -> Self {
         Self::V1(SynthConfigV1 {
             soundfont_path: None,
+            soundfont_layers: Vec::new(),
             audio_gain: default_audio_gain(),
+            reverb: ReverbConfigV1::default(),
+            chorus: ChorusConfigV1::default(),
+            polyphony: default_polyphony(),
+            interpolation: InterpolationV1::default(),
+            audio_host: None,
+            audio_device: None,
+            sample_rate: None,
+            buffer_size: None,
         })
     }
 }
@@ -125,11 +297,114 @@ impl Default for LayoutConfig {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VelocityCurveV1 {
+    #[default]
+    Linear,
+    /// Louder notes for less force, for keyboards that need to be hit hard
+    Soft,
+    /// Quieter notes for the same force, for keyboards that are too sensitive
+    Hard,
+    /// Every note plays at this velocity
+    Fixed(u8),
+}
+
+/// Keys from `start` to `end` (inclusive) played on `channel`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SplitZoneV1 {
+    pub start: u8,
+    pub end: u8,
+    pub channel: u8,
+}
+
+/// Playing the piano with the computer keyboard.
+///
+/// Keys are physical key codes (winit `KeyCode` names, eg. `KeyA` or `Digit2`), so the rows stay in
+/// place on every keyboard layout. Each row is a run of semitones, starting from a C, where an
+/// empty name leaves the semitone without a key.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PcKeyboardConfigV1 {
+    #[serde(default = "default_pc_keyboard_enabled")]
+    pub enabled: bool,
+    /// Show which computer key plays which piano key
+    #[serde(default)]
+    pub hints: bool,
+    /// Note played by the first key of the upper row, the lower row is an octave below it
+    #[serde(default = "default_pc_keyboard_base_note")]
+    pub base_note: u8,
+    #[serde(default = "default_pc_keyboard_velocity")]
+    pub velocity: u8,
+    #[serde(default = "default_pc_keyboard_upper_row")]
+    pub upper_row: Vec<String>,
+    #[serde(default = "default_pc_keyboard_lower_row")]
+    pub lower_row: Vec<String>,
+    #[serde(default = "default_pc_keyboard_octave_down")]
+    pub octave_down: String,
+    #[serde(default = "default_pc_keyboard_octave_up")]
+    pub octave_up: String,
+    #[serde(default = "default_pc_keyboard_velocity_down")]
+    pub velocity_down: String,
+    #[serde(default = "default_pc_keyboard_velocity_up")]
+    pub velocity_up: String,
+}
+
+impl Default for PcKeyboardConfigV1 {
+    fn default() -> Self {
+        Self {
+            enabled: default_pc_keyboard_enabled(),
+            hints: false,
+            base_note: default_pc_keyboard_base_note(),
+            velocity: default_pc_keyboard_velocity(),
+            upper_row: default_pc_keyboard_upper_row(),
+            lower_row: default_pc_keyboard_lower_row(),
+            octave_down: default_pc_keyboard_octave_down(),
+            octave_up: default_pc_keyboard_octave_up(),
+            velocity_down: default_pc_keyboard_velocity_down(),
+            velocity_up: default_pc_keyboard_velocity_up(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DevicesConfigV1 {
     #[serde(default = "default_output")]
     pub output: Option<String>,
     pub input: Option<String>,
+    /// Inputs listened to next to the main one, their events are merged
+    #[serde(default)]
+    pub extra_inputs: Vec<String>,
+
+    /// Only listen to this input channel, `None` accepts every channel
+    #[serde(default)]
+    pub input_channel: Option<u8>,
+    /// Semitones added to the keys of the input
+    #[serde(default)]
+    pub input_transpose: i8,
+    /// Octaves added to the keys of the input
+    #[serde(default)]
+    pub input_octave: i8,
+    #[serde(default)]
+    pub velocity_curve: VelocityCurveV1,
+    /// Key ranges sent on their own channel, keys outside of every zone keep their channel
+    #[serde(default)]
+    pub split_zones: Vec<SplitZoneV1>,
+
+    /// Send MIDI clock and transport of the playback to the output
+    #[serde(default)]
+    pub clock_output: bool,
+    /// Input whose MIDI clock and transport the playback follows
+    #[serde(default)]
+    pub clock_input: Option<String>,
+
+    /// Address (`ip:port`) that network MIDI is sent to, as OSC over UDP
+    #[serde(default)]
+    pub network_output: Option<String>,
+    /// UDP port that network MIDI is received on, `None` doesn't listen
+    #[serde(default)]
+    pub network_input_port: Option<u16>,
+
+    #[serde(default)]
+    pub pc_keyboard: PcKeyboardConfigV1,
 
     #[serde(default = "default_separate_channels")]
     pub separate_channels: bool,
@@ -145,6 +420,17 @@ impl Default for DevicesConfig {
         Self::V1(DevicesConfigV1 {
             output: default_output(),
             input: None,
+            extra_inputs: Vec::new(),
+            input_channel: None,
+            input_transpose: 0,
+            input_octave: 0,
+            velocity_curve: VelocityCurveV1::default(),
+            split_zones: Vec::new(),
+            clock_output: false,
+            clock_input: None,
+            network_output: None,
+            network_input_port: None,
+            pc_keyboard: PcKeyboardConfigV1::default(),
             separate_channels: default_separate_channels(),
         })
     }
@@ -195,10 +481,79 @@ fn default_piano_range() -> (u8, u8) {
     (21, 108)
 }
 
+fn default_pc_keyboard_enabled() -> bool {
+    true
+}
+
+fn default_pc_keyboard_base_note() -> u8 {
+    60
+}
+
+fn default_pc_keyboard_velocity() -> u8 {
+    100
+}
+
+fn key_codes(codes: &[&str]) -> Vec<String> {
+    codes.iter().map(|code| code.to_string()).collect()
+}
+
+/// White keys on the home row and black keys on the row above it, which leaves the digit and
+/// `-`/`=` shortcuts of the playing scene alone
+fn default_pc_keyboard_upper_row() -> Vec<String> {
+    key_codes(&[
+        "KeyA",
+        "KeyW",
+        "KeyS",
+        "KeyE",
+        "KeyD",
+        "KeyF",
+        "KeyT",
+        "KeyG",
+        "KeyY",
+        "KeyH",
+        "KeyU",
+        "KeyJ",
+        "KeyK",
+        "KeyO",
+        "KeyL",
+        "KeyP",
+        "Semicolon",
+        "Quote",
+    ])
+}
+
+/// White keys only on the bottom row, as the home row is taken by the upper row
+fn default_pc_keyboard_lower_row() -> Vec<String> {
+    key_codes(&[
+        "KeyZ", "", "KeyX", "", "KeyC", "KeyV", "", "KeyB", "", "KeyN", "", "KeyM", "Comma", "",
+        "Period", "", "Slash",
+    ])
+}
+
+fn default_pc_keyboard_octave_down() -> String {
+    "F5".to_string()
+}
+
+fn default_pc_keyboard_octave_up() -> String {
+    "F6".to_string()
+}
+
+fn default_pc_keyboard_velocity_down() -> String {
+    "F7".to_string()
+}
+
+fn default_pc_keyboard_velocity_up() -> String {
+    "F8".to_string()
+}
+
 fn default_speed_multiplier() -> f32 {
     1.0
 }
 
+fn default_loop_practice_target() -> u32 {
+    3
+}
+
 fn default_animation_speed() -> f32 {
     400.0
 }
@@ -215,6 +570,10 @@ fn default_audio_gain() -> f32 {
     0.2
 }
 
+fn default_polyphony() -> u16 {
+    256
+}
+
 fn default_vertical_guidelines() -> bool {
     true
 }
@@ -263,3 +622,7 @@ fn default_color_schema() -> Vec<ColorSchemaV1> {
 fn default_output() -> Option<String> {
     Some("Buildin Synth".into())
 }
+
+fn default_track_volume() -> f32 {
+    1.0
+}
@@ -19,6 +19,9 @@ pub struct KeyboardRenderer {
 
     cache: Vec<QuadInstance>,
     text_cache: Vec<super::text::TextArea>,
+
+    /// Labels drawn on keys, by key id
+    key_hints: Vec<(usize, String)>,
 }
 
 impl KeyboardRenderer {
@@ -39,6 +42,7 @@ impl KeyboardRenderer {
             layout,
             cache,
             text_cache: Vec::new(),
+            key_hints: Vec::new(),
         }
     }
 
@@ -86,6 +90,17 @@ impl KeyboardRenderer {
         self.invalidate_cache();
     }
 
+    /// Draw a label on each of these notes, notes outside of the range are skipped
+    pub fn set_key_hints(&mut self, hints: impl IntoIterator<Item = (u8, String)>) {
+        let range = &self.layout.range;
+        self.key_hints = hints
+            .into_iter()
+            .filter(|(note, _)| range.contains(*note))
+            .map(|(note, label)| ((note - range.start()) as usize, label))
+            .collect();
+        self.text_cache.clear();
+    }
+
     pub fn invalidate_cache(&mut self) {
         self.cache.clear();
         self.text_cache.clear();
@@ -148,30 +163,46 @@ impl KeyboardRenderer {
 
             let oct_number = (key.id() + range_start) / 12;
 
-            let mut buffer = glyphon::Buffer::new(font_system, glyphon::Metrics::new(size, size));
-            buffer.set_size(Some(w), Some(h));
-            buffer.set_wrap(glyphon::Wrap::None);
-            buffer.set_text(
+            self.text_cache.push(key_label(
+                font_system,
                 &format!("C{}", oct_number as i8 - 1),
-                &glyphon::Attrs::new().family(glyphon::Family::SansSerif),
-                glyphon::Shaping::Basic,
-                Some(glyphon::cosmic_text::Align::Center),
-            );
-            buffer.shape_until_scroll(font_system, false);
-
-            self.text_cache.push(super::text::TextArea {
-                buffer,
-                left: x,
-                top: y + h - size * 1.2,
-                scale: 1.0,
-                bounds: glyphon::TextBounds {
-                    left: x.round() as i32,
-                    top: y.round() as i32,
-                    right: x.round() as i32 + w.round() as i32,
-                    bottom: y.round() as i32 + h.round() as i32,
-                },
-                default_color: glyphon::Color::rgba(0, 0, 0, 150),
-            });
+                (x, y),
+                (w, h),
+                size,
+                y + h - size * 1.2,
+                glyphon::Color::rgba(0, 0, 0, 150),
+            ));
+        }
+
+        for (id, label) in self.key_hints.iter() {
+            let Some(key) = self.layout.keys.get(*id) else {
+                continue;
+            };
+
+            let x = self.pos.x + key.x();
+            let y = self.pos.y;
+
+            let w = key.width();
+            let h = key.height();
+
+            let (size, top, color) = if key.kind().is_sharp() {
+                let size = w * 0.8;
+                (size, y + h - size * 1.4, glyphon::Color::rgb(220, 220, 220))
+            } else {
+                // Above the octave labels, below the black keys
+                let size = w * 0.5;
+                (size, y + h - size * 3.6, glyphon::Color::rgb(60, 60, 60))
+            };
+
+            self.text_cache.push(key_label(
+                font_system,
+                label,
+                (x, y),
+                (w, h),
+                size,
+                top,
+                color,
+            ));
         }
     }
 
@@ -196,3 +227,38 @@ impl KeyboardRenderer {
         }
     }
 }
+
+fn key_label(
+    font_system: &mut glyphon::FontSystem,
+    text: &str,
+    (x, y): (f32, f32),
+    (w, h): (f32, f32),
+    size: f32,
+    top: f32,
+    color: glyphon::Color,
+) -> super::text::TextArea {
+    let mut buffer = glyphon::Buffer::new(font_system, glyphon::Metrics::new(size, size));
+    buffer.set_size(Some(w), Some(h));
+    buffer.set_wrap(glyphon::Wrap::None);
+    buffer.set_text(
+        text,
+        &glyphon::Attrs::new().family(glyphon::Family::SansSerif),
+        glyphon::Shaping::Basic,
+        Some(glyphon::cosmic_text::Align::Center),
+    );
+    buffer.shape_until_scroll(font_system, false);
+
+    super::text::TextArea {
+        buffer,
+        left: x,
+        top,
+        scale: 1.0,
+        bounds: glyphon::TextBounds {
+            left: x.round() as i32,
+            top: y.round() as i32,
+            right: x.round() as i32 + w.round() as i32,
+            bottom: y.round() as i32 + h.round() as i32,
+        },
+        default_color: color,
+    }
+}
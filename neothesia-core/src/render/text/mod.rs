@@ -174,14 +174,18 @@ impl TextRenderer {
     }
 
     pub fn queue_fps(&mut self, fps: f64, y: f32) {
+        self.queue_debug_text(&format!("FPS: {}", fps.round() as u32), y);
+    }
+
+    /// Multi-line debug info in the top left corner
+    pub fn queue_debug_text(&mut self, text: &str, y: f32) {
         let font_system = crate::font_system::font_system();
         let font_system = &mut font_system.borrow_mut();
 
-        let text = format!("FPS: {}", fps.round() as u32);
         let mut buffer = glyphon::Buffer::new(font_system, glyphon::Metrics::new(15.0, 15.0));
         buffer.set_size(Some(f32::MAX), Some(f32::MAX));
         buffer.set_text(
-            &text,
+            text,
             &glyphon::Attrs::new().family(glyphon::Family::SansSerif),
             glyphon::Shaping::Basic,
             None,
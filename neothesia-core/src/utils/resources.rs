@@ -60,6 +60,15 @@ pub fn settings_ron() -> Option<PathBuf> {
     return bundled_resource_path("settings", "ron").map(PathBuf::from);
 }
 
+/// Folder that audio recordings are saved to
+pub fn recordings_dir() -> Option<PathBuf> {
+    #[cfg(target_family = "unix")]
+    return home().map(|h| h.join("Music").join("Neothesia"));
+
+    #[cfg(target_os = "windows")]
+    return Some(PathBuf::from("./recordings"));
+}
+
 #[cfg(target_os = "macos")]
 fn bundled_resource_path(name: &str, extension: &str) -> Option<String> {
     use objc2_foundation::{NSBundle, NSString};
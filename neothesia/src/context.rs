@@ -54,6 +54,11 @@ impl Context {
 
         let config = Config::new();
 
+        let mut output_manager = OutputManager::default();
+        output_manager.set_network_output(config.network_output());
+        let mut input_manager = InputManager::new(proxy.clone());
+        input_manager.set_network_input_port(config.network_input_port());
+
         let text_renderer_factory = TextRendererFactory::new(&gpu);
         let quad_renderer_factory = QuadRendererFactory::new(&gpu, &transform_uniform);
 
@@ -66,8 +71,8 @@ impl Context {
             text_renderer_factory,
             quad_renderer_factory,
 
-            output_manager: Default::default(),
-            input_manager: InputManager::new(proxy.clone()),
+            output_manager,
+            input_manager,
             config,
             proxy,
             frame_timestamp: std::time::Instant::now(),
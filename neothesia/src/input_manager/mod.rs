@@ -1,56 +1,290 @@
-use midi_file::midly::{self, MidiMessage, live::LiveEvent};
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use midi_file::midly::{
+    self, MidiMessage,
+    live::{LiveEvent, SystemCommon, SystemRealtime},
+};
 use winit::event_loop::EventLoopProxy;
 
 use crate::NeothesiaEvent;
 
+mod parser;
+use parser::{DeviceClock, LiveParser};
+
+mod processing;
+pub use processing::InputProcessing;
+use processing::InputProcessor;
+
+/// Device that a live input event came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSource {
+    /// MIDI input port, by name
+    Midi(Arc<str>),
+    /// PC keyboard or mouse on the on-screen piano
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct MidiInputEvent {
+    /// The device that sent this message.
+    pub source: InputSource,
+    /// The MIDI channel that this message is associated with.
+    pub channel: u8,
+    /// The MIDI message type and associated data.
+    pub message: MidiMessage,
+    /// When the device received the message, rather than when it got handled.
+    pub timestamp: Instant,
+}
+
+impl MidiInputEvent {
+    /// Event played on the PC keyboard or on-screen piano
+    pub fn local(channel: u8, message: MidiMessage) -> Self {
+        Self {
+            source: InputSource::Local,
+            channel,
+            message,
+            timestamp: Instant::now(),
+        }
+    }
+}
+
+/// MIDI clock and transport messages, used to follow another device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportMessage {
+    /// 24 of them per quarter note
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    /// Song position pointer, in sixteenth notes from the start of the song
+    SongPosition(u16),
+}
+
+impl TransportMessage {
+    fn from_live(event: &LiveEvent) -> Option<Self> {
+        match event {
+            LiveEvent::Realtime(SystemRealtime::TimingClock) => Some(Self::Clock),
+            LiveEvent::Realtime(SystemRealtime::Start) => Some(Self::Start),
+            LiveEvent::Realtime(SystemRealtime::Continue) => Some(Self::Continue),
+            LiveEvent::Realtime(SystemRealtime::Stop) => Some(Self::Stop),
+            LiveEvent::Common(SystemCommon::SongPosition(pos)) => {
+                Some(Self::SongPosition(pos.as_int()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// System message of an input (SysEx, MTC, clock...), only sent out for the MIDI monitor
+#[derive(Debug, Clone)]
+pub struct SystemMidiEvent {
+    pub source: InputSource,
+    /// The whole message, status byte included
+    pub bytes: Vec<u8>,
+    /// When the device received the message
+    pub timestamp: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransportEvent {
+    pub message: TransportMessage,
+    /// When the device received the message
+    pub timestamp: Instant,
+}
+
 pub struct InputManager {
     input: midi_io::MidiInputManager,
     tx: EventLoopProxy<NeothesiaEvent>,
-    current_connection: Option<midi_io::MidiInputConnection>,
+    /// Every open input, their events are merged into one stream
+    connections: Vec<(midi_io::MidiInputPort, midi_io::MidiInputConnection)>,
+    /// Shared with the input threads, applied to every MIDI input before it is sent out
+    processing: Arc<RwLock<InputProcessing>>,
+    /// Input that clock and transport messages are taken from, the ones of other inputs are dropped
+    clock_source: Arc<RwLock<Option<Arc<str>>>>,
+    /// Shared with the input threads, send out the system messages of every input
+    monitor_system: Arc<AtomicBool>,
+    /// Address that network MIDI is received on
+    network_input: Option<SocketAddr>,
+    _port_watcher: Option<midi_io::PortWatcher>,
 }
 
 impl InputManager {
     pub fn new(tx: EventLoopProxy<NeothesiaEvent>) -> Self {
         let input = midi_io::MidiInputManager::new().unwrap();
+
+        let port_watcher = midi_io::PortWatcher::new(Duration::from_millis(500), {
+            let tx = tx.clone();
+            move |event| {
+                tx.send_event(NeothesiaEvent::MidiPort(event)).ok();
+            }
+        })
+        .inspect_err(|err| log::error!("{err}"))
+        .ok();
+
         Self {
             input,
             tx,
-            current_connection: None,
+            connections: Vec::new(),
+            processing: Arc::default(),
+            clock_source: Arc::default(),
+            monitor_system: Arc::default(),
+            network_input: None,
+            _port_watcher: port_watcher,
         }
     }
 
+    /// Ports of the system, followed by the network input if there is one
     pub fn inputs(&self) -> Vec<midi_io::MidiInputPort> {
-        self.input.inputs()
+        let mut inputs = self.input.inputs();
+        inputs.extend(self.network_input.map(midi_io::MidiInputPort::network));
+        inputs
+    }
+
+    /// Offer network MIDI received on a UDP port, of any network interface, as one more input
+    pub fn set_network_input_port(&mut self, port: Option<u16>) {
+        self.network_input = port.map(|port| SocketAddr::from(([0, 0, 0, 0], port)));
+    }
+
+    pub fn set_processing(&self, processing: InputProcessing) {
+        if let Ok(mut current) = self.processing.write() {
+            *current = processing;
+        }
+    }
+
+    pub fn set_clock_source(&self, name: Option<&str>) {
+        if let Ok(mut current) = self.clock_source.write() {
+            *current = name.map(Arc::from);
+        }
+    }
+
+    /// Send out the system messages of every input, which are dropped otherwise
+    pub fn set_monitor_system_messages(&self, enabled: bool) {
+        self.monitor_system.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Listen to a set of inputs, closing the ones that are not in it
+    pub fn connect_inputs(&mut self, ports: Vec<midi_io::MidiInputPort>) {
+        // Keep the connections that stay, as Windows does not like it when we hold 2 connections to one port
+        self.connections.retain(|(port, _)| ports.contains(port));
+
+        for port in ports {
+            if self.connections.iter().any(|(open, _)| *open == port) {
+                continue;
+            }
+
+            if let Some(conn) = self.open(port.clone()) {
+                self.connections.push((port, conn));
+            }
+        }
+    }
+
+    pub fn is_connected(&self, port: &midi_io::MidiInputPort) -> bool {
+        self.connections.iter().any(|(open, _)| open == port)
+    }
+
+    /// Open an input that was plugged back in, returns `true` if it wasn't open yet
+    pub fn reconnect(&mut self, port: &midi_io::MidiInputPort) -> bool {
+        if self.connections.iter().any(|(open, _)| open == port) {
+            return false;
+        }
+
+        let Some(conn) = self.open(port.clone()) else {
+            return false;
+        };
+        self.connections.push((port.clone(), conn));
+        true
+    }
+
+    /// Close the connection of an input that was unplugged, returns `true` if it was open
+    pub fn disconnect(&mut self, port: &midi_io::MidiInputPort) -> bool {
+        let len = self.connections.len();
+        self.connections.retain(|(open, _)| open != port);
+        self.connections.len() != len
     }
 
-    pub fn connect_input(&mut self, port: midi_io::MidiInputPort) {
+    fn open(&self, port: midi_io::MidiInputPort) -> Option<midi_io::MidiInputConnection> {
         let tx = self.tx.clone();
+        let source = InputSource::Midi(port.to_string().into());
 
-        // Close the connection first, as Windows does not like it when we hold 2 connections
-        self.current_connection = None;
+        let mut parser = LiveParser::default();
+        let mut clock = DeviceClock::default();
+        let mut processor = InputProcessor::default();
+        let processing = self.processing.clone();
+        let clock_source = self.clock_source.clone();
+        let monitor_system = self.monitor_system.clone();
 
-        self.current_connection = midi_io::MidiInputManager::connect_input(port, move |message| {
-            let event = LiveEvent::parse(message).unwrap();
+        midi_io::MidiInputManager::connect_input(port, move |stamp, bytes| {
+            let timestamp = clock.instant(stamp, Instant::now());
 
-            if let LiveEvent::Midi { channel, message } = event {
-                match message {
-                    // Some keyboards send NoteOn event with vel 0 instead of NoteOff
-                    midly::MidiMessage::NoteOn { key, vel } if vel == 0 => {
-                        tx.send_event(NeothesiaEvent::MidiInput {
-                            channel: channel.as_int(),
-                            message: MidiMessage::NoteOff { key, vel },
-                        })
+            parser.feed(bytes, |event| {
+                if !matches!(event, LiveEvent::Midi { .. })
+                    && monitor_system.load(Ordering::Relaxed)
+                {
+                    let mut bytes = Vec::new();
+                    if event.write(&mut bytes).is_ok() {
+                        tx.send_event(NeothesiaEvent::MidiSystem(SystemMidiEvent {
+                            source: source.clone(),
+                            bytes,
+                            timestamp,
+                        }))
                         .ok();
                     }
-                    message => {
-                        tx.send_event(NeothesiaEvent::MidiInput {
-                            channel: channel.as_int(),
+                }
+
+                if let Some(message) = TransportMessage::from_live(&event) {
+                    let InputSource::Midi(name) = &source else {
+                        return;
+                    };
+                    if clock_source
+                        .read()
+                        .is_ok_and(|clock| clock.as_ref() == Some(name))
+                    {
+                        tx.send_event(NeothesiaEvent::MidiTransport(TransportEvent {
                             message,
-                        })
+                            timestamp,
+                        }))
                         .ok();
                     }
+                    return;
                 }
-            }
-        });
+
+                let LiveEvent::Midi { channel, message } = event else {
+                    return;
+                };
+
+                let message = match message {
+                    // Some keyboards send NoteOn event with vel 0 instead of NoteOff
+                    midly::MidiMessage::NoteOn { key, vel } if vel == 0 => {
+                        MidiMessage::NoteOff { key, vel }
+                    }
+                    message => message,
+                };
+
+                let Ok(processing) = processing.read() else {
+                    return;
+                };
+                processor.process(
+                    &processing,
+                    channel.as_int(),
+                    message,
+                    |channel, message| {
+                        tx.send_event(NeothesiaEvent::MidiInput(MidiInputEvent {
+                            source: source.clone(),
+                            channel,
+                            message,
+                            timestamp,
+                        }))
+                        .ok();
+                    },
+                );
+            });
+        })
     }
 }
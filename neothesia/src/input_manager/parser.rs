@@ -0,0 +1,243 @@
+use std::time::{Duration, Instant};
+
+use midi_file::midly::live::LiveEvent;
+
+/// Longest SysEx message that is kept, anything longer is dropped
+const MAX_SYSEX_LEN: usize = 64 * 1024;
+
+/// Frames a live MIDI byte stream into complete messages.
+///
+/// Devices don't always deliver one complete message per packet, this handles running status,
+/// realtime bytes in the middle of other messages and SysEx spread over several packets.
+/// Malformed data is skipped, up to the next status byte.
+#[derive(Default)]
+pub struct LiveParser {
+    /// Status of the last channel message, reused when a message starts with a data byte
+    running_status: Option<u8>,
+    /// Message being read, starting with its status byte
+    buf: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl LiveParser {
+    pub fn feed(&mut self, bytes: &[u8], mut f: impl FnMut(LiveEvent<'_>)) {
+        for &byte in bytes {
+            match byte {
+                // Realtime messages can show up anywhere, even inside of other messages
+                0xF8..=0xFF => {
+                    if let Ok(event) = LiveEvent::parse(&[byte]) {
+                        f(event);
+                    }
+                }
+                0xF0 => {
+                    self.buf.clear();
+                    self.buf.push(byte);
+                    self.in_sysex = true;
+                    self.running_status = None;
+                }
+                0xF7 => {
+                    if self.in_sysex {
+                        self.buf.push(byte);
+                        emit(&self.buf, &mut f);
+                    }
+                    self.buf.clear();
+                    self.in_sysex = false;
+                }
+                0x80..=0xF6 => {
+                    // Any other status ends an unterminated SysEx, which gets dropped
+                    self.in_sysex = false;
+                    self.buf.clear();
+                    self.buf.push(byte);
+                    // System common messages cancel running status
+                    self.running_status = (byte < 0xF0).then_some(byte);
+                    self.complete(&mut f);
+                }
+                data => {
+                    if self.in_sysex {
+                        if self.buf.len() < MAX_SYSEX_LEN {
+                            self.buf.push(data);
+                        } else {
+                            self.buf.clear();
+                            self.in_sysex = false;
+                        }
+                        continue;
+                    }
+
+                    if self.buf.is_empty() {
+                        // Stray data bytes without any status to go with
+                        let Some(status) = self.running_status else {
+                            continue;
+                        };
+                        self.buf.push(status);
+                    }
+
+                    self.buf.push(data);
+                    self.complete(&mut f);
+                }
+            }
+        }
+    }
+
+    fn complete(&mut self, f: &mut impl FnMut(LiveEvent<'_>)) {
+        if self.buf.len() > data_len(self.buf[0]) {
+            emit(&self.buf, f);
+            self.buf.clear();
+        }
+    }
+}
+
+fn emit(message: &[u8], f: &mut impl FnMut(LiveEvent<'_>)) {
+    match LiveEvent::parse(message) {
+        Ok(event) => f(event),
+        Err(err) => log::debug!("Dropped MIDI message {message:02X?}: {err}"),
+    }
+}
+
+/// Number of data bytes that follow a status byte
+fn data_len(status: u8) -> usize {
+    match status {
+        0xC0..=0xDF => 1,
+        0x80..=0xEF => 2,
+        // MTC quarter frame, song select
+        0xF1 | 0xF3 => 1,
+        // Song position
+        0xF2 => 2,
+        _ => 0,
+    }
+}
+
+/// Maps device timestamps to `Instant`s.
+///
+/// Devices count microseconds from an arbitrary point, so the first message is taken as "now".
+/// Later messages keep their spacing, but never end up in the future,
+/// or further than [`DeviceClock::MAX_LAG`] in the past.
+#[derive(Default)]
+pub struct DeviceClock {
+    anchor: Option<(Instant, u64)>,
+}
+
+impl DeviceClock {
+    /// How far behind a mapped instant may fall before the clock is re-anchored
+    const MAX_LAG: Duration = Duration::from_millis(20);
+
+    pub fn instant(&mut self, stamp: u64, now: Instant) -> Instant {
+        if let Some((anchor, anchor_stamp)) = self.anchor
+            && stamp >= anchor_stamp
+        {
+            let instant = anchor + Duration::from_micros(stamp - anchor_stamp);
+            if instant <= now && now - instant <= Self::MAX_LAG {
+                return instant;
+            }
+        }
+
+        // First message, the device clock was reset, or it drifted away from ours
+        self.anchor = Some((now, stamp));
+        now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midi_file::midly::{
+        MidiMessage,
+        live::{SystemCommon, SystemRealtime},
+    };
+
+    use super::*;
+
+    fn parse(parser: &mut LiveParser, bytes: &[u8]) -> Vec<String> {
+        let mut events = Vec::new();
+        parser.feed(bytes, |event| events.push(format!("{event:?}")));
+        events
+    }
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> String {
+        format!(
+            "{:?}",
+            LiveEvent::Midi {
+                channel: channel.into(),
+                message: MidiMessage::NoteOn {
+                    key: key.into(),
+                    vel: vel.into(),
+                },
+            }
+        )
+    }
+
+    #[test]
+    fn running_status_and_split_packets() {
+        let mut parser = LiveParser::default();
+
+        assert_eq!(
+            parse(&mut parser, &[0x91, 60, 100, 62, 0, 64]),
+            vec![note_on(1, 60, 100), note_on(1, 62, 0)]
+        );
+        assert_eq!(parse(&mut parser, &[90]), vec![note_on(1, 64, 90)]);
+    }
+
+    #[test]
+    fn realtime_inside_of_messages() {
+        let mut parser = LiveParser::default();
+        let clock = format!("{:?}", LiveEvent::Realtime(SystemRealtime::TimingClock));
+
+        assert_eq!(
+            parse(&mut parser, &[0x90, 60, 0xF8, 100]),
+            vec![clock.clone(), note_on(0, 60, 100)]
+        );
+
+        let sysex = format!(
+            "{:?}",
+            LiveEvent::Common(SystemCommon::SysEx(&[1.into(), 2.into()]))
+        );
+        assert_eq!(
+            parse(&mut parser, &[0xF0, 1, 0xF8, 2, 0xF7]),
+            vec![clock, sysex]
+        );
+    }
+
+    #[test]
+    fn malformed_data_is_skipped() {
+        let mut parser = LiveParser::default();
+
+        // Data without status, and a message cut short by the next one
+        assert_eq!(
+            parse(&mut parser, &[60, 100, 0x90, 60, 0x90, 61, 100]),
+            vec![note_on(0, 61, 100)]
+        );
+        // Stray end of SysEx, then an MTC quarter frame that cancels running status
+        assert_eq!(parse(&mut parser, &[0xF7, 0xF1, 0x23, 62, 100]).len(), 1);
+    }
+
+    #[test]
+    fn device_clock() {
+        let now = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        assert_eq!(clock.instant(1_000, now), now);
+        let later = now + Duration::from_millis(10);
+        assert_eq!(clock.instant(6_000, later), now + Duration::from_millis(5));
+        // Never in the future
+        assert_eq!(clock.instant(100_000, later), later);
+    }
+
+    #[test]
+    fn device_clock_running_slow() {
+        let now = Instant::now();
+        let mut clock = DeviceClock::default();
+
+        assert_eq!(clock.instant(0, now), now);
+        // 15ms behind is still within the allowed lag
+        let later = now + Duration::from_millis(100);
+        assert_eq!(
+            clock.instant(85_000, later),
+            now + Duration::from_millis(85)
+        );
+        // 30ms behind is not, so the clock starts over
+        let later = now + Duration::from_millis(200);
+        assert_eq!(clock.instant(170_000, later), later);
+        assert_eq!(
+            clock.instant(175_000, later + Duration::from_millis(5)),
+            later + Duration::from_millis(5)
+        );
+    }
+}
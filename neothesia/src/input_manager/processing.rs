@@ -0,0 +1,244 @@
+use midi_file::midly::MidiMessage;
+use neothesia_core::config::{Config, SplitZoneV1, VelocityCurveV1};
+
+/// Settings of the input processing stage, applied to every MIDI input
+#[derive(Debug, Clone, Default)]
+pub struct InputProcessing {
+    pub channel: Option<u8>,
+    /// In semitones, octave shift included
+    pub transpose: i8,
+    pub velocity_curve: VelocityCurveV1,
+    pub split_zones: Vec<SplitZoneV1>,
+}
+
+impl InputProcessing {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            channel: config.input_channel(),
+            transpose: config.input_transpose() + config.input_octave() * 12,
+            velocity_curve: config.velocity_curve(),
+            split_zones: config.split_zones(),
+        }
+    }
+
+    fn velocity(&self, vel: u8) -> u8 {
+        let curved = |exp: f32| (127.0 * (vel as f32 / 127.0).powf(exp)).round() as u8;
+        let vel = match self.velocity_curve {
+            VelocityCurveV1::Linear => vel,
+            VelocityCurveV1::Soft => curved(0.6),
+            VelocityCurveV1::Hard => curved(1.6),
+            VelocityCurveV1::Fixed(vel) => vel,
+        };
+        // Velocity of 0 would turn the note on into a note off
+        vel.clamp(1, 127)
+    }
+
+    fn zone_channel(&self, key: u8) -> Option<u8> {
+        self.split_zones
+            .iter()
+            .find(|zone| (zone.start..=zone.end).contains(&key))
+            .map(|zone| zone.channel)
+    }
+}
+
+/// Channel and key that each held key of each input channel was sent as
+type HeldNotes = [[Option<(u8, u8)>; 128]; 16];
+
+/// Applies `InputProcessing` to the events of one input.
+///
+/// Held notes remember where they were sent, so that changing the settings mid-note can't leave
+/// a note hanging.
+pub struct InputProcessor {
+    held: Box<HeldNotes>,
+}
+
+impl Default for InputProcessor {
+    fn default() -> Self {
+        Self {
+            held: Box::new([[None; 128]; 16]),
+        }
+    }
+}
+
+impl InputProcessor {
+    pub fn process(
+        &mut self,
+        settings: &InputProcessing,
+        channel: u8,
+        message: MidiMessage,
+        mut f: impl FnMut(u8, MidiMessage),
+    ) {
+        if settings.channel.is_some_and(|filter| filter != channel) {
+            return;
+        }
+
+        let held = &mut self.held[channel as usize & 15];
+
+        match message {
+            MidiMessage::NoteOn { key, vel } => {
+                let Some((channel, new_key)) = map_key(settings, channel, key.as_int()) else {
+                    return;
+                };
+                held[key.as_int() as usize] = Some((channel, new_key));
+
+                let vel = settings.velocity(vel.as_int());
+                f(
+                    channel,
+                    MidiMessage::NoteOn {
+                        key: new_key.into(),
+                        vel: vel.into(),
+                    },
+                );
+            }
+            MidiMessage::NoteOff { key, vel } => {
+                let Some((channel, key)) = held[key.as_int() as usize]
+                    .take()
+                    .or_else(|| map_key(settings, channel, key.as_int()))
+                else {
+                    return;
+                };
+                f(
+                    channel,
+                    MidiMessage::NoteOff {
+                        key: key.into(),
+                        vel,
+                    },
+                );
+            }
+            MidiMessage::Aftertouch { key, vel } => {
+                let Some((channel, key)) = map_key(settings, channel, key.as_int()) else {
+                    return;
+                };
+                f(
+                    channel,
+                    MidiMessage::Aftertouch {
+                        key: key.into(),
+                        vel,
+                    },
+                );
+            }
+            // Pedals and pitch bend apply to every zone
+            message => {
+                let mut channels: Vec<u8> = settings
+                    .split_zones
+                    .iter()
+                    .map(|zone| zone.channel)
+                    .chain(std::iter::once(channel))
+                    .collect();
+                channels.sort_unstable();
+                channels.dedup();
+
+                for channel in channels {
+                    f(channel, message);
+                }
+            }
+        }
+    }
+}
+
+/// Channel and key that a played key ends up on, `None` if transposed out of the MIDI range
+fn map_key(settings: &InputProcessing, channel: u8, key: u8) -> Option<(u8, u8)> {
+    let channel = settings.zone_channel(key).unwrap_or(channel);
+    let key = key.checked_add_signed(settings.transpose)?;
+    (key <= 127).then_some((channel, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(
+        processor: &mut InputProcessor,
+        settings: &InputProcessing,
+        channel: u8,
+        message: MidiMessage,
+    ) -> Vec<(u8, MidiMessage)> {
+        let mut out = Vec::new();
+        processor.process(settings, channel, message, |ch, msg| out.push((ch, msg)));
+        out
+    }
+
+    fn note_on(key: u8, vel: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        }
+    }
+
+    fn note_off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        }
+    }
+
+    #[test]
+    fn channel_filter_and_transpose() {
+        let mut processor = InputProcessor::default();
+        let mut settings = InputProcessing {
+            channel: Some(1),
+            transpose: 12,
+            ..Default::default()
+        };
+
+        assert!(process(&mut processor, &settings, 0, note_on(60, 100)).is_empty());
+        assert_eq!(
+            process(&mut processor, &settings, 1, note_on(60, 100)),
+            vec![(1, note_on(72, 100))]
+        );
+        assert!(process(&mut processor, &settings, 1, note_on(120, 100)).is_empty());
+
+        // The note off goes where the note on went, even after the settings changed
+        settings.transpose = 0;
+        assert_eq!(
+            process(&mut processor, &settings, 1, note_off(60)),
+            vec![(1, note_off(72))]
+        );
+    }
+
+    #[test]
+    fn velocity_curves() {
+        let settings = |velocity_curve| InputProcessing {
+            velocity_curve,
+            ..Default::default()
+        };
+
+        assert_eq!(settings(VelocityCurveV1::Linear).velocity(64), 64);
+        assert!(settings(VelocityCurveV1::Soft).velocity(64) > 64);
+        assert!(settings(VelocityCurveV1::Hard).velocity(64) < 64);
+        assert_eq!(settings(VelocityCurveV1::Hard).velocity(1), 1);
+        assert_eq!(settings(VelocityCurveV1::Fixed(90)).velocity(10), 90);
+        assert_eq!(settings(VelocityCurveV1::Soft).velocity(127), 127);
+    }
+
+    #[test]
+    fn split_zones() {
+        let mut processor = InputProcessor::default();
+        let settings = InputProcessing {
+            split_zones: vec![SplitZoneV1 {
+                start: 0,
+                end: 59,
+                channel: 1,
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            process(&mut processor, &settings, 0, note_on(48, 100)),
+            vec![(1, note_on(48, 100))]
+        );
+        assert_eq!(
+            process(&mut processor, &settings, 0, note_on(60, 100)),
+            vec![(0, note_on(60, 100))]
+        );
+
+        let sustain = MidiMessage::Controller {
+            controller: 64.into(),
+            value: 127.into(),
+        };
+        assert_eq!(
+            process(&mut processor, &settings, 0, sustain),
+            vec![(0, sustain), (1, sustain)]
+        );
+    }
+}
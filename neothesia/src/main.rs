@@ -15,7 +15,6 @@ use scene::{Scene, menu_scene, playing_scene};
 use song::Song;
 use utils::window::WindowState;
 
-use midi_file::midly::MidiMessage;
 use neothesia_core::{config, render};
 use wgpu_jumpstart::{Gpu, Surface, TransformUniform};
 use winit::{
@@ -34,15 +33,23 @@ pub enum NeothesiaEvent {
     FreePlay(Option<song::Song>),
     /// Go to main menu scene
     MainMenu(Option<song::Song>),
-    MidiInput {
-        /// The MIDI channel that this message is associated with.
-        channel: u8,
-        /// The MIDI message type and associated data.
-        message: MidiMessage,
-    },
+    /// A MIDI port was plugged in or out
+    MidiPort(midi_io::PortEvent),
+    MidiInput(input_manager::MidiInputEvent),
+    /// Clock or transport message of the input that playback follows
+    MidiTransport(input_manager::TransportEvent),
+    /// System message of any input, while the MIDI monitor listens for them
+    MidiSystem(input_manager::SystemMidiEvent),
     Exit,
 }
 
+/// A MIDI device in use that was unplugged or came back
+#[derive(Debug, Clone)]
+pub enum MidiDeviceEvent {
+    Disconnected(String),
+    Reconnected(String),
+}
+
 struct Neothesia {
     context: Context,
     game_scene: Box<dyn Scene>,
@@ -144,12 +151,26 @@ impl Neothesia {
                 self.game_scene = Box::new(to);
             }
             NeothesiaEvent::MainMenu(song) => {
+                if let Some(Err(err)) = self.context.output_manager.stop_audio_recording() {
+                    log::error!("{err}");
+                }
                 let to = menu_scene::MenuScene::new(&mut self.context, song);
                 self.game_scene = Box::new(to);
             }
-            NeothesiaEvent::MidiInput { channel, message } => {
-                self.game_scene
-                    .midi_event(&mut self.context, channel, &message);
+            NeothesiaEvent::MidiInput(event) => {
+                self.game_scene.midi_event(&mut self.context, &event);
+            }
+            NeothesiaEvent::MidiTransport(event) => {
+                self.game_scene.transport_event(&mut self.context, &event);
+            }
+            NeothesiaEvent::MidiSystem(event) => {
+                self.game_scene.system_midi_event(&mut self.context, &event);
+            }
+            NeothesiaEvent::MidiPort(event) => {
+                if let Some(change) = self.midi_port_event(event) {
+                    self.game_scene
+                        .midi_device_event(&mut self.context, &change);
+                }
             }
             NeothesiaEvent::Exit => {
                 event_loop.exit();
@@ -157,14 +178,45 @@ impl Neothesia {
         }
     }
 
+    /// Reconnect the configured devices when they come back
+    fn midi_port_event(&mut self, event: midi_io::PortEvent) -> Option<MidiDeviceEvent> {
+        let ctx = &mut self.context;
+        match event {
+            midi_io::PortEvent::InputAdded(port) => {
+                let name = port.to_string();
+                let wanted = ctx.config.input() == Some(name.as_str())
+                    || ctx.config.extra_inputs().contains(&name)
+                    || ctx.config.clock_input() == Some(name.as_str());
+                (wanted && ctx.input_manager.reconnect(&port))
+                    .then_some(MidiDeviceEvent::Reconnected(name))
+            }
+            midi_io::PortEvent::InputRemoved(port) => ctx
+                .input_manager
+                .disconnect(&port)
+                .then(|| MidiDeviceEvent::Disconnected(port.to_string())),
+            midi_io::PortEvent::OutputAdded(port) => ctx
+                .output_manager
+                .reconnect_midi_port(&port)
+                .then(|| MidiDeviceEvent::Reconnected(port.to_string())),
+            midi_io::PortEvent::OutputRemoved(port) => ctx
+                .output_manager
+                .uses_midi_port(&port)
+                .then(|| MidiDeviceEvent::Disconnected(port.to_string())),
+        }
+    }
+
     fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
         self.context.window.request_redraw();
     }
 
     #[profiling::function]
     fn update(&mut self, delta: Duration) {
-        #[cfg(debug_assertions)]
         self.context.fps_ticker.tick();
+        self.context.output_manager.update();
+        if let Some(result) = self.context.output_manager.take_interrupted_recording() {
+            self.game_scene
+                .audio_recording_stopped(&mut self.context, result);
+        }
 
         self.game_scene.update(&mut self.context, delta);
     }
@@ -0,0 +1,441 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, SyncSender, TrySendError},
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Blocks that may wait for the writer thread, before the audio thread starts dropping them
+const QUEUE_LEN: usize = 256;
+/// Buffers of every capture, the audio thread drops blocks while all of them are in flight
+const BUFFER_COUNT: usize = 32;
+/// Samples preallocated in each buffer, enough for the usual callback sizes
+const BUFFER_CAPACITY: usize = 8192;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioRecorderError {
+    #[error("The built-in synth is not in use")]
+    NoSynth,
+    #[error("Failed to write audio file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Finished audio recording
+#[derive(Debug)]
+pub struct AudioRecording {
+    pub path: PathBuf,
+    pub duration: Duration,
+}
+
+/// Path for a new recording, in the recordings folder of the user
+pub fn recording_path(name: &str) -> Option<PathBuf> {
+    let dir = neothesia_core::utils::resources::recordings_dir()?;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Some(dir.join(format!("{name}-{secs}.wav")))
+}
+
+enum CaptureMsg {
+    /// New source, with the channel its buffers are handed back on
+    Attach {
+        source: u32,
+        recycle: SyncSender<Vec<f32>>,
+    },
+    Samples {
+        source: u32,
+        /// Frames dropped since the previous block, left as silence in the mix
+        skipped: u64,
+        samples: Vec<f32>,
+    },
+    /// Capture taken off a stream, dropped here rather than on the audio thread
+    Release(AudioCapture),
+    Detach(u32),
+    Finish,
+}
+
+/// Sink for the audio of one synth stream, fed from the audio thread
+pub struct AudioCapture {
+    source: u32,
+    tx: SyncSender<CaptureMsg>,
+    /// Buffers that the writer is done with, so that the audio thread doesn't allocate
+    free: Receiver<Vec<f32>>,
+    /// Puts buffers back into `free` when the writer can't take them
+    recycle: SyncSender<Vec<f32>>,
+    /// Frames dropped since the last block that got through
+    skipped: u64,
+}
+
+impl AudioCapture {
+    /// Queue a block of interleaved stereo frames, dropping it if the writer can't keep up
+    pub fn push(&mut self, block: &[f32]) {
+        let frames = block.len() as u64 / 2;
+        let Ok(mut samples) = self.free.try_recv() else {
+            self.skipped += frames;
+            return;
+        };
+        samples.clear();
+        samples.extend_from_slice(block);
+
+        let msg = CaptureMsg::Samples {
+            source: self.source,
+            skipped: self.skipped,
+            samples,
+        };
+        match self.tx.try_send(msg) {
+            Ok(()) => self.skipped = 0,
+            Err(TrySendError::Full(CaptureMsg::Samples { samples, .. })) => {
+                self.skipped += frames;
+                // There is room for it, the buffer was just taken out
+                self.recycle.try_send(samples).ok();
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Hand the capture over to the writer thread, so that its buffers aren't freed on the audio thread
+    pub fn release(self) {
+        let tx = self.tx.clone();
+        // Only dropped here if the queue is full
+        tx.try_send(CaptureMsg::Release(self)).ok();
+    }
+}
+
+impl Drop for AudioCapture {
+    fn drop(&mut self) {
+        self.tx.try_send(CaptureMsg::Detach(self.source)).ok();
+    }
+}
+
+/// Mixes the audio of synth streams into a 16-bit stereo WAV file, on a writer thread
+pub struct AudioRecorder {
+    path: PathBuf,
+    sample_rate: u32,
+    started_at: Instant,
+
+    tx: SyncSender<CaptureMsg>,
+    writer: Option<JoinHandle<io::Result<u64>>>,
+    next_source: u32,
+}
+
+impl AudioRecorder {
+    pub fn start(path: PathBuf, sample_rate: u32) -> Result<Self, AudioRecorderError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let wav = WavWriter::new(BufWriter::new(File::create(&path)?), sample_rate)?;
+
+        let (tx, rx) = std::sync::mpsc::sync_channel(QUEUE_LEN);
+        // Streams can be half a second apart, before a lagging one is given up on
+        let lag = sample_rate as u64 / 2;
+        let writer = std::thread::Builder::new()
+            .name("audio-recorder".into())
+            .spawn(move || write_loop(rx, wav, lag))?;
+
+        Ok(Self {
+            path,
+            sample_rate,
+            started_at: Instant::now(),
+
+            tx,
+            writer: Some(writer),
+            next_source: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// New sink, for one more stream to be mixed in
+    pub fn capture(&mut self) -> AudioCapture {
+        self.next_source += 1;
+        let source = self.next_source;
+
+        let (recycle, free) = std::sync::mpsc::sync_channel(BUFFER_COUNT);
+        for _ in 0..BUFFER_COUNT {
+            recycle.try_send(Vec::with_capacity(BUFFER_CAPACITY)).ok();
+        }
+        self.tx
+            .send(CaptureMsg::Attach {
+                source,
+                recycle: recycle.clone(),
+            })
+            .ok();
+
+        AudioCapture {
+            source,
+            tx: self.tx.clone(),
+            free,
+            recycle,
+            skipped: 0,
+        }
+    }
+
+    /// Write the remaining audio and close the file
+    pub fn finish(mut self) -> Result<AudioRecording, AudioRecorderError> {
+        let frames = self.join()?;
+        Ok(AudioRecording {
+            path: std::mem::take(&mut self.path),
+            duration: Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64),
+        })
+    }
+
+    fn join(&mut self) -> io::Result<u64> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(0);
+        };
+        // Blocking send, so that the finish message can't be dropped by a full queue
+        self.tx.send(CaptureMsg::Finish).ok();
+        writer
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("audio recorder thread panicked")))
+    }
+}
+
+impl Drop for AudioRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.join() {
+            log::error!("Failed to write audio recording: {err}");
+        }
+    }
+}
+
+fn write_loop(
+    rx: Receiver<CaptureMsg>,
+    mut wav: WavWriter<BufWriter<File>>,
+    lag: u64,
+) -> io::Result<u64> {
+    let mut mixer = Mixer::default();
+    let mut recycle = HashMap::new();
+    for msg in rx {
+        match msg {
+            CaptureMsg::Attach {
+                source,
+                recycle: tx,
+            } => {
+                recycle.insert(source, tx);
+            }
+            CaptureMsg::Samples {
+                source,
+                skipped,
+                samples,
+            } => {
+                mixer.push(source, skipped, &samples);
+                if let Some(tx) = recycle.get(&source) {
+                    tx.try_send(samples).ok();
+                }
+                wav.write(&mixer.take_ready(lag))?;
+            }
+            CaptureMsg::Release(capture) => {
+                mixer.remove(capture.source);
+                recycle.remove(&capture.source);
+            }
+            CaptureMsg::Detach(source) => {
+                mixer.remove(source);
+                recycle.remove(&source);
+            }
+            CaptureMsg::Finish => break,
+        }
+    }
+
+    wav.write(&mixer.take_all())?;
+    let frames = wav.frames();
+    wav.finish()?;
+    Ok(frames)
+}
+
+/// Sums interleaved stereo blocks of several streams, each continuing where its previous block ended
+#[derive(Default)]
+struct Mixer {
+    buffer: VecDeque<f32>,
+    /// Frame at the front of `buffer`
+    start: u64,
+    /// Next frame of every source
+    sources: HashMap<u32, u64>,
+}
+
+impl Mixer {
+    fn end(&self) -> u64 {
+        self.start + self.buffer.len() as u64 / 2
+    }
+
+    /// Add a block of a source, `skipped` frames after the end of its previous block
+    fn push(&mut self, source: u32, skipped: u64, samples: &[f32]) {
+        // The first block of a new source lines up with the end of the mix,
+        // as it covers the same period as the latest blocks of the other sources
+        let end = self.end();
+        let frames = samples.len() as u64 / 2;
+        let join_at = end.saturating_sub(frames).max(self.start);
+        let pos = match self.sources.get(&source) {
+            Some(pos) => pos + skipped,
+            None => join_at,
+        };
+        let pos_end = pos + frames;
+
+        if pos_end > end {
+            self.buffer.resize((pos_end - self.start) as usize * 2, 0.0);
+        }
+
+        for (id, sample) in samples.iter().enumerate() {
+            let frame = pos + id as u64 / 2;
+            // Frames that were already written out
+            if frame < self.start {
+                continue;
+            }
+            self.buffer[(frame - self.start) as usize * 2 + id % 2] += sample;
+        }
+
+        self.sources.insert(source, pos_end);
+    }
+
+    fn remove(&mut self, source: u32) {
+        self.sources.remove(&source);
+    }
+
+    /// Frames that every source got past, or that are more than `lag` frames old
+    fn take_ready(&mut self, lag: u64) -> Vec<f32> {
+        let complete = self.sources.values().copied().min().unwrap_or(self.start);
+        let ready = complete.max(self.end().saturating_sub(lag));
+        self.take_until(ready)
+    }
+
+    fn take_all(&mut self) -> Vec<f32> {
+        self.take_until(self.end())
+    }
+
+    fn take_until(&mut self, frame: u64) -> Vec<f32> {
+        let frames = frame
+            .saturating_sub(self.start)
+            .min(self.end() - self.start);
+        self.start += frames;
+        self.buffer.drain(..frames as usize * 2).collect()
+    }
+}
+
+/// 16-bit PCM stereo WAV, the sizes in the header are filled in on finish
+struct WavWriter<W: Write + Seek> {
+    out: W,
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    const CHANNELS: u16 = 2;
+    const BITS: u16 = 16;
+
+    fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let block_align = Self::CHANNELS * Self::BITS / 8;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVE")?;
+
+        out.write_all(b"fmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&Self::CHANNELS.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&Self::BITS.to_le_bytes())?;
+
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { out, data_len: 0 })
+    }
+
+    fn frames(&self) -> u64 {
+        self.data_len as u64 / (Self::CHANNELS * Self::BITS / 8) as u64
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len = self.data_len.saturating_add(samples.len() as u32 * 2);
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&self.data_len.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixer_sums_sources_and_waits_for_the_slowest() {
+        let mut mixer = Mixer::default();
+
+        mixer.push(1, 0, &[0.5; 4]);
+        mixer.push(2, 0, &[0.25; 4]);
+        assert_eq!(mixer.take_ready(100), vec![0.75; 4]);
+
+        mixer.push(1, 0, &[0.5; 4]);
+        // Source 2 didn't catch up yet
+        assert!(mixer.take_ready(100).is_empty());
+        mixer.push(2, 0, &[0.25; 4]);
+        assert_eq!(mixer.take_ready(100), vec![0.75; 4]);
+
+        // A source that stopped is given up on after `lag` frames
+        mixer.push(1, 0, &[0.5; 6]);
+        assert_eq!(mixer.take_ready(1), vec![0.5; 4]);
+        assert_eq!(mixer.take_all(), vec![0.5; 2]);
+    }
+
+    #[test]
+    fn mixer_leaves_a_gap_for_skipped_frames() {
+        let mut mixer = Mixer::default();
+
+        mixer.push(1, 0, &[0.5; 4]);
+        mixer.push(2, 0, &[0.25; 4]);
+        // Source 2 dropped a frame, the block after it stays in time with source 1
+        mixer.push(1, 0, &[0.5; 4]);
+        mixer.push(2, 1, &[0.25; 2]);
+
+        assert_eq!(
+            mixer.take_all(),
+            vec![0.75, 0.75, 0.75, 0.75, 0.5, 0.5, 0.75, 0.75]
+        );
+    }
+
+    #[test]
+    fn wav_header_sizes() {
+        let mut wav = WavWriter::new(io::Cursor::new(Vec::new()), 48000).unwrap();
+        wav.write(&[1.0, -1.0, 0.0, 0.0]).unwrap();
+        assert_eq!(wav.frames(), 2);
+
+        let data = wav.finish().unwrap().into_inner();
+        let u32_at = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 8);
+        assert_eq!(u32_at(24), 48000);
+        assert_eq!(u32_at(40), 8);
+        assert_eq!(i16::from_le_bytes([data[44], data[45]]), i16::MAX);
+        assert_eq!(i16::from_le_bytes([data[46], data[47]]), -i16::MAX);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashSet, rc::Rc};
+use std::{cell::RefCell, collections::HashSet, net::SocketAddr, rc::Rc};
 
 use crate::output_manager::OutputDescriptor;
 
@@ -48,9 +48,13 @@ impl MidiBackend {
         })
     }
 
-    pub fn get_outputs(&self) -> Vec<OutputDescriptor> {
+    /// Ports of the system, followed by the network output if there is one
+    pub fn get_outputs(&self, network: Option<SocketAddr>) -> Vec<OutputDescriptor> {
+        let network = network.map(midi_io::MidiOutputPort::network);
+        let ports = self.manager.outputs().into_iter().chain(network);
+
         let mut outs = Vec::new();
-        for (id, port) in self.manager.outputs().into_iter().enumerate() {
+        for (id, port) in ports.enumerate() {
             outs.push(OutputDescriptor::MidiOut(MidiPortInfo { id, port }))
         }
         outs
@@ -63,6 +67,26 @@ impl MidiBackend {
 }
 
 impl MidiOutputConnection {
+    /// Open the port again after it was plugged back in, every handle follows.
+    ///
+    /// Returns `false` if the port is not available.
+    pub fn reconnect(&self, port: &midi_io::MidiOutputPort) -> bool {
+        let Some(conn) = midi_io::MidiOutputManager::connect_output(port.clone()) else {
+            return false;
+        };
+
+        let inner = &mut *self.inner.borrow_mut();
+        // Notes that were playing on the old device are gone with it
+        inner.active_notes.clear();
+        inner.conn = conn;
+        true
+    }
+
+    /// Check if both handles point to the same device connection
+    pub fn same_connection(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
     pub fn midi_event(&self, channel: u4, message: midly::MidiMessage) {
         let inner = &mut *self.inner.borrow_mut();
         match message {
@@ -82,6 +106,25 @@ impl MidiOutputConnection {
         inner.conn.send(&inner.buf).ok();
     }
 
+    pub fn sysex(&self, data: &[u8]) {
+        let inner = &mut *self.inner.borrow_mut();
+
+        inner.buf.clear();
+        inner.buf.push(0xF0);
+        inner.buf.extend_from_slice(data);
+        if inner.buf.last() != Some(&0xF7) {
+            inner.buf.push(0xF7);
+        }
+
+        inner.conn.send(&inner.buf).ok();
+    }
+
+    pub fn send_raw(&self, data: &[u8]) {
+        if !data.is_empty() {
+            self.inner.borrow_mut().conn.send(data).ok();
+        }
+    }
+
     pub fn stop_all(&self) {
         let inner = &mut *self.inner.borrow_mut();
         for note in std::mem::take(&mut inner.active_notes).iter() {
@@ -118,6 +161,12 @@ impl PartialEq for MidiPortInfo {
     }
 }
 
+impl MidiPortInfo {
+    pub fn port(&self) -> &midi_io::MidiOutputPort {
+        &self.port
+    }
+}
+
 impl std::fmt::Display for MidiPortInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.port)
@@ -1,6 +1,10 @@
+mod audio_recorder;
 mod midi_backend;
+pub use audio_recorder::{AudioRecorder, AudioRecorderError, AudioRecording, recording_path};
 use midi_backend::{MidiBackend, MidiPortInfo};
 
+#[cfg(feature = "synth")]
+mod soundfont;
 #[cfg(feature = "synth")]
 mod synth_backend;
 
@@ -8,16 +12,21 @@ mod synth_backend;
 use synth_backend::SynthBackend;
 
 use std::{
+    collections::HashMap,
     fmt::{self, Display, Formatter},
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
 use midi_file::midly::{MidiMessage, num::u4};
+use neothesia_core::config::SynthConfigV1;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum OutputDescriptor {
+    /// SoundFonts (SF2, SF3) and SFZ instruments to load, lowest priority first
     #[cfg(feature = "synth")]
-    Synth(Option<PathBuf>),
+    Synth(Vec<PathBuf>),
     MidiOut(MidiPortInfo),
     DummyOutput,
 }
@@ -51,6 +60,24 @@ impl Display for OutputDescriptor {
     }
 }
 
+/// Audio timings of a synth connection
+#[derive(Debug, Clone, Copy)]
+pub struct SynthLatency {
+    pub sample_rate: u32,
+    /// Frames rendered per audio callback
+    pub buffer_frames: u32,
+    /// Time from the audio callback until the device plays the buffer
+    pub output_latency: Duration,
+    /// Average time from sending an event until it is heard
+    pub event_latency: Duration,
+}
+
+impl SynthLatency {
+    pub fn buffer_duration(&self) -> Duration {
+        Duration::from_secs_f64(self.buffer_frames as f64 / self.sample_rate.max(1) as f64)
+    }
+}
+
 #[derive(Clone)]
 pub enum OutputConnection {
     Midi(midi_backend::MidiOutputConnection),
@@ -68,10 +95,62 @@ impl OutputConnection {
             OutputConnection::DummyOutput => {}
         }
     }
-    pub fn set_gain(&self, gain: f32) {
+    /// Send SysEx message, `data` is the payload without the leading `0xF0`
+    pub fn sysex(&self, data: &[u8]) {
+        match self {
+            OutputConnection::Midi(b) => b.sysex(data),
+            #[cfg(feature = "synth")]
+            OutputConnection::Synth(b) => b.sysex(data),
+            OutputConnection::DummyOutput => {}
+        }
+    }
+
+    /// Send raw bytes of a MIDI file escape event
+    pub fn escape(&self, data: &[u8]) {
+        match self {
+            OutputConnection::Midi(b) => b.send_raw(data),
+            _ => {}
+        }
+    }
+
+    /// Send raw bytes of a clock or transport message, other devices can follow the playback with
+    pub fn transport(&self, data: &[u8]) {
+        match self {
+            OutputConnection::Midi(b) => b.send_raw(data),
+            _ => {}
+        }
+    }
+
+    pub fn set_synth_config(&self, config: &SynthConfigV1) {
+        match self {
+            #[cfg(feature = "synth")]
+            OutputConnection::Synth(b) => b.set_config(config),
+            _ => {}
+        }
+    }
+    /// Audio timings, for outputs that render audio
+    pub fn latency(&self) -> Option<SynthLatency> {
+        match self {
+            #[cfg(feature = "synth")]
+            OutputConnection::Synth(b) => Some(b.latency()),
+            _ => None,
+        }
+    }
+    /// Take presets of a channel from a specific SoundFont, `None` searches the whole font stack
+    pub fn set_channel_font(&self, channel: u4, font: Option<&Path>) {
+        match self {
+            #[cfg(feature = "synth")]
+            OutputConnection::Synth(b) => b.set_channel_font(channel, font),
+            _ => {}
+        }
+    }
+    /// Let the output know that a channel plays a drum kit.
+    ///
+    /// External MIDI devices pick it up from bank select and SysEx on their own.
+    pub fn set_drum_channel(&self, channel: u4, is_drum: bool) {
         match self {
             #[cfg(feature = "synth")]
-            OutputConnection::Synth(b) => b.set_gain(gain),
+            OutputConnection::Synth(b) => b.set_drum_channel(channel, is_drum),
             _ => {}
         }
     }
@@ -83,6 +162,58 @@ impl OutputConnection {
             OutputConnection::DummyOutput => {}
         }
     }
+
+    /// Check if both handles point to the same underlying connection
+    pub fn same_connection(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OutputConnection::Midi(a), OutputConnection::Midi(b)) => a.same_connection(b),
+            #[cfg(feature = "synth")]
+            (OutputConnection::Synth(a), OutputConnection::Synth(b)) => a.same_connection(b),
+            (OutputConnection::DummyOutput, OutputConnection::DummyOutput) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Connections that a song is played through.
+///
+/// Every track goes to the default connection, unless it was routed somewhere else.
+#[derive(Clone)]
+pub struct TrackOutputs {
+    default: OutputConnection,
+    tracks: HashMap<usize, OutputConnection>,
+}
+
+impl TrackOutputs {
+    pub fn new(default: OutputConnection) -> Self {
+        Self {
+            default,
+            tracks: HashMap::new(),
+        }
+    }
+
+    pub fn route(&mut self, track_id: usize, connection: OutputConnection) {
+        self.tracks.insert(track_id, connection);
+    }
+
+    pub fn default_output(&self) -> &OutputConnection {
+        &self.default
+    }
+
+    pub fn for_track(&self, track_id: usize) -> &OutputConnection {
+        self.tracks.get(&track_id).unwrap_or(&self.default)
+    }
+
+    /// Every distinct connection in use
+    pub fn unique(&self) -> Vec<&OutputConnection> {
+        let mut res: Vec<&OutputConnection> = vec![&self.default];
+        for conn in self.tracks.values() {
+            if !res.iter().any(|c| c.same_connection(conn)) {
+                res.push(conn);
+            }
+        }
+        res
+    }
 }
 
 pub struct OutputManager {
@@ -91,6 +222,16 @@ pub struct OutputManager {
     midi_backend: Option<MidiBackend>,
 
     output_connection: (OutputDescriptor, OutputConnection),
+    /// Outputs opened in addition to the main one, used for per-track routing
+    extra_connections: Vec<(OutputDescriptor, OutputConnection)>,
+
+    /// Settings applied to every synth connection that gets opened
+    synth_config: Option<SynthConfigV1>,
+    audio_recorder: Option<AudioRecorder>,
+    /// Recording that had to be finished, because the synth streams changed their sample rate
+    interrupted_recording: Option<Result<AudioRecording, AudioRecorderError>>,
+    /// Peer that network MIDI is sent to
+    network_output: Option<SocketAddr>,
 }
 
 impl Default for OutputManager {
@@ -124,9 +265,25 @@ impl OutputManager {
             midi_backend,
 
             output_connection: (OutputDescriptor::DummyOutput, OutputConnection::DummyOutput),
+            extra_connections: Vec::new(),
+            synth_config: None,
+            audio_recorder: None,
+            interrupted_recording: None,
+            network_output: None,
         }
     }
 
+    /// Offer network MIDI to `addr` (`ip:port`) as one more output
+    ///
+    /// Host names are not resolved, that would block the UI thread.
+    pub fn set_network_output(&mut self, addr: Option<&str>) {
+        self.network_output = addr.and_then(|addr| {
+            addr.parse::<SocketAddr>()
+                .inspect_err(|err| log::error!("Invalid network MIDI address {addr}: {err}"))
+                .ok()
+        });
+    }
+
     pub fn outputs(&self) -> Vec<OutputDescriptor> {
         let mut outs = Vec::new();
 
@@ -135,7 +292,7 @@ impl OutputManager {
             outs.append(&mut synth.get_outputs());
         }
         if let Some(midi) = &self.midi_backend {
-            outs.append(&mut midi.get_outputs());
+            outs.append(&mut midi.get_outputs(self.network_output));
         }
 
         outs.push(OutputDescriptor::DummyOutput);
@@ -143,40 +300,259 @@ impl OutputManager {
         outs
     }
 
-    pub fn connect(&mut self, desc: OutputDescriptor) {
-        if desc != self.output_connection.0 {
-            match desc {
-                #[cfg(feature = "synth")]
-                OutputDescriptor::Synth(ref font) => {
-                    if let Some(ref mut synth) = self.synth_backend {
-                        if let Some(font) = font.clone() {
-                            self.output_connection = (
-                                desc,
-                                OutputConnection::Synth(synth.new_output_connection(&font)),
-                            );
-                        } else if let Some(path) = crate::utils::resources::default_sf2()
-                            && path.exists()
-                        {
-                            self.output_connection = (
-                                desc,
-                                OutputConnection::Synth(synth.new_output_connection(&path)),
-                            );
+    fn open(&mut self, desc: &OutputDescriptor) -> Option<OutputConnection> {
+        let conn = self.open_connection(desc)?;
+        if let Some(config) = self.synth_config.as_ref() {
+            conn.set_synth_config(config);
+        }
+        Some(conn)
+    }
+
+    fn open_connection(&mut self, desc: &OutputDescriptor) -> Option<OutputConnection> {
+        match desc {
+            #[cfg(feature = "synth")]
+            OutputDescriptor::Synth(fonts) => {
+                let synth = self.synth_backend.as_mut()?;
+                let fonts: Vec<_> = fonts
+                    .iter()
+                    .filter_map(|path| {
+                        let font = soundfont::SoundFontFile::detect(path);
+                        if font.is_none() {
+                            log::warn!("Unsupported instrument file {path:?}");
                         }
-                    }
+                        font
+                    })
+                    .collect();
+
+                if fonts.is_empty() {
+                    return None;
                 }
-                OutputDescriptor::MidiOut(ref info) => {
-                    if let Some(conn) = MidiBackend::new_output_connection(info) {
-                        self.output_connection = (desc, OutputConnection::Midi(conn));
+
+                match synth.new_output_connection(&fonts) {
+                    Ok(conn) => Some(OutputConnection::Synth(conn)),
+                    Err(err) => {
+                        log::error!("Failed to open audio stream: {err}");
+                        None
                     }
                 }
-                OutputDescriptor::DummyOutput => {
-                    self.output_connection = (desc, OutputConnection::DummyOutput);
-                }
             }
+            OutputDescriptor::MidiOut(info) => {
+                MidiBackend::new_output_connection(info).map(OutputConnection::Midi)
+            }
+            OutputDescriptor::DummyOutput => Some(OutputConnection::DummyOutput),
         }
     }
 
+    pub fn connect(&mut self, desc: OutputDescriptor) {
+        if desc != self.output_connection.0 {
+            // Reuse the connection if it's already open as an extra output,
+            // as some devices don't allow to be opened twice
+            if let Some(id) = self.extra_connections.iter().position(|(d, _)| *d == desc) {
+                let extra = self.extra_connections.remove(id);
+                self.output_connection = extra;
+            } else if let Some(conn) = self.open(&desc) {
+                self.output_connection = (desc, conn);
+            }
+        }
+    }
+
+    /// Open an additional output, next to the main one
+    pub fn connect_extra(&mut self, desc: OutputDescriptor) {
+        let is_open = self.output_connection.0 == desc
+            || self.extra_connections.iter().any(|(d, _)| *d == desc);
+
+        if !is_open && let Some(conn) = self.open(&desc) {
+            self.extra_connections.push((desc, conn));
+        }
+    }
+
+    /// Close extra outputs that are no longer needed
+    pub fn retain_extra(&mut self, f: impl Fn(&OutputDescriptor) -> bool) {
+        self.extra_connections.retain(|(desc, _)| f(desc));
+    }
+
     pub fn connection(&self) -> &OutputConnection {
         &self.output_connection.1
     }
+
+    /// Find an open connection by the output name
+    pub fn connection_by_name(&self, name: &str) -> Option<&OutputConnection> {
+        std::iter::once(&self.output_connection)
+            .chain(self.extra_connections.iter())
+            .find(|(desc, _)| desc.to_string() == name)
+            .map(|(_, conn)| conn)
+    }
+
+    /// Reopen MIDI outputs in use when their device is plugged back in.
+    ///
+    /// Returns `true` if an open output was reconnected.
+    pub fn reconnect_midi_port(&mut self, port: &midi_io::MidiOutputPort) -> bool {
+        let mut reconnected = false;
+        for (desc, conn) in std::iter::once(&self.output_connection).chain(&self.extra_connections)
+        {
+            if let (OutputDescriptor::MidiOut(info), OutputConnection::Midi(conn)) = (desc, conn)
+                && info.port() == port
+            {
+                reconnected |= conn.reconnect(port);
+            }
+        }
+        reconnected
+    }
+
+    /// Check if an open output uses a MIDI port
+    pub fn uses_midi_port(&self, port: &midi_io::MidiOutputPort) -> bool {
+        std::iter::once(&self.output_connection)
+            .chain(&self.extra_connections)
+            .any(|(desc, _)| matches!(desc, OutputDescriptor::MidiOut(info) if info.port() == port))
+    }
+
+    /// Names of the audio hosts that the synth can play through
+    pub fn audio_hosts(&self) -> Vec<String> {
+        #[cfg(feature = "synth")]
+        return SynthBackend::hosts();
+        #[cfg(not(feature = "synth"))]
+        Vec::new()
+    }
+
+    /// Names of the output devices of an audio host, `None` is the default host
+    pub fn audio_devices(&self, host: Option<&str>) -> Vec<String> {
+        #[cfg(feature = "synth")]
+        return SynthBackend::output_devices(host);
+        #[cfg(not(feature = "synth"))]
+        {
+            let _ = host;
+            Vec::new()
+        }
+    }
+
+    /// Watch for audio devices that went away, falling back to the default device
+    pub fn update(&mut self) {
+        #[cfg(feature = "synth")]
+        if let Some(synth) = self.synth_backend.as_mut()
+            && synth.take_device_lost()
+        {
+            log::warn!("Audio device is no longer available, reconnecting");
+            if synth.reopen_device() {
+                if let Some(config) = self.synth_config.as_ref() {
+                    synth.set_sample_rate(config.sample_rate);
+                    synth.set_buffer_size(config.buffer_size);
+                }
+                self.reopen_synth_connections();
+            }
+        }
+    }
+
+    /// Apply synth settings to the open connections.
+    ///
+    /// Synth connections are reopened when the audio device, sample rate or buffer size changes.
+    pub fn set_synth_config(&mut self, config: &SynthConfigV1) {
+        self.synth_config = Some(config.clone());
+
+        #[cfg(feature = "synth")]
+        if let Some(synth) = self.synth_backend.as_mut() {
+            let device_changed =
+                synth.select_device(config.audio_host.as_deref(), config.audio_device.as_deref());
+            let rate_changed = synth.set_sample_rate(config.sample_rate);
+            let size_changed = synth.set_buffer_size(config.buffer_size);
+            if device_changed || rate_changed || size_changed {
+                self.reopen_synth_connections();
+            }
+        }
+
+        self.output_connection.1.set_synth_config(config);
+        for (_, conn) in self.extra_connections.iter() {
+            conn.set_synth_config(config);
+        }
+    }
+
+    #[cfg(feature = "synth")]
+    fn synth_connections(&self) -> Vec<(OutputDescriptor, synth_backend::SynthOutputConnection)> {
+        std::iter::once(&self.output_connection)
+            .chain(self.extra_connections.iter())
+            .filter_map(|(desc, conn)| match conn {
+                OutputConnection::Synth(conn) => Some((desc.clone(), conn.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Reopen synth streams in place, so that scenes holding their handles keep playing.
+    ///
+    /// The new streams get the synth settings and channel state of the old ones.
+    #[cfg(feature = "synth")]
+    fn reopen_synth_connections(&mut self) {
+        for (desc, conn) in self.synth_connections() {
+            // Close the old stream first, so that the device is free
+            conn.close_stream();
+            if let Some(OutputConnection::Synth(new)) = self.open_connection(&desc) {
+                conn.replace_stream(new);
+            }
+        }
+
+        let connections = self.synth_connections();
+        let Some(recorder) = self.audio_recorder.as_mut() else {
+            return;
+        };
+        // The WAV header can't change its sample rate, the rest would play at the wrong pitch
+        if connections
+            .iter()
+            .any(|(_, conn)| conn.sample_rate() != recorder.sample_rate())
+        {
+            log::warn!("Audio output sample rate changed, finishing the audio recording");
+            self.interrupted_recording = self.stop_audio_recording();
+            return;
+        }
+        for (_, conn) in connections {
+            conn.set_capture(Some(recorder.capture()));
+        }
+    }
+
+    /// Recording that was finished on its own, as the audio output changed underneath it
+    pub fn take_interrupted_recording(
+        &mut self,
+    ) -> Option<Result<AudioRecording, AudioRecorderError>> {
+        self.interrupted_recording.take()
+    }
+
+    /// Start recording the mixed audio of the open synth connections to a WAV file
+    pub fn start_audio_recording(&mut self, path: PathBuf) -> Result<(), AudioRecorderError> {
+        self.stop_audio_recording();
+
+        #[cfg(feature = "synth")]
+        {
+            let connections = self.synth_connections();
+            let Some((_, first)) = connections.first() else {
+                return Err(AudioRecorderError::NoSynth);
+            };
+
+            let mut recorder = AudioRecorder::start(path, first.sample_rate())?;
+            for (_, conn) in connections.iter() {
+                conn.set_capture(Some(recorder.capture()));
+            }
+            self.audio_recorder = Some(recorder);
+            Ok(())
+        }
+
+        #[cfg(not(feature = "synth"))]
+        {
+            let _ = path;
+            Err(AudioRecorderError::NoSynth)
+        }
+    }
+
+    /// Finish the audio recording, if there is one
+    pub fn stop_audio_recording(&mut self) -> Option<Result<AudioRecording, AudioRecorderError>> {
+        let recorder = self.audio_recorder.take()?;
+
+        #[cfg(feature = "synth")]
+        for (_, conn) in self.synth_connections() {
+            conn.set_capture(None);
+        }
+
+        Some(recorder.finish())
+    }
+
+    pub fn audio_recorder(&self) -> Option<&AudioRecorder> {
+        self.audio_recorder.as_ref()
+    }
 }
@@ -0,0 +1,253 @@
+//! Instrument formats of the built-in synth: SF2, SF3 and SFZ
+
+pub mod sampler;
+mod sf3;
+pub mod sfz;
+
+use std::{
+    error::Error,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use midi_file::midly::MidiMessage;
+use sampler::{Sampler, SfzInstrument};
+
+use symphonia::core::{
+    codecs::audio::AudioDecoderOptions,
+    errors::Error as DecodeError,
+    formats::{FormatOptions, TrackType, probe::Hint},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundFontFormat {
+    Sf2,
+    /// SF2 with Ogg Vorbis compressed samples
+    Sf3,
+    Sfz,
+}
+
+impl SoundFontFormat {
+    /// Detect the format from the RIFF header, text based SFZ is detected by the file extension
+    pub fn detect(path: &Path) -> Option<Self> {
+        let mut header = Vec::new();
+        std::fs::File::open(path)
+            .ok()?
+            .take(4096)
+            .read_to_end(&mut header)
+            .ok()?;
+
+        if let Some(major) = sf3::riff_version(&header) {
+            return Some(if major >= 3 { Self::Sf3 } else { Self::Sf2 });
+        }
+
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        (ext == "sfz").then_some(Self::Sfz)
+    }
+}
+
+/// Instrument file together with its detected format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoundFontFile {
+    pub path: PathBuf,
+    pub format: SoundFontFormat,
+}
+
+impl SoundFontFile {
+    pub fn detect(path: &Path) -> Option<Self> {
+        let format = SoundFontFormat::detect(path)?;
+        Some(Self {
+            path: path.to_path_buf(),
+            format,
+        })
+    }
+
+    /// Load SF2 or SF3 file, SF3 samples get decompressed on the way
+    #[cfg(feature = "oxi-synth")]
+    pub fn load_oxisynth(&self) -> Result<oxisynth::SoundFont, Box<dyn Error>> {
+        let font = match self.format {
+            SoundFontFormat::Sf2 => {
+                let mut file = std::fs::File::open(&self.path)?;
+                oxisynth::SoundFont::load(&mut file)
+            }
+            SoundFontFormat::Sf3 => {
+                let sf2 = sf3::to_sf2(&std::fs::read(&self.path)?)?;
+                oxisynth::SoundFont::load(&mut std::io::Cursor::new(sf2))
+            }
+            SoundFontFormat::Sfz => return Err("SFZ is not a SoundFont".into()),
+        };
+        font.map_err(|err| format!("{err:?}").into())
+    }
+
+    /// Path of an SF2 file that a file based loader can read, SF3 gets converted into a temp file
+    #[cfg(feature = "fluid-synth")]
+    pub fn sf2_path(&self) -> Result<PathBuf, Box<dyn Error>> {
+        match self.format {
+            SoundFontFormat::Sf2 => Ok(self.path.clone()),
+            SoundFontFormat::Sf3 => {
+                let name = self.path.file_stem().unwrap_or_default().to_string_lossy();
+                let out = std::env::temp_dir().join(format!("neothesia-{name}.sf2"));
+                std::fs::write(&out, sf3::to_sf2(&std::fs::read(&self.path)?)?)?;
+                Ok(out)
+            }
+            SoundFontFormat::Sfz => Err("SFZ is not a SoundFont".into()),
+        }
+    }
+}
+
+/// SFZ instruments of a synth connection, and the channels they play
+pub struct SfzLayer {
+    samplers: Vec<(PathBuf, Sampler)>,
+    channels: [Option<usize>; 16],
+    /// Sampler used by channels without an explicit font, only when no SoundFont is loaded
+    default: Option<usize>,
+}
+
+impl SfzLayer {
+    pub fn load(fonts: &[SoundFontFile], sample_rate: f32) -> Self {
+        let mut samplers = Vec::new();
+        for font in fonts
+            .iter()
+            .filter(|font| font.format == SoundFontFormat::Sfz)
+        {
+            match SfzInstrument::load(&font.path) {
+                Ok(instrument) => {
+                    let sampler = Sampler::new(Arc::new(instrument), sample_rate);
+                    samplers.push((font.path.clone(), sampler));
+                }
+                Err(err) => log::error!("Failed to load SFZ {:?}: {err}", font.path),
+            }
+        }
+
+        let has_soundfont = fonts.iter().any(|font| font.format != SoundFontFormat::Sfz);
+        let default = (!has_soundfont && !samplers.is_empty()).then(|| samplers.len() - 1);
+
+        Self {
+            samplers,
+            channels: [default; 16],
+            default,
+        }
+    }
+
+    /// Route a channel to an SFZ instrument, returns `false` if the font is not one of ours
+    pub fn set_channel_font(&mut self, channel: u8, font: Option<&Path>) -> bool {
+        let Some(slot) = self.channels.get_mut(channel as usize) else {
+            return false;
+        };
+
+        let id = font.and_then(|font| self.samplers.iter().position(|(path, _)| path == font));
+        let new = id.or(self.default);
+        if *slot != new {
+            if let Some(old) = *slot {
+                self.samplers[old].1.all_notes_off(channel);
+            }
+            *slot = new;
+        }
+
+        id.is_some()
+    }
+
+    /// Returns `true` if the message was consumed by a sampler
+    pub fn midi_event(&mut self, channel: u8, message: MidiMessage) -> bool {
+        match self.channels.get(channel as usize).copied().flatten() {
+            Some(id) => {
+                self.samplers[id].1.midi_event(channel, message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        for (_, sampler) in self.samplers.iter_mut() {
+            sampler.set_gain(gain);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for (_, sampler) in self.samplers.iter_mut() {
+            sampler.reset();
+        }
+    }
+
+    /// Mix the next frames into an interleaved stereo buffer
+    pub fn write(&mut self, out: &mut [f32]) {
+        for (_, sampler) in self.samplers.iter_mut() {
+            sampler.write(out);
+        }
+    }
+}
+
+/// Decoded audio, one buffer per channel
+pub struct DecodedAudio {
+    pub sample_rate: u32,
+    pub channels: Vec<Vec<f32>>,
+}
+
+/// Decode a whole audio stream (WAV, FLAC, Ogg Vorbis)
+pub fn decode_audio(
+    source: Box<dyn MediaSource>,
+    extension: Option<&str>,
+) -> Result<DecodedAudio, Box<dyn Error>> {
+    let mut hint = Hint::new();
+    if let Some(ext) = extension {
+        hint.with_extension(ext);
+    }
+
+    let mss = MediaSourceStream::new(source, Default::default());
+    let mut format = symphonia::default::get_probe().probe(
+        &hint,
+        mss,
+        FormatOptions::default(),
+        MetadataOptions::default(),
+    )?;
+
+    let track = format
+        .default_track(TrackType::Audio)
+        .ok_or("no audio track")?;
+    let track_id = track.id;
+    let params = track
+        .codec_params
+        .as_ref()
+        .and_then(|params| params.audio())
+        .ok_or("unsupported codec")?;
+    let mut sample_rate = params.sample_rate.unwrap_or(0);
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make_audio_decoder(params, &AudioDecoderOptions::default())?;
+
+    let mut channels: Vec<Vec<f32>> = Vec::new();
+    let mut planes: Vec<Vec<f32>> = Vec::new();
+
+    while let Some(packet) = format.next_packet()? {
+        if packet.track_id != track_id {
+            continue;
+        }
+
+        let buf = match decoder.decode(&packet) {
+            Ok(buf) => buf,
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        sample_rate = buf.spec().rate();
+        buf.copy_to_vecs_planar(&mut planes);
+
+        channels.resize_with(planes.len(), Vec::new);
+        for (out, plane) in channels.iter_mut().zip(planes.iter()) {
+            out.extend_from_slice(plane);
+        }
+    }
+
+    if channels.is_empty() || sample_rate == 0 {
+        return Err("empty audio stream".into());
+    }
+
+    Ok(DecodedAudio {
+        sample_rate,
+        channels,
+    })
+}
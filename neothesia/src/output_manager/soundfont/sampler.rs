@@ -0,0 +1,521 @@
+//! Sample player for SFZ instruments
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use midi_file::midly::MidiMessage;
+
+use super::sfz::{LoopMode, Region, Trigger};
+
+const MAX_VOICES: usize = 64;
+/// Semitones
+const PITCH_BEND_RANGE: f32 = 2.0;
+
+struct Sample {
+    sample_rate: f32,
+    channels: Vec<Vec<f32>>,
+}
+
+impl Sample {
+    fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    fn frame(&self, pos: f64) -> (f32, f32) {
+        let i = pos as usize;
+        let t = (pos - i as f64) as f32;
+        let read = |ch: &[f32]| {
+            let a = ch.get(i).copied().unwrap_or(0.0);
+            let b = ch.get(i + 1).copied().unwrap_or(0.0);
+            a + (b - a) * t
+        };
+        let l = read(&self.channels[0]);
+        let r = self.channels.get(1).map(|ch| read(ch)).unwrap_or(l);
+        (l, r)
+    }
+}
+
+struct LoadedRegion {
+    region: Region,
+    sample: Arc<Sample>,
+}
+
+/// SFZ file with all of its samples decoded
+pub struct SfzInstrument {
+    regions: Vec<LoadedRegion>,
+}
+
+impl SfzInstrument {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut cache: HashMap<PathBuf, Arc<Sample>> = HashMap::new();
+        let mut regions = Vec::new();
+
+        for region in super::sfz::load(path)? {
+            let sample = if let Some(sample) = cache.get(&region.sample) {
+                sample.clone()
+            } else {
+                let decoded = std::fs::File::open(&region.sample)
+                    .map_err(Box::<dyn Error>::from)
+                    .and_then(|file| {
+                        let ext = region.sample.extension().and_then(|ext| ext.to_str());
+                        super::decode_audio(Box::new(file), ext)
+                    });
+                let decoded = match decoded {
+                    Ok(decoded) => decoded,
+                    Err(err) => {
+                        log::warn!("Failed to load sample {:?}: {err}", region.sample);
+                        continue;
+                    }
+                };
+                let sample = Arc::new(Sample {
+                    sample_rate: decoded.sample_rate as f32,
+                    channels: decoded.channels,
+                });
+                cache.insert(region.sample.clone(), sample.clone());
+                sample
+            };
+
+            regions.push(LoadedRegion { region, sample });
+        }
+
+        if regions.is_empty() {
+            return Err("SFZ file has no playable regions".into());
+        }
+
+        Ok(Self { regions })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Envelope {
+    attack: f32,
+    hold: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+
+    stage: Stage,
+    level: f32,
+    /// Seconds spent in the current stage
+    time: f32,
+    release_from: f32,
+}
+
+impl Envelope {
+    fn new(region: &Region) -> Self {
+        Self {
+            attack: region.ampeg_attack.max(0.0),
+            hold: region.ampeg_hold.max(0.0),
+            decay: region.ampeg_decay.max(0.0),
+            sustain: (region.ampeg_sustain / 100.0).clamp(0.0, 1.0),
+            release: region.ampeg_release.max(0.001),
+
+            stage: Stage::Attack,
+            level: 0.0,
+            time: 0.0,
+            release_from: 0.0,
+        }
+    }
+
+    fn release(&mut self) {
+        if !matches!(self.stage, Stage::Release | Stage::Done) {
+            self.stage = Stage::Release;
+            self.release_from = self.level;
+            self.time = 0.0;
+        }
+    }
+
+    fn next(&mut self, dt: f32) -> f32 {
+        self.time += dt;
+        loop {
+            match self.stage {
+                Stage::Attack if self.time >= self.attack => {
+                    self.time -= self.attack;
+                    self.stage = Stage::Hold;
+                }
+                Stage::Attack => {
+                    self.level = self.time / self.attack;
+                    break;
+                }
+                Stage::Hold if self.time >= self.hold => {
+                    self.time -= self.hold;
+                    self.stage = Stage::Decay;
+                }
+                Stage::Hold => {
+                    self.level = 1.0;
+                    break;
+                }
+                Stage::Decay if self.time >= self.decay => {
+                    self.time = 0.0;
+                    self.stage = Stage::Sustain;
+                }
+                Stage::Decay => {
+                    self.level = 1.0 - (1.0 - self.sustain) * self.time / self.decay;
+                    break;
+                }
+                Stage::Sustain => {
+                    self.level = self.sustain;
+                    break;
+                }
+                Stage::Release if self.time >= self.release => {
+                    self.stage = Stage::Done;
+                }
+                Stage::Release => {
+                    self.level = self.release_from * (1.0 - self.time / self.release);
+                    break;
+                }
+                Stage::Done => {
+                    self.level = 0.0;
+                    break;
+                }
+            }
+        }
+        self.level
+    }
+}
+
+struct Voice {
+    region: usize,
+    channel: u8,
+    key: u8,
+    pos: f64,
+    step: f64,
+    end: f64,
+    /// Loop range, when the voice currently loops
+    looping: Option<(f64, f64)>,
+    loop_mode: LoopMode,
+    gain: f32,
+    pan: f32,
+    envelope: Envelope,
+    /// Note off arrived while the sustain pedal was down
+    sustained: bool,
+    released: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChannelState {
+    volume: u8,
+    expression: u8,
+    pan: u8,
+    sustain: bool,
+    bend: f32,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            volume: 100,
+            expression: 127,
+            pan: 64,
+            sustain: false,
+            bend: 1.0,
+        }
+    }
+}
+
+impl ChannelState {
+    fn gain(&self) -> f32 {
+        let volume = self.volume as f32 / 127.0;
+        let expression = self.expression as f32 / 127.0;
+        volume * volume * expression * expression
+    }
+}
+
+pub struct Sampler {
+    instrument: Arc<SfzInstrument>,
+    sample_rate: f32,
+    gain: f32,
+
+    voices: Vec<Voice>,
+    channels: [ChannelState; 16],
+    /// Velocity of held keys, used by release triggers
+    velocities: [[u8; 128]; 16],
+    rand_state: u32,
+}
+
+impl Sampler {
+    pub fn new(instrument: Arc<SfzInstrument>, sample_rate: f32) -> Self {
+        Self {
+            instrument,
+            sample_rate,
+            gain: 0.2,
+
+            voices: Vec::with_capacity(MAX_VOICES),
+            channels: [ChannelState::default(); 16],
+            velocities: [[0; 128]; 16],
+            rand_state: 0x2545_f491,
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    pub fn reset(&mut self) {
+        self.voices.clear();
+        self.channels = [ChannelState::default(); 16];
+        self.velocities = [[0; 128]; 16];
+    }
+
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        let mut x = self.rand_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rand_state = x;
+        (x >> 8) as f32 / (1 << 24) as f32
+    }
+
+    pub fn midi_event(&mut self, channel: u8, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                self.note_on(channel, key.as_int(), vel.as_int(), Trigger::Attack);
+            }
+            MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                self.note_off(channel, key.as_int());
+            }
+            MidiMessage::Controller { controller, value } => {
+                self.control_change(channel, controller.as_int(), value.as_int())
+            }
+            MidiMessage::PitchBend { bend } => {
+                if let Some(state) = self.channels.get_mut(channel as usize) {
+                    let semitones = bend.as_f32() * PITCH_BEND_RANGE;
+                    state.bend = 2f32.powf(semitones / 12.0);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn control_change(&mut self, channel: u8, ctrl: u8, value: u8) {
+        let Some(state) = self.channels.get_mut(channel as usize) else {
+            return;
+        };
+
+        match ctrl {
+            7 => state.volume = value,
+            10 => state.pan = value,
+            11 => state.expression = value,
+            64 => {
+                state.sustain = value >= 64;
+                if !state.sustain {
+                    for voice in self.voices.iter_mut() {
+                        if voice.channel == channel && voice.sustained {
+                            voice.sustained = false;
+                            voice.envelope.release();
+                        }
+                    }
+                }
+            }
+            120 => self.voices.retain(|voice| voice.channel != channel),
+            121 => *state = ChannelState::default(),
+            123 => self.all_notes_off(channel),
+            _ => {}
+        }
+    }
+
+    pub fn all_notes_off(&mut self, channel: u8) {
+        for voice in self.voices.iter_mut() {
+            if voice.channel == channel {
+                voice.sustained = false;
+                voice.released = true;
+                voice.envelope.release();
+            }
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, vel: u8, trigger: Trigger) {
+        if channel >= 16 || key >= 128 {
+            return;
+        }
+        if trigger == Trigger::Attack {
+            self.velocities[channel as usize][key as usize] = vel;
+        }
+
+        let rand = self.random();
+        let instrument = self.instrument.clone();
+        for (id, loaded) in instrument.regions.iter().enumerate() {
+            let region = &loaded.region;
+            if region.trigger != trigger || !region.matches(channel, key, vel, rand) {
+                continue;
+            }
+            self.start_voice(id, loaded, channel, key, vel);
+        }
+    }
+
+    fn note_off(&mut self, channel: u8, key: u8) {
+        if channel >= 16 || key >= 128 {
+            return;
+        }
+        let sustain = self.channels[channel as usize].sustain;
+
+        for voice in self.voices.iter_mut() {
+            if voice.channel != channel || voice.key != key || voice.released {
+                continue;
+            }
+            voice.released = true;
+            if voice.loop_mode == LoopMode::OneShot {
+                continue;
+            }
+            if sustain {
+                voice.sustained = true;
+            } else {
+                voice.envelope.release();
+            }
+        }
+
+        let vel = std::mem::take(&mut self.velocities[channel as usize][key as usize]);
+        if vel > 0 {
+            self.note_on(channel, key, vel, Trigger::Release);
+        }
+    }
+
+    fn start_voice(&mut self, id: usize, loaded: &LoadedRegion, channel: u8, key: u8, vel: u8) {
+        let region = &loaded.region;
+        let sample = &loaded.sample;
+
+        let len = sample.len() as f64;
+        let end = region
+            .end
+            .map(|end| (end as f64 + 1.0).min(len))
+            .unwrap_or(len);
+        let pos = region.offset as f64;
+        if pos >= end {
+            return;
+        }
+
+        let loop_mode = match (region.trigger, region.loop_mode) {
+            // Release samples play out on their own
+            (Trigger::Release, _) => LoopMode::OneShot,
+            (_, Some(mode)) => mode,
+            (_, None) if region.loop_end.is_some() => LoopMode::Continuous,
+            (_, None) => LoopMode::NoLoop,
+        };
+        let looping = match loop_mode {
+            LoopMode::Continuous | LoopMode::Sustain => {
+                let start = region.loop_start.unwrap_or(0) as f64;
+                let loop_end = region
+                    .loop_end
+                    .map(|end| (end as f64 + 1.0).min(len))
+                    .unwrap_or(end);
+                (loop_end > start).then_some((start, loop_end))
+            }
+            LoopMode::NoLoop | LoopMode::OneShot => None,
+        };
+
+        let cents = (key as f32 - region.pitch_keycenter as f32) * region.pitch_keytrack
+            + region.transpose as f32 * 100.0
+            + region.tune;
+        let step =
+            2f64.powf(cents as f64 / 1200.0) * sample.sample_rate as f64 / self.sample_rate as f64;
+
+        let velocity = vel as f32 / 127.0;
+        let veltrack = (region.amp_veltrack / 100.0).clamp(-1.0, 1.0);
+        let velocity_gain = if veltrack >= 0.0 {
+            1.0 - veltrack * (1.0 - velocity * velocity)
+        } else {
+            1.0 + veltrack * velocity * velocity
+        };
+        let gain = 10f32.powf(region.volume / 20.0) * velocity_gain;
+
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+
+        self.voices.push(Voice {
+            region: id,
+            channel,
+            key,
+            pos,
+            step,
+            end,
+            looping,
+            loop_mode,
+            gain,
+            pan: (region.pan / 100.0).clamp(-1.0, 1.0),
+            envelope: Envelope::new(region),
+            sustained: false,
+            released: false,
+        });
+    }
+
+    /// Mix the next frames into an interleaved stereo buffer
+    pub fn write(&mut self, out: &mut [f32]) {
+        if self.voices.is_empty() {
+            return;
+        }
+        for frame in out.chunks_exact_mut(2) {
+            let (l, r) = self.read_next();
+            frame[0] += l;
+            frame[1] += r;
+        }
+    }
+
+    fn read_next(&mut self) -> (f32, f32) {
+        if self.voices.is_empty() {
+            return (0.0, 0.0);
+        }
+
+        let dt = 1.0 / self.sample_rate;
+        let mut out = (0.0, 0.0);
+
+        for voice in self.voices.iter_mut() {
+            let loaded = &self.instrument.regions[voice.region];
+            let channel = &self.channels[voice.channel as usize];
+
+            let level = voice.envelope.next(dt);
+            if voice.envelope.stage == Stage::Done {
+                continue;
+            }
+
+            let (l, r) = loaded.sample.frame(voice.pos);
+
+            let pan = (voice.pan + (channel.pan as f32 - 64.0) / 64.0).clamp(-1.0, 1.0);
+            // Constant power pan, unity gain in the center
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+            let gain = voice.gain * level * channel.gain() * std::f32::consts::SQRT_2;
+
+            out.0 += l * angle.cos() * gain;
+            out.1 += r * angle.sin() * gain;
+
+            voice.pos += voice.step * channel.bend as f64;
+
+            // Sustain loops only run while the key is held
+            let loops = match voice.loop_mode {
+                LoopMode::Continuous => true,
+                LoopMode::Sustain => !voice.released || voice.sustained,
+                _ => false,
+            };
+            match voice.looping {
+                Some((start, end)) if loops && voice.pos >= end => {
+                    voice.pos = start + (voice.pos - end) % (end - start);
+                }
+                _ => {
+                    if voice.pos >= voice.end {
+                        voice.envelope.stage = Stage::Done;
+                    }
+                }
+            }
+        }
+
+        self.voices
+            .retain(|voice| voice.envelope.stage != Stage::Done);
+
+        (out.0 * self.gain, out.1 * self.gain)
+    }
+}
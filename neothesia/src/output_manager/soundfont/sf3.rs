@@ -0,0 +1,217 @@
+//! SF3 to SF2 conversion
+//!
+//! SF3 is regular SF2 where each sample is stored as a standalone Ogg Vorbis stream.
+//! The `shdr` start/end offsets point at bytes of the compressed stream and loop points are
+//! relative to the sample start. We decode every sample and rebuild a plain 16-bit SF2 in memory.
+
+use std::error::Error;
+
+const SHDR_LEN: usize = 46;
+const COMPRESSED: u16 = 0x10;
+const ROM: u16 = 0x8000;
+/// SF2 spec requires at least 46 zero samples after each sample
+const SAMPLE_PADDING: usize = 46;
+
+struct Chunk<'a> {
+    id: [u8; 4],
+    data: &'a [u8],
+}
+
+fn chunks(mut data: &[u8]) -> impl Iterator<Item = Chunk<'_>> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let id = data[0..4].try_into().unwrap();
+        let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let body = data.get(8..8 + len)?;
+        let next = (8 + len + (len & 1)).min(data.len());
+        data = &data[next..];
+        Some(Chunk { id, data: body })
+    })
+}
+
+/// Body of a `RIFF`/`LIST` chunk with the given form type
+fn list_body<'a>(chunk: &Chunk<'a>, form: &[u8; 4]) -> Option<&'a [u8]> {
+    (chunk.data.get(0..4)? == form).then(|| &chunk.data[4..])
+}
+
+/// Major `ifil` version of a SoundFont, works on a truncated header
+pub fn riff_version(header: &[u8]) -> Option<u16> {
+    if header.get(0..4)? != b"RIFF" || header.get(8..12)? != b"sfbk" {
+        return None;
+    }
+
+    let mut data = &header[12..];
+    while data.len() >= 12 {
+        let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        if &data[0..4] == b"LIST" && &data[8..12] == b"INFO" {
+            let info = data.get(12..(8 + len).min(data.len()))?;
+            return chunks(info)
+                .find(|chunk| &chunk.id == b"ifil")
+                .and_then(|chunk| {
+                    Some(u16::from_le_bytes(chunk.data.get(0..2)?.try_into().ok()?))
+                });
+        }
+        data = data.get(8 + len + (len & 1)..)?;
+    }
+
+    None
+}
+
+pub fn to_sf2(file: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let riff = chunks(file)
+        .find(|chunk| &chunk.id == b"RIFF")
+        .ok_or("not a RIFF file")?;
+    let sfbk = list_body(&riff, b"sfbk").ok_or("not a SoundFont")?;
+
+    let mut info = None;
+    let mut smpl = None;
+    let mut pdta = None;
+    for chunk in chunks(sfbk).filter(|chunk| &chunk.id == b"LIST") {
+        if let Some(body) = list_body(&chunk, b"INFO") {
+            info = Some(body);
+        } else if let Some(body) = list_body(&chunk, b"sdta") {
+            smpl = chunks(body)
+                .find(|chunk| &chunk.id == b"smpl")
+                .map(|chunk| chunk.data);
+        } else if let Some(body) = list_body(&chunk, b"pdta") {
+            pdta = Some(body);
+        }
+    }
+
+    let info = info.ok_or("missing INFO chunk")?;
+    let smpl = smpl.ok_or("missing smpl chunk")?;
+    let pdta = pdta.ok_or("missing pdta chunk")?;
+
+    let shdr = chunks(pdta)
+        .find(|chunk| &chunk.id == b"shdr")
+        .ok_or("missing shdr chunk")?
+        .data;
+
+    let mut samples: Vec<i16> = Vec::new();
+    let mut new_shdr = shdr.to_vec();
+
+    for record in new_shdr.chunks_exact_mut(SHDR_LEN) {
+        let field = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let (start, end) = (field(20) as usize, field(24) as usize);
+        let (loop_start, loop_end) = (field(28), field(32));
+        let kind = u16::from_le_bytes(record[44..46].try_into().unwrap());
+
+        // Terminal "EOS" record and ROM samples carry no data
+        if kind & ROM != 0 || end <= start {
+            record[20..36].fill(0);
+            continue;
+        }
+
+        let offset = samples.len() as u32;
+        let (loop_start, loop_end) = if kind & COMPRESSED != 0 {
+            let ogg = smpl.get(start..end).ok_or("sample out of bounds")?;
+            let decoded =
+                super::decode_audio(Box::new(std::io::Cursor::new(ogg.to_vec())), Some("ogg"))?;
+            samples.extend(
+                decoded.channels[0]
+                    .iter()
+                    .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+            );
+            (loop_start, loop_end)
+        } else {
+            // Uncompressed samples keep absolute 16-bit offsets
+            let pcm = smpl.get(start * 2..end * 2).ok_or("sample out of bounds")?;
+            samples.extend(
+                pcm.chunks_exact(2)
+                    .map(|s| i16::from_le_bytes([s[0], s[1]])),
+            );
+            (
+                loop_start.saturating_sub(start as u32),
+                loop_end.saturating_sub(start as u32),
+            )
+        };
+        let new_end = samples.len() as u32;
+        samples.extend(std::iter::repeat_n(0, SAMPLE_PADDING));
+
+        record[20..24].copy_from_slice(&offset.to_le_bytes());
+        record[24..28].copy_from_slice(&new_end.to_le_bytes());
+        record[28..32].copy_from_slice(&(offset + loop_start).min(new_end).to_le_bytes());
+        record[32..36].copy_from_slice(&(offset + loop_end).min(new_end).to_le_bytes());
+        record[44..46].copy_from_slice(&(kind & !COMPRESSED).to_le_bytes());
+    }
+
+    let mut new_info = Vec::new();
+    for chunk in chunks(info) {
+        if &chunk.id == b"ifil" {
+            write_chunk(&mut new_info, b"ifil", &[2, 0, 1, 0]);
+        } else {
+            write_chunk(&mut new_info, &chunk.id, chunk.data);
+        }
+    }
+
+    let smpl_bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut sdta = Vec::new();
+    write_chunk(&mut sdta, b"smpl", &smpl_bytes);
+
+    let mut new_pdta = Vec::new();
+    for chunk in chunks(pdta) {
+        if &chunk.id == b"shdr" {
+            write_chunk(&mut new_pdta, b"shdr", &new_shdr);
+        } else {
+            write_chunk(&mut new_pdta, &chunk.id, chunk.data);
+        }
+    }
+
+    let mut body = b"sfbk".to_vec();
+    write_list(&mut body, b"INFO", &new_info);
+    write_list(&mut body, b"sdta", &sdta);
+    write_list(&mut body, b"pdta", &new_pdta);
+
+    let mut out = Vec::with_capacity(body.len() + 8);
+    write_chunk(&mut out, b"RIFF", &body);
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn write_list(out: &mut Vec<u8>, form: &[u8; 4], data: &[u8]) {
+    let mut body = form.to_vec();
+    body.extend_from_slice(data);
+    write_chunk(out, b"LIST", &body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sfbk_header(list: &[u8]) -> Vec<u8> {
+        let mut header = b"RIFF\0\0\0\0sfbk".to_vec();
+        header.extend_from_slice(list);
+        header
+    }
+
+    #[test]
+    fn version() {
+        let mut info = Vec::new();
+        write_chunk(&mut info, b"ifil", &[3, 0, 1, 0]);
+        let mut list = Vec::new();
+        write_list(&mut list, b"INFO", &info);
+
+        assert_eq!(riff_version(&sfbk_header(&list)), Some(3));
+        // Cut off in the middle of the `ifil` chunk
+        assert_eq!(riff_version(&sfbk_header(&list[..22])), None);
+    }
+
+    #[test]
+    fn truncated_list() {
+        let mut list = b"LIST".to_vec();
+        list.extend_from_slice(&2u32.to_le_bytes());
+        list.extend_from_slice(b"INFO");
+
+        assert_eq!(riff_version(&sfbk_header(&list)), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! Minimal SFZ parser
+//!
+//! Supports `<control>`, `<global>`, `<master>`, `<group>` and `<region>` headers with opcode
+//! inheritance, `#define` and `#include`. Unknown opcodes are ignored.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopMode {
+    #[default]
+    NoLoop,
+    OneShot,
+    Continuous,
+    Sustain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Trigger {
+    #[default]
+    Attack,
+    Release,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub sample: PathBuf,
+    pub lokey: u8,
+    pub hikey: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    /// 1 based, like in the SFZ files
+    pub lochan: u8,
+    pub hichan: u8,
+    pub pitch_keycenter: u8,
+    /// Cents per key
+    pub pitch_keytrack: f32,
+    /// Cents
+    pub tune: f32,
+    /// Semitones
+    pub transpose: i32,
+    /// Decibels
+    pub volume: f32,
+    /// -100..=100
+    pub pan: f32,
+    /// Percent
+    pub amp_veltrack: f32,
+    pub ampeg_attack: f32,
+    pub ampeg_hold: f32,
+    pub ampeg_decay: f32,
+    /// Percent
+    pub ampeg_sustain: f32,
+    pub ampeg_release: f32,
+    pub offset: u32,
+    pub end: Option<u32>,
+    pub loop_mode: Option<LoopMode>,
+    pub loop_start: Option<u32>,
+    pub loop_end: Option<u32>,
+    pub trigger: Trigger,
+    pub lorand: f32,
+    pub hirand: f32,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self {
+            sample: PathBuf::new(),
+            lokey: 0,
+            hikey: 127,
+            lovel: 1,
+            hivel: 127,
+            lochan: 1,
+            hichan: 16,
+            pitch_keycenter: 60,
+            pitch_keytrack: 100.0,
+            tune: 0.0,
+            transpose: 0,
+            volume: 0.0,
+            pan: 0.0,
+            amp_veltrack: 100.0,
+            ampeg_attack: 0.0,
+            ampeg_hold: 0.0,
+            ampeg_decay: 0.0,
+            ampeg_sustain: 100.0,
+            ampeg_release: 0.001,
+            offset: 0,
+            end: None,
+            loop_mode: None,
+            loop_start: None,
+            loop_end: None,
+            trigger: Trigger::Attack,
+            lorand: 0.0,
+            hirand: 1.0,
+        }
+    }
+}
+
+impl Region {
+    pub fn matches(&self, channel: u8, key: u8, velocity: u8, rand: f32) -> bool {
+        (self.lokey..=self.hikey).contains(&key)
+            && (self.lovel..=self.hivel).contains(&velocity)
+            && (self.lochan..=self.hichan).contains(&(channel + 1))
+            && rand >= self.lorand
+            && rand < self.hirand
+    }
+
+    fn apply(&mut self, key: &str, value: &str, default_path: &Path) {
+        let float = || value.parse::<f32>().ok();
+        let int = || value.parse::<u32>().ok();
+
+        match key {
+            "sample" => {
+                self.sample = default_path.join(value.replace('\\', "/"));
+            }
+            "key" => {
+                if let Some(note) = parse_note(value) {
+                    self.lokey = note;
+                    self.hikey = note;
+                    self.pitch_keycenter = note;
+                }
+            }
+            "lokey" => self.lokey = parse_note(value).unwrap_or(self.lokey),
+            "hikey" => self.hikey = parse_note(value).unwrap_or(self.hikey),
+            "pitch_keycenter" => {
+                self.pitch_keycenter = parse_note(value).unwrap_or(self.pitch_keycenter)
+            }
+            "lovel" => self.lovel = value.parse().unwrap_or(self.lovel),
+            "hivel" => self.hivel = value.parse().unwrap_or(self.hivel),
+            "lochan" => self.lochan = value.parse().unwrap_or(self.lochan),
+            "hichan" => self.hichan = value.parse().unwrap_or(self.hichan),
+            "pitch_keytrack" => self.pitch_keytrack = float().unwrap_or(self.pitch_keytrack),
+            "tune" => self.tune = float().unwrap_or(self.tune),
+            "transpose" => self.transpose = value.parse().unwrap_or(self.transpose),
+            "volume" => self.volume = float().unwrap_or(self.volume),
+            "pan" => self.pan = float().unwrap_or(self.pan),
+            "amp_veltrack" => self.amp_veltrack = float().unwrap_or(self.amp_veltrack),
+            "ampeg_attack" => self.ampeg_attack = float().unwrap_or(self.ampeg_attack),
+            "ampeg_hold" => self.ampeg_hold = float().unwrap_or(self.ampeg_hold),
+            "ampeg_decay" => self.ampeg_decay = float().unwrap_or(self.ampeg_decay),
+            "ampeg_sustain" => self.ampeg_sustain = float().unwrap_or(self.ampeg_sustain),
+            "ampeg_release" => self.ampeg_release = float().unwrap_or(self.ampeg_release),
+            "offset" => self.offset = int().unwrap_or(self.offset),
+            "end" => self.end = int().or(self.end),
+            "loop_mode" | "loopmode" => {
+                self.loop_mode = match value {
+                    "no_loop" => Some(LoopMode::NoLoop),
+                    "one_shot" => Some(LoopMode::OneShot),
+                    "loop_continuous" => Some(LoopMode::Continuous),
+                    "loop_sustain" => Some(LoopMode::Sustain),
+                    _ => self.loop_mode,
+                }
+            }
+            "loop_start" | "loopstart" => self.loop_start = int().or(self.loop_start),
+            "loop_end" | "loopend" => self.loop_end = int().or(self.loop_end),
+            "trigger" => {
+                self.trigger = match value {
+                    "release" => Trigger::Release,
+                    _ => Trigger::Attack,
+                }
+            }
+            "lorand" => self.lorand = float().unwrap_or(self.lorand),
+            "hirand" => self.hirand = float().unwrap_or(self.hirand),
+            _ => {}
+        }
+    }
+}
+
+/// Parse a MIDI note number or a note name, `c4` is 60
+pub fn parse_note(value: &str) -> Option<u8> {
+    if let Ok(note) = value.parse::<u8>() {
+        return (note < 128).then_some(note);
+    }
+
+    let value = value.to_lowercase();
+    let mut chars = value.chars().peekable();
+    let base: i32 = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let accidental = match chars.peek() {
+        Some('#') => 1,
+        Some('b') => -1,
+        _ => 0,
+    };
+    if accidental != 0 {
+        chars.next();
+    }
+    let octave: i32 = chars.collect::<String>().parse().ok()?;
+
+    let note = (octave + 1) * 12 + base + accidental;
+    u8::try_from(note).ok().filter(|note| *note < 128)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Header {
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+}
+
+/// Parse an SFZ file, sample paths are resolved relative to it
+pub fn load(path: &Path) -> std::io::Result<Vec<Region>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut defines = HashMap::new();
+    let source = preprocess(path, &mut defines, 0)?;
+    Ok(parse(&source, base))
+}
+
+fn preprocess(
+    path: &Path,
+    defines: &mut HashMap<String, String>,
+    depth: usize,
+) -> std::io::Result<String> {
+    let text = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut out = String::new();
+    for line in text.lines() {
+        let line = strip_comments(line);
+        let trimmed = line.trim();
+
+        if let Some(rest) = trimmed.strip_prefix("#define") {
+            let mut parts = rest.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                defines.insert(name.to_string(), value.to_string());
+            }
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("#include") {
+            let file = rest.trim().trim_matches('"');
+            if depth < 16 {
+                match preprocess(&dir.join(file), defines, depth + 1) {
+                    Ok(included) => out.push_str(&included),
+                    Err(err) => log::warn!("sfz include {file}: {err}"),
+                }
+            }
+            continue;
+        }
+
+        let mut line = line.to_string();
+        // Longest names first, so `$KEY` doesn't clobber `$KEYS`
+        let mut names: Vec<_> = defines.keys().cloned().collect();
+        names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+        for name in names {
+            if line.contains(&name) {
+                line = line.replace(&name, &defines[&name]);
+            }
+        }
+
+        out.push_str(&line);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+fn strip_comments(line: &str) -> &str {
+    match line.find("//") {
+        Some(at) => &line[..at],
+        None => line,
+    }
+}
+
+fn parse(source: &str, base: &Path) -> Vec<Region> {
+    let mut regions = Vec::new();
+
+    let mut default_path = base.to_path_buf();
+    let mut global = Region::default();
+    let mut master = Region::default();
+    let mut group = Region::default();
+    let mut current: Option<Region> = None;
+    let mut header = Header::Control;
+
+    for line in source.lines() {
+        let mut rest = strip_comments(line).trim();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('<') {
+                let Some(close) = after.find('>') else {
+                    break;
+                };
+                let name = &after[..close];
+                rest = after[close + 1..].trim_start();
+
+                flush(&mut current, &mut regions);
+                header = match name {
+                    "control" => Header::Control,
+                    "global" => {
+                        global = Region::default();
+                        master = global.clone();
+                        group = global.clone();
+                        Header::Global
+                    }
+                    "master" => {
+                        master = global.clone();
+                        group = master.clone();
+                        Header::Master
+                    }
+                    "group" => {
+                        group = master.clone();
+                        Header::Group
+                    }
+                    "region" => {
+                        current = Some(group.clone());
+                        Header::Region
+                    }
+                    // Unsupported headers (`<curve>`, `<effect>`, ...) swallow their opcodes
+                    _ => Header::Control,
+                };
+                continue;
+            }
+
+            let Some(eq) = rest.find('=') else {
+                break;
+            };
+            let key = rest[..eq].trim();
+            let (value, next) = split_value(&rest[eq + 1..]);
+            rest = next;
+
+            match header {
+                Header::Control => {
+                    if key == "default_path" {
+                        default_path = base.join(value.replace('\\', "/"));
+                    }
+                }
+                Header::Global => {
+                    global.apply(key, value, &default_path);
+                    master.apply(key, value, &default_path);
+                    group.apply(key, value, &default_path);
+                }
+                Header::Master => {
+                    master.apply(key, value, &default_path);
+                    group.apply(key, value, &default_path);
+                }
+                Header::Group => group.apply(key, value, &default_path),
+                Header::Region => {
+                    if let Some(region) = current.as_mut() {
+                        region.apply(key, value, &default_path);
+                    }
+                }
+            }
+        }
+    }
+
+    flush(&mut current, &mut regions);
+    regions
+}
+
+fn flush(current: &mut Option<Region>, regions: &mut Vec<Region>) {
+    if let Some(region) = current.take()
+        && !region.sample.as_os_str().is_empty()
+    {
+        regions.push(region);
+    }
+}
+
+/// Opcode values may contain spaces (sample paths), a value ends at the next header or `key=`
+fn split_value(input: &str) -> (&str, &str) {
+    let mut end = input.len();
+
+    if let Some(at) = input.find('<') {
+        end = at;
+    }
+
+    let mut word_start = None;
+    for (i, c) in input[..end].char_indices() {
+        if c.is_whitespace() {
+            word_start = Some(i);
+        } else if c == '=' {
+            if let Some(start) = word_start {
+                end = start;
+            }
+            break;
+        }
+    }
+
+    (input[..end].trim(), input[end..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_names() {
+        assert_eq!(parse_note("c4"), Some(60));
+        assert_eq!(parse_note("C#4"), Some(61));
+        assert_eq!(parse_note("eb3"), Some(51));
+        assert_eq!(parse_note("a0"), Some(21));
+        assert_eq!(parse_note("72"), Some(72));
+        assert_eq!(parse_note("h2"), None);
+    }
+
+    #[test]
+    fn inheritance_and_values_with_spaces() {
+        let source = "
+            <control> default_path=samples/
+            <global> volume=-6 // comment
+            <group> lokey=c4 hikey=b4 ampeg_release=0.5
+            <region> sample=Grand Piano C4.wav pitch_keycenter=c4
+            <region> sample=Grand Piano D4.wav key=62 loopmode=loop_continuous
+            <group> trigger=release
+            <region> sample=release.wav
+            <region> volume=0
+        ";
+        let regions = parse(source, Path::new("/sfz"));
+        assert_eq!(regions.len(), 3);
+
+        assert_eq!(
+            regions[0].sample,
+            Path::new("/sfz/samples/Grand Piano C4.wav")
+        );
+        assert_eq!((regions[0].lokey, regions[0].hikey), (60, 71));
+        assert_eq!(regions[0].volume, -6.0);
+        assert_eq!(regions[0].ampeg_release, 0.5);
+
+        assert_eq!((regions[1].lokey, regions[1].hikey), (62, 62));
+        assert_eq!(regions[1].loop_mode, Some(LoopMode::Continuous));
+
+        assert_eq!(regions[2].trigger, Trigger::Release);
+        assert_eq!(regions[2].lokey, 0);
+        assert_eq!(regions[2].volume, -6.0);
+    }
+}
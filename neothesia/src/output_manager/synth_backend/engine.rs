@@ -0,0 +1,214 @@
+use std::{collections::HashMap, error::Error, path::PathBuf};
+
+use midi_file::midly::MidiMessage;
+use neothesia_core::config::{ChorusConfigV1, InterpolationV1, ReverbConfigV1};
+
+use super::SynthEvent;
+use crate::output_manager::soundfont::{SfzLayer, SoundFontFile, SoundFontFormat};
+
+/// Synthesizer that renders the audio of a synth connection.
+///
+/// Engines only translate calls to their own API, bank and drum tracking,
+/// per-channel fonts, SFZ instruments and event timing are shared in [`SynthRenderer`].
+pub trait SynthEngine {
+    /// Handle of a loaded SoundFont
+    type FontId: Copy;
+
+    /// Load a SF2 or SF3 file on top of the already loaded ones
+    fn load_font(&mut self, font: &SoundFontFile) -> Result<Self::FontId, Box<dyn Error>>;
+
+    fn set_gain(&mut self, gain: f32);
+    fn set_reverb(&mut self, reverb: &ReverbConfigV1);
+    fn set_chorus(&mut self, chorus: &ChorusConfigV1);
+    fn set_polyphony(&mut self, polyphony: u16);
+    fn set_interpolation(&mut self, interpolation: InterpolationV1);
+    fn system_reset(&mut self);
+
+    fn select_bank(&mut self, channel: u8, bank: u32);
+    /// Select a preset of a specific font, returns `false` if the font lacks it
+    fn select_program(&mut self, channel: u8, font: Self::FontId, bank: u32, program: u8) -> bool;
+
+    /// Channel voice and channel mode messages, including all notes/sound off controllers
+    fn midi_message(&mut self, channel: u8, message: MidiMessage);
+
+    /// Render interleaved stereo frames
+    fn write(&mut self, out: &mut [f32]);
+}
+
+/// SoundFont bank that holds drum kits
+const DRUM_BANK: u32 = 128;
+
+/// Tracks which channels play drum kits, and which bank melodic channels selected
+struct ChannelBanks {
+    drums: [bool; 16],
+    bank_msb: [u32; 16],
+}
+
+impl ChannelBanks {
+    fn new() -> Self {
+        Self {
+            drums: std::array::from_fn(|ch| {
+                ch as u8 == midi_file::program_track::DEFAULT_DRUM_CHANNEL
+            }),
+            bank_msb: [0; 16],
+        }
+    }
+
+    fn set_drum_channel(&mut self, channel: u8, is_drum: bool) {
+        if let Some(drum) = self.drums.get_mut(channel as usize) {
+            *drum = is_drum;
+        }
+    }
+
+    fn on_control_change(&mut self, channel: u8, ctrl: u8, value: u8) {
+        if ctrl == 0
+            && let Some(bank) = self.bank_msb.get_mut(channel as usize)
+        {
+            *bank = value as u32;
+        }
+    }
+
+    /// Bank that should be selected before a program change
+    fn bank(&self, channel: u8) -> u32 {
+        let channel = channel as usize % 16;
+        if self.drums[channel] {
+            DRUM_BANK
+        } else {
+            self.bank_msb[channel]
+        }
+    }
+}
+
+/// Drives a [`SynthEngine`] from the events of a synth connection
+pub struct SynthRenderer<E: SynthEngine> {
+    engine: E,
+    sfz: SfzLayer,
+    banks: ChannelBanks,
+
+    font_ids: HashMap<PathBuf, E::FontId>,
+    /// Fonts that channels take their presets from, instead of searching the whole stack
+    channel_fonts: [Option<E::FontId>; 16],
+}
+
+impl<E: SynthEngine> SynthRenderer<E> {
+    pub fn new(mut engine: E, fonts: &[SoundFontFile], sample_rate: f32, gain: f32) -> Self {
+        let mut font_ids = HashMap::new();
+        for font in fonts
+            .iter()
+            .filter(|font| font.format != SoundFontFormat::Sfz)
+        {
+            match engine.load_font(font) {
+                Ok(id) => {
+                    font_ids.insert(font.path.clone(), id);
+                }
+                Err(err) => log::error!("Failed to load SoundFont {:?}: {err}", font.path),
+            }
+        }
+
+        engine.set_gain(gain);
+        engine.set_reverb(&ReverbConfigV1::default());
+        engine.set_chorus(&ChorusConfigV1::default());
+
+        let mut sfz = SfzLayer::load(fonts, sample_rate);
+        sfz.set_gain(gain);
+
+        let mut renderer = Self {
+            engine,
+            sfz,
+            banks: ChannelBanks::new(),
+
+            font_ids,
+            channel_fonts: [None; 16],
+        };
+        renderer.select_default_drums();
+        renderer
+    }
+
+    /// Channel 10 is a drum kit by default, the engine doesn't know about it as drums are managed by `ChannelBanks`
+    fn select_default_drums(&mut self) {
+        let channel = midi_file::program_track::DEFAULT_DRUM_CHANNEL;
+        self.engine.select_bank(channel, self.banks.bank(channel));
+        self.engine
+            .midi_message(channel, MidiMessage::ProgramChange { program: 0.into() });
+    }
+
+    /// Render a block of interleaved stereo frames, applying every event at its frame offset
+    pub fn render(&mut self, out: &mut [f32], events: &mut Vec<(usize, SynthEvent)>) {
+        let mut frame = 0;
+        for (offset, event) in events.drain(..) {
+            if offset > frame {
+                self.write(&mut out[frame * 2..offset * 2]);
+                frame = offset;
+            }
+            self.handle_event(event);
+        }
+
+        self.write(&mut out[frame * 2..]);
+    }
+
+    fn write(&mut self, out: &mut [f32]) {
+        self.engine.write(out);
+        self.sfz.write(out);
+    }
+
+    fn handle_event(&mut self, event: SynthEvent) {
+        match event {
+            SynthEvent::SetGain(gain) => {
+                self.engine.set_gain(gain);
+                self.sfz.set_gain(gain);
+            }
+            SynthEvent::SetReverb(reverb) => self.engine.set_reverb(&reverb),
+            SynthEvent::SetChorus(chorus) => self.engine.set_chorus(&chorus),
+            SynthEvent::SetPolyphony(polyphony) => self.engine.set_polyphony(polyphony),
+            SynthEvent::SetInterpolation(interpolation) => {
+                self.engine.set_interpolation(interpolation);
+            }
+            SynthEvent::SetDrumChannel { channel, is_drum } => {
+                self.banks.set_drum_channel(channel, is_drum);
+            }
+            SynthEvent::SetChannelFont { channel, font } => {
+                self.sfz.set_channel_font(channel, font.as_deref());
+                if let Some(slot) = self.channel_fonts.get_mut(channel as usize) {
+                    *slot = font.and_then(|font| self.font_ids.get(&font).copied());
+                }
+            }
+            SynthEvent::Reset => {
+                self.engine.system_reset();
+                self.sfz.reset();
+                self.banks = ChannelBanks::new();
+                self.select_default_drums();
+            }
+            SynthEvent::Midi { channel, message } => self.midi_message(channel, message),
+            // Consumed by the stream callback before it gets here
+            SynthEvent::SetCapture(_) => {}
+        }
+    }
+
+    fn midi_message(&mut self, channel: u8, message: MidiMessage) {
+        if self.sfz.midi_event(channel, message) {
+            return;
+        }
+
+        match message {
+            MidiMessage::Controller { controller, value } => {
+                self.banks
+                    .on_control_change(channel, controller.as_int(), value.as_int());
+            }
+            MidiMessage::ProgramChange { program } => {
+                let bank = self.banks.bank(channel);
+                // Fall back to the whole stack, if the font lacks the preset
+                let font = self.channel_fonts.get(channel as usize).copied().flatten();
+                if font.is_some_and(|font| {
+                    self.engine
+                        .select_program(channel, font, bank, program.as_int())
+                }) {
+                    return;
+                }
+                self.engine.select_bank(channel, bank);
+            }
+            _ => {}
+        }
+
+        self.engine.midi_message(channel, message);
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+
+use fluidlite::{IsSettings, Settings};
+use midi_file::midly::MidiMessage;
+use neothesia_core::config::{ChorusConfigV1, InterpolationV1, ReverbConfigV1};
+
+use super::engine::SynthEngine;
+use crate::output_manager::soundfont::SoundFontFile;
+
+pub struct FluidSynthEngine {
+    synth: fluidlite::Synth,
+}
+
+impl FluidSynthEngine {
+    pub fn new(sample_rate: f32) -> Self {
+        let settings = Settings::new().unwrap();
+
+        let rate = settings.pick::<_, f64>("synth.sample-rate").unwrap();
+        rate.set(sample_rate as f64);
+
+        Self {
+            synth: fluidlite::Synth::new(settings).unwrap(),
+        }
+    }
+}
+
+impl SynthEngine for FluidSynthEngine {
+    type FontId = u32;
+
+    fn load_font(&mut self, font: &SoundFontFile) -> Result<Self::FontId, Box<dyn Error>> {
+        self.synth
+            .sfload(font.sf2_path()?, true)
+            .map_err(|err| format!("{err:?}").into())
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.synth.set_gain(gain);
+    }
+
+    fn set_reverb(&mut self, reverb: &ReverbConfigV1) {
+        self.synth.set_reverb_on(reverb.level > 0.0);
+        self.synth.set_reverb_params(
+            reverb.room_size as f64,
+            reverb.damp as f64,
+            reverb.width as f64,
+            reverb.level as f64,
+        );
+    }
+
+    fn set_chorus(&mut self, chorus: &ChorusConfigV1) {
+        self.synth.set_chorus_on(chorus.level > 0.0);
+        // `ChorusMode` is not exported, its default is the sine wave
+        self.synth.set_chorus_params(
+            chorus.voices,
+            chorus.level as f64,
+            chorus.speed as f64,
+            chorus.depth as f64,
+            Default::default(),
+        );
+    }
+
+    fn set_polyphony(&mut self, polyphony: u16) {
+        self.synth.set_polyphony(polyphony as u32).ok();
+    }
+
+    fn set_interpolation(&mut self, interpolation: InterpolationV1) {
+        // fluidlite doesn't export `InterpMethod`, only its fourth order default can be selected
+        if interpolation != InterpolationV1::FourthOrder {
+            log::warn!(
+                "{interpolation:?} interpolation is not supported by the fluidlite engine, using fourth order"
+            );
+        }
+        self.synth.set_interp_method(None, Default::default()).ok();
+    }
+
+    fn system_reset(&mut self) {
+        self.synth.system_reset().ok();
+    }
+
+    fn select_bank(&mut self, channel: u8, bank: u32) {
+        self.synth.bank_select(channel as u32, bank).ok();
+    }
+
+    fn select_program(&mut self, channel: u8, font: Self::FontId, bank: u32, program: u8) -> bool {
+        self.synth
+            .program_select(channel as u32, font, bank, program as u32)
+            .is_ok()
+    }
+
+    fn midi_message(&mut self, channel: u8, message: MidiMessage) {
+        let channel = channel as u32;
+        // Channel mode controllers (all notes/sound off) are handled by fluidsynth's `cc`
+        let res = match message {
+            MidiMessage::NoteOff { key, .. } => self.synth.note_off(channel, key.as_int() as u32),
+            MidiMessage::NoteOn { key, vel } => {
+                self.synth
+                    .note_on(channel, key.as_int() as u32, vel.as_int() as u32)
+            }
+            MidiMessage::Aftertouch { key, vel } => {
+                self.synth
+                    .key_pressure(channel, key.as_int() as u32, vel.as_int() as u32)
+            }
+            MidiMessage::Controller { controller, value } => {
+                self.synth
+                    .cc(channel, controller.as_int() as u32, value.as_int() as u32)
+            }
+            MidiMessage::ProgramChange { program } => {
+                self.synth.program_change(channel, program.as_int() as u32)
+            }
+            MidiMessage::ChannelAftertouch { vel } => {
+                self.synth.channel_pressure(channel, vel.as_int() as u32)
+            }
+            MidiMessage::PitchBend { bend } => {
+                self.synth.pitch_bend(channel, bend.0.as_int() as u32)
+            }
+        };
+        res.ok();
+    }
+
+    fn write(&mut self, out: &mut [f32]) {
+        self.synth.write(out).ok();
+    }
+}
@@ -0,0 +1,625 @@
+mod engine;
+#[cfg(all(feature = "fluid-synth", not(feature = "oxi-synth")))]
+mod fluid;
+#[cfg(all(feature = "oxi-synth", not(feature = "fluid-synth")))]
+mod oxi;
+
+use std::{
+    cell::RefCell,
+    error::Error,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::Receiver,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::output_manager::{
+    OutputDescriptor, SynthLatency, audio_recorder::AudioCapture, soundfont::SoundFontFile,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use engine::SynthRenderer;
+use midi_file::{
+    midly::{self, num::u4},
+    program_track::PartMode,
+};
+use neothesia_core::config::{ChorusConfigV1, InterpolationV1, ReverbConfigV1, SynthConfigV1};
+
+pub struct SynthBackend {
+    host: cpal::Host,
+    device: cpal::Device,
+    device_name: String,
+    /// Host and device names as requested, before falling back to the defaults
+    requested: (Option<String>, Option<String>),
+
+    stream_config: cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    default_sample_rate: cpal::SampleRate,
+    supported_buffer_size: cpal::SupportedBufferSize,
+    gain: f32,
+
+    /// Set by the stream error callback, when the device went away
+    device_lost: Arc<AtomicBool>,
+}
+
+impl SynthBackend {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::with_device(None, None)
+    }
+
+    /// Open a specific output device, falling back to the defaults if it can't be found
+    pub fn with_device(host: Option<&str>, device: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let host_name = host.map(str::to_string);
+        let device_name = device.map(str::to_string);
+
+        let host = find_host(host);
+        let device = find_device(&host, device).ok_or("failed to find an output device")?;
+
+        let config = device.default_output_config()?;
+        let sample_format = config.sample_format();
+        let supported_buffer_size = *config.buffer_size();
+
+        let stream_config: cpal::StreamConfig = config.into();
+        let default_sample_rate = stream_config.sample_rate;
+
+        Ok(Self {
+            host,
+            device_name: device.to_string(),
+            device,
+            requested: (host_name, device_name),
+
+            stream_config,
+            sample_format,
+            default_sample_rate,
+            supported_buffer_size,
+            gain: 0.2,
+
+            device_lost: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Names of the audio hosts available on this system
+    pub fn hosts() -> Vec<String> {
+        cpal::available_hosts()
+            .into_iter()
+            .map(|id| id.name().to_string())
+            .collect()
+    }
+
+    /// Names of the output devices of a host, `None` lists the devices of the default host
+    pub fn output_devices(host: Option<&str>) -> Vec<String> {
+        let host = find_host(host);
+        match host.output_devices() {
+            Ok(devices) => devices.map(|device| device.to_string()).collect(),
+            Err(err) => {
+                log::error!("Failed to list output devices: {err}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Switch streams opened from now on to another device, `None` selects the defaults.
+    ///
+    /// The sample rate and buffer size go back to the device defaults.
+    /// Returns `true` if the device changed, open connections have to be reopened to pick it up.
+    pub fn select_device(&mut self, host: Option<&str>, device: Option<&str>) -> bool {
+        let requested = (host.map(str::to_string), device.map(str::to_string));
+        if self.requested == requested {
+            return false;
+        }
+        self.open_device(host, device)
+    }
+
+    /// Look up the requested device again, after it went away.
+    ///
+    /// Falls back to the default device if the requested one is still missing,
+    /// it's only looked up again when the fallback goes away too or the settings change.
+    pub fn reopen_device(&mut self) -> bool {
+        let (host, device) = self.requested.clone();
+        self.open_device(host.as_deref(), device.as_deref())
+    }
+
+    fn open_device(&mut self, host: Option<&str>, device: Option<&str>) -> bool {
+        match Self::with_device(host, device) {
+            Ok(backend) => {
+                log::info!(
+                    "Audio output: {} ({})",
+                    backend.device_name,
+                    backend.host.id().name()
+                );
+                *self = Self {
+                    gain: self.gain,
+                    ..backend
+                };
+                true
+            }
+            Err(err) => {
+                log::error!("Failed to open audio device: {err}");
+                false
+            }
+        }
+    }
+
+    /// Check if the device of the open streams went away, clearing the flag
+    pub fn take_device_lost(&self) -> bool {
+        self.device_lost.swap(false, Ordering::Relaxed)
+    }
+
+    /// Select the sample rate of streams opened from now on, `None` goes back to the device default.
+    ///
+    /// Returns `true` if the rate changed, open connections have to be reopened to pick it up.
+    pub fn set_sample_rate(&mut self, sample_rate: Option<cpal::SampleRate>) -> bool {
+        let rate = match sample_rate {
+            Some(rate) if self.supports_sample_rate(rate) => rate,
+            Some(rate) => {
+                log::warn!("Sample rate {rate} is not supported by the output device");
+                self.default_sample_rate
+            }
+            None => self.default_sample_rate,
+        };
+
+        let changed = self.stream_config.sample_rate != rate;
+        self.stream_config.sample_rate = rate;
+        changed
+    }
+
+    /// Select the number of frames rendered per callback, `None` lets the device decide.
+    ///
+    /// Returns `true` if the size changed, open connections have to be reopened to pick it up.
+    pub fn set_buffer_size(&mut self, buffer_size: Option<u32>) -> bool {
+        let size = match (buffer_size, self.supported_buffer_size) {
+            (Some(size), cpal::SupportedBufferSize::Range { min, max }) => {
+                cpal::BufferSize::Fixed(size.clamp(min, max))
+            }
+            (Some(size), cpal::SupportedBufferSize::Unknown) => cpal::BufferSize::Fixed(size),
+            (None, _) => cpal::BufferSize::Default,
+        };
+
+        let changed = self.stream_config.buffer_size != size;
+        self.stream_config.buffer_size = size;
+        changed
+    }
+
+    fn supports_sample_rate(&self, rate: cpal::SampleRate) -> bool {
+        let Ok(mut configs) = self.device.supported_output_configs() else {
+            return false;
+        };
+
+        configs.any(|config| {
+            config.sample_format() == self.sample_format
+                && config.channels() == self.stream_config.channels
+                && config.try_with_sample_rate(rate).is_some()
+        })
+    }
+
+    fn run<T: cpal::SizedSample + cpal::FromSample<f32>>(
+        &self,
+        rx: Receiver<(Instant, SynthEvent)>,
+        fonts: &[SoundFontFile],
+        monitor: Arc<LatencyMonitor>,
+    ) -> Result<cpal::Stream, Box<dyn Error>> {
+        let sample_rate = self.stream_config.sample_rate as f64;
+
+        #[cfg(all(feature = "fluid-synth", not(feature = "oxi-synth")))]
+        let engine = fluid::FluidSynthEngine::new(sample_rate as f32);
+
+        #[cfg(all(feature = "oxi-synth", not(feature = "fluid-synth")))]
+        let engine = oxi::OxiSynthEngine::new(sample_rate as f32, self.gain);
+
+        let mut renderer = SynthRenderer::new(engine, fonts, sample_rate as f32, self.gain);
+
+        let device_lost = self.device_lost.clone();
+        let err_fn = move |err: cpal::Error| {
+            log::error!("an error occurred on stream: {err}");
+            if matches!(
+                err.kind(),
+                cpal::ErrorKind::DeviceNotAvailable
+                    | cpal::ErrorKind::HostUnavailable
+                    | cpal::ErrorKind::StreamInvalidated
+            ) {
+                device_lost.store(true, Ordering::Relaxed);
+            }
+        };
+
+        let channels = self.stream_config.channels as usize;
+
+        let mut block: Vec<f32> = Vec::new();
+        let mut events: Vec<(usize, SynthEvent)> = Vec::new();
+        let mut last_callback = Instant::now();
+        let mut capture: Option<AudioCapture> = None;
+
+        let stream = self.device.build_output_stream(
+            self.stream_config,
+            move |output: &mut [T], info: &cpal::OutputCallbackInfo| {
+                let now = Instant::now();
+                let frames = output.len() / channels;
+                let timestamp = info.timestamp();
+                let output_latency = timestamp.playback.duration_since(timestamp.callback);
+
+                // Events keep their position within the period they arrived in,
+                // trading one period of latency for sample accurate timing without jitter
+                while let Ok((sent, event)) = rx.try_recv() {
+                    if let SynthEvent::SetCapture(new) = event {
+                        if let Some(old) = std::mem::replace(&mut capture, new) {
+                            old.release();
+                        }
+                        continue;
+                    }
+
+                    let since = sent.saturating_duration_since(last_callback);
+                    let offset = ((since.as_secs_f64() * sample_rate) as usize)
+                        .min(frames.saturating_sub(1));
+
+                    monitor.record_event(
+                        now.saturating_duration_since(sent)
+                            + output_latency
+                            + Duration::from_secs_f64(offset as f64 / sample_rate),
+                    );
+                    events.push((offset, event));
+                }
+                last_callback = now;
+                monitor.record_block(frames, output_latency);
+
+                block.clear();
+                block.resize(frames * 2, 0.0);
+                renderer.render(&mut block, &mut events);
+
+                if let Some(capture) = capture.as_mut() {
+                    capture.push(&block);
+                }
+
+                for (frame, stereo) in output.chunks_mut(channels).zip(block.chunks_exact(2)) {
+                    for (id, sample) in frame.iter_mut().enumerate() {
+                        *sample = T::from_sample(stereo[id % 2]);
+                    }
+                }
+            },
+            err_fn,
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(stream)
+    }
+
+    /// Open a synth with a stack of SoundFonts, lowest priority first
+    pub fn new_output_connection(
+        &mut self,
+        fonts: &[SoundFontFile],
+    ) -> Result<SynthOutputConnection, Box<dyn Error>> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let monitor = Arc::new(LatencyMonitor::default());
+        let m = monitor.clone();
+        let stream = match self.sample_format {
+            cpal::SampleFormat::I8 => self.run::<i8>(rx, fonts, m),
+            cpal::SampleFormat::I16 => self.run::<i16>(rx, fonts, m),
+            cpal::SampleFormat::I32 => self.run::<i32>(rx, fonts, m),
+            cpal::SampleFormat::I64 => self.run::<i64>(rx, fonts, m),
+
+            cpal::SampleFormat::U8 => self.run::<u8>(rx, fonts, m),
+            cpal::SampleFormat::U16 => self.run::<u16>(rx, fonts, m),
+            cpal::SampleFormat::U32 => self.run::<u32>(rx, fonts, m),
+            cpal::SampleFormat::U64 => self.run::<u64>(rx, fonts, m),
+
+            cpal::SampleFormat::F32 => self.run::<f32>(rx, fonts, m),
+            cpal::SampleFormat::F64 => self.run::<f64>(rx, fonts, m),
+            sample_format => {
+                return Err(format!("Unsupported sample format '{sample_format}'").into());
+            }
+        }?;
+
+        Ok(SynthOutputConnection {
+            stream: Rc::new(RefCell::new(SynthStream {
+                stream: Some(stream),
+                tx,
+                monitor,
+                sample_rate: self.stream_config.sample_rate,
+            })),
+            settings: Rc::default(),
+        })
+    }
+
+    pub fn get_outputs(&self) -> Vec<OutputDescriptor> {
+        vec![OutputDescriptor::Synth(Vec::new())]
+    }
+}
+
+fn find_host(name: Option<&str>) -> cpal::Host {
+    let Some(name) = name else {
+        return cpal::default_host();
+    };
+
+    let host = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name() == name)
+        .map(cpal::host_from_id);
+
+    match host {
+        Some(Ok(host)) => host,
+        _ => {
+            log::warn!("Audio host {name:?} is not available, using the default one");
+            cpal::default_host()
+        }
+    }
+}
+
+fn find_device(host: &cpal::Host, name: Option<&str>) -> Option<cpal::Device> {
+    if let Some(name) = name {
+        let device = host
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|device| device.to_string() == name));
+        if device.is_some() {
+            return device;
+        }
+        log::warn!("Audio device {name:?} is not available, using the default one");
+    }
+
+    host.default_output_device()
+}
+
+/// Channel mode messages, every engine is expected to handle them
+const ALL_SOUND_OFF: u8 = 120;
+const ALL_NOTES_OFF: u8 = 123;
+
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+
+enum SynthEvent {
+    SetGain(f32),
+    SetReverb(ReverbConfigV1),
+    SetChorus(ChorusConfigV1),
+    SetPolyphony(u16),
+    SetInterpolation(InterpolationV1),
+    SetDrumChannel {
+        channel: u8,
+        is_drum: bool,
+    },
+    SetChannelFont {
+        channel: u8,
+        font: Option<PathBuf>,
+    },
+    /// GM/GS/XG system reset
+    Reset,
+    /// Copy the rendered audio to a recording, handled by the stream callback
+    SetCapture(Option<AudioCapture>),
+    Midi {
+        channel: u8,
+        message: midly::MidiMessage,
+    },
+}
+
+/// Audio timings measured in the stream callback, in microseconds
+#[derive(Default)]
+struct LatencyMonitor {
+    buffer_frames: AtomicU32,
+    output_latency: AtomicU32,
+    /// Smoothed time from sending an event until it is heard
+    event_latency: AtomicU32,
+}
+
+impl LatencyMonitor {
+    fn record_block(&self, frames: usize, output_latency: Duration) {
+        self.buffer_frames.store(frames as u32, Ordering::Relaxed);
+        self.output_latency
+            .store(output_latency.as_micros() as u32, Ordering::Relaxed);
+    }
+
+    fn record_event(&self, latency: Duration) {
+        let latency = latency.as_micros() as u32;
+        let old = self.event_latency.load(Ordering::Relaxed);
+        let new = if old == 0 {
+            latency
+        } else {
+            (old as u64 * 7 / 8 + latency as u64 / 8) as u32
+        };
+        self.event_latency.store(new, Ordering::Relaxed);
+    }
+}
+
+struct SynthStream {
+    stream: Option<cpal::Stream>,
+    tx: std::sync::mpsc::Sender<(Instant, SynthEvent)>,
+    monitor: Arc<LatencyMonitor>,
+    sample_rate: u32,
+}
+
+/// State sent to a synth, replayed when its stream gets reopened
+#[derive(Default)]
+struct SynthSettings {
+    config: Option<SynthConfigV1>,
+    channel_fonts: [Option<PathBuf>; 16],
+    drum_channels: [Option<bool>; 16],
+    /// Bank select MSB and LSB of each channel
+    banks: [[Option<u8>; 2]; 16],
+    programs: [Option<u8>; 16],
+}
+
+/// Handle of a synth audio stream.
+///
+/// The stream can be swapped out underneath, so that every handle follows when it gets reopened.
+#[derive(Clone)]
+pub struct SynthOutputConnection {
+    stream: Rc<RefCell<SynthStream>>,
+    settings: Rc<RefCell<SynthSettings>>,
+}
+
+impl SynthOutputConnection {
+    /// Check if both handles point to the same audio stream
+    pub fn same_connection(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.stream, &other.stream)
+    }
+
+    /// Stop the audio stream, so that the device is free to be opened again
+    pub fn close_stream(&self) {
+        self.stream.borrow_mut().stream = None;
+    }
+
+    /// Take over the audio stream of a freshly opened connection, bringing it to the state of the old one
+    pub fn replace_stream(&self, other: Self) {
+        std::mem::swap(
+            &mut *self.stream.borrow_mut(),
+            &mut *other.stream.borrow_mut(),
+        );
+
+        let settings = self.settings.borrow();
+        if let Some(config) = settings.config.as_ref() {
+            self.send_config(config);
+        }
+        for channel in 0..16u8 {
+            let id = channel as usize;
+            if let Some(is_drum) = settings.drum_channels[id] {
+                self.send(SynthEvent::SetDrumChannel { channel, is_drum });
+            }
+            if let Some(font) = settings.channel_fonts[id].clone() {
+                self.send(SynthEvent::SetChannelFont {
+                    channel,
+                    font: Some(font),
+                });
+            }
+            for (controller, value) in [BANK_SELECT_MSB, BANK_SELECT_LSB]
+                .into_iter()
+                .zip(settings.banks[id])
+            {
+                if let Some(value) = value {
+                    self.send(SynthEvent::Midi {
+                        channel,
+                        message: midly::MidiMessage::Controller {
+                            controller: controller.into(),
+                            value: value.into(),
+                        },
+                    });
+                }
+            }
+            if let Some(program) = settings.programs[id] {
+                self.send(SynthEvent::Midi {
+                    channel,
+                    message: midly::MidiMessage::ProgramChange {
+                        program: program.into(),
+                    },
+                });
+            }
+        }
+    }
+
+    fn send(&self, event: SynthEvent) {
+        self.stream.borrow().tx.send((Instant::now(), event)).ok();
+    }
+
+    pub fn midi_event(&self, channel: u4, message: midly::MidiMessage) {
+        {
+            let mut settings = self.settings.borrow_mut();
+            let id = channel.as_int() as usize;
+            match message {
+                midly::MidiMessage::Controller { controller, value } => match controller.as_int() {
+                    BANK_SELECT_MSB => settings.banks[id][0] = Some(value.as_int()),
+                    BANK_SELECT_LSB => settings.banks[id][1] = Some(value.as_int()),
+                    _ => {}
+                },
+                midly::MidiMessage::ProgramChange { program } => {
+                    settings.programs[id] = Some(program.as_int());
+                }
+                _ => {}
+            }
+        }
+
+        self.send(SynthEvent::Midi {
+            channel: channel.as_int(),
+            message,
+        });
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.stream.borrow().sample_rate
+    }
+
+    /// Copy the audio of this connection to a recording, `None` stops copying
+    pub fn set_capture(&self, capture: Option<AudioCapture>) {
+        self.send(SynthEvent::SetCapture(capture));
+    }
+
+    pub fn latency(&self) -> SynthLatency {
+        let micros =
+            |value: &AtomicU32| Duration::from_micros(value.load(Ordering::Relaxed) as u64);
+        let stream = self.stream.borrow();
+        SynthLatency {
+            sample_rate: stream.sample_rate,
+            buffer_frames: stream.monitor.buffer_frames.load(Ordering::Relaxed),
+            output_latency: micros(&stream.monitor.output_latency),
+            event_latency: micros(&stream.monitor.event_latency),
+        }
+    }
+
+    /// Apply the synth settings, except for the sample rate and buffer size which are properties of the stream
+    pub fn set_config(&self, config: &SynthConfigV1) {
+        self.settings.borrow_mut().config = Some(config.clone());
+        self.send_config(config);
+    }
+
+    fn send_config(&self, config: &SynthConfigV1) {
+        let events = [
+            SynthEvent::SetGain(config.audio_gain),
+            SynthEvent::SetReverb(config.reverb),
+            SynthEvent::SetChorus(config.chorus),
+            SynthEvent::SetPolyphony(config.polyphony),
+            SynthEvent::SetInterpolation(config.interpolation),
+        ];
+        for event in events {
+            self.send(event);
+        }
+    }
+
+    /// Translate the SysEx messages that the synth understands
+    pub fn sysex(&self, data: &[u8]) {
+        let event = match midi_file::program_track::parse_part_mode_sysex(data) {
+            Some(PartMode::Reset) => {
+                let mut settings = self.settings.borrow_mut();
+                settings.drum_channels = Default::default();
+                settings.banks = Default::default();
+                settings.programs = Default::default();
+                SynthEvent::Reset
+            }
+            Some(PartMode::Channel { channel, is_drum }) => {
+                self.settings.borrow_mut().drum_channels[channel as usize] = Some(is_drum);
+                SynthEvent::SetDrumChannel { channel, is_drum }
+            }
+            None => return,
+        };
+        self.send(event);
+    }
+
+    pub fn set_channel_font(&self, channel: u4, font: Option<&Path>) {
+        self.settings.borrow_mut().channel_fonts[channel.as_int() as usize] =
+            font.map(Path::to_path_buf);
+        self.send(SynthEvent::SetChannelFont {
+            channel: channel.as_int(),
+            font: font.map(Path::to_path_buf),
+        });
+    }
+
+    pub fn set_drum_channel(&self, channel: u4, is_drum: bool) {
+        self.settings.borrow_mut().drum_channels[channel.as_int() as usize] = Some(is_drum);
+        self.send(SynthEvent::SetDrumChannel {
+            channel: channel.as_int(),
+            is_drum,
+        });
+    }
+
+    pub fn stop_all(&self) {
+        for channel in 0..16 {
+            for controller in [ALL_NOTES_OFF, ALL_SOUND_OFF] {
+                self.send(SynthEvent::Midi {
+                    channel,
+                    message: midly::MidiMessage::Controller {
+                        controller: controller.into(),
+                        value: 0.into(),
+                    },
+                });
+            }
+        }
+    }
+}
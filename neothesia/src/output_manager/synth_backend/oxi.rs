@@ -0,0 +1,138 @@
+use std::error::Error;
+
+use midi_file::midly::MidiMessage;
+use neothesia_core::config::{ChorusConfigV1, InterpolationV1, ReverbConfigV1};
+
+use super::{ALL_NOTES_OFF, ALL_SOUND_OFF, engine::SynthEngine};
+use crate::output_manager::soundfont::SoundFontFile;
+
+pub struct OxiSynthEngine {
+    synth: oxisynth::Synth,
+}
+
+impl OxiSynthEngine {
+    pub fn new(sample_rate: f32, gain: f32) -> Self {
+        let synth = oxisynth::Synth::new(oxisynth::SynthDescriptor {
+            sample_rate,
+            gain,
+            // Drum channels are managed by `ChannelBanks`, so that any channel can be a drum kit
+            drums_channel_active: false,
+            ..Default::default()
+        })
+        .unwrap();
+
+        Self { synth }
+    }
+}
+
+impl SynthEngine for OxiSynthEngine {
+    type FontId = oxisynth::SoundFontId;
+
+    fn load_font(&mut self, font: &SoundFontFile) -> Result<Self::FontId, Box<dyn Error>> {
+        Ok(self.synth.add_font(font.load_oxisynth()?, true))
+    }
+
+    fn set_gain(&mut self, gain: f32) {
+        self.synth.set_gain(gain);
+    }
+
+    fn set_reverb(&mut self, reverb: &ReverbConfigV1) {
+        self.synth.set_reverb_params(&oxisynth::ReverbParams {
+            roomsize: reverb.room_size,
+            damp: reverb.damp,
+            width: reverb.width,
+            level: reverb.level,
+        });
+    }
+
+    fn set_chorus(&mut self, chorus: &ChorusConfigV1) {
+        self.synth.set_chorus_params(&oxisynth::ChorusParams {
+            nr: chorus.voices,
+            level: chorus.level,
+            speed: chorus.speed,
+            depth: chorus.depth,
+            mode: Default::default(),
+        });
+    }
+
+    fn set_polyphony(&mut self, polyphony: u16) {
+        self.synth.set_polyphony(polyphony).ok();
+    }
+
+    fn set_interpolation(&mut self, interpolation: InterpolationV1) {
+        let method = match interpolation {
+            InterpolationV1::None => oxisynth::InterpolationMethod::None,
+            InterpolationV1::Linear => oxisynth::InterpolationMethod::Linear,
+            InterpolationV1::FourthOrder => oxisynth::InterpolationMethod::FourthOrder,
+            InterpolationV1::SeventhOrder => oxisynth::InterpolationMethod::SeventhOrder,
+        };
+        self.synth.set_interpolation_method(None, method);
+    }
+
+    fn system_reset(&mut self) {
+        self.synth.send_event(oxisynth::MidiEvent::SystemReset).ok();
+    }
+
+    fn select_bank(&mut self, channel: u8, bank: u32) {
+        self.synth.select_bank(channel, bank).ok();
+    }
+
+    fn select_program(&mut self, channel: u8, font: Self::FontId, bank: u32, program: u8) -> bool {
+        self.synth
+            .select_program(channel, font, bank, program)
+            .is_ok()
+    }
+
+    fn midi_message(&mut self, channel: u8, message: MidiMessage) {
+        self.synth
+            .send_event(libmidi_to_oxisynth_event(channel, message))
+            .ok();
+    }
+
+    fn write(&mut self, out: &mut [f32]) {
+        self.synth.write(out);
+    }
+}
+
+fn libmidi_to_oxisynth_event(channel: u8, message: MidiMessage) -> oxisynth::MidiEvent {
+    match message {
+        MidiMessage::NoteOff { key, .. } => oxisynth::MidiEvent::NoteOff {
+            channel,
+            key: key.as_int(),
+        },
+        MidiMessage::NoteOn { key, vel } => oxisynth::MidiEvent::NoteOn {
+            channel,
+            key: key.as_int(),
+            vel: vel.as_int(),
+        },
+        MidiMessage::Aftertouch { key, vel } => oxisynth::MidiEvent::PolyphonicKeyPressure {
+            channel,
+            key: key.as_int(),
+            value: vel.as_int(),
+        },
+        // oxisynth treats the all sound off controller like all notes off, which lets notes ring out
+        MidiMessage::Controller { controller, .. } if controller.as_int() == ALL_SOUND_OFF => {
+            oxisynth::MidiEvent::AllSoundOff { channel }
+        }
+        MidiMessage::Controller { controller, .. } if controller.as_int() == ALL_NOTES_OFF => {
+            oxisynth::MidiEvent::AllNotesOff { channel }
+        }
+        MidiMessage::Controller { controller, value } => oxisynth::MidiEvent::ControlChange {
+            channel,
+            ctrl: controller.as_int(),
+            value: value.as_int(),
+        },
+        MidiMessage::ProgramChange { program } => oxisynth::MidiEvent::ProgramChange {
+            channel,
+            program_id: program.as_int(),
+        },
+        MidiMessage::ChannelAftertouch { vel } => oxisynth::MidiEvent::ChannelPressure {
+            channel,
+            value: vel.as_int(),
+        },
+        MidiMessage::PitchBend { bend } => oxisynth::MidiEvent::PitchBend {
+            channel,
+            value: bend.0.as_int(),
+        },
+    }
+}
@@ -10,9 +10,12 @@ use winit::{
 use crate::{
     NeothesiaEvent,
     context::Context,
+    input_manager::MidiInputEvent,
+    output_manager::{AudioRecorderError, AudioRecording},
     scene::{
-        MouseToMidiEventState, NuonRenderer, Scene,
-        freeplay::recorder::{FreeplayRecorder, Preview, RecorderStatus},
+        MouseToMidiEventState, NuonRenderer, Scene, TouchToMidiEventState,
+        freeplay::recorder::{FreeplayRecorder, Preview, RecorderError, RecorderStatus},
+        pc_keyboard::PcKeyboard,
         playing_scene::Keyboard,
     },
     song::Song,
@@ -51,6 +54,8 @@ pub struct FreeplayScene {
     nuon_renderer: NuonRenderer,
     nuon: nuon::Ui,
     mouse_to_midi_state: MouseToMidiEventState,
+    touch_to_midi_state: TouchToMidiEventState,
+    pc_keyboard: PcKeyboard,
     deduced_chord_name: String,
 
     recorder: FreeplayRecorder,
@@ -66,6 +71,9 @@ impl FreeplayScene {
         let mut keyboard = Keyboard::new(ctx, Default::default());
         keyboard.set_pressed_by_user_colors(ctx.config.color_schema()[0].clone());
 
+        let pc_keyboard = PcKeyboard::new(ctx.config.pc_keyboard());
+        keyboard.set_key_hints(pc_keyboard.hints());
+
         let keyboard_layout = keyboard.layout();
 
         let guidelines = GuidelineRenderer::new(
@@ -98,6 +106,8 @@ impl FreeplayScene {
             nuon_renderer: NuonRenderer::new(ctx),
             nuon: nuon::Ui::new(),
             mouse_to_midi_state: MouseToMidiEventState::default(),
+            touch_to_midi_state: TouchToMidiEventState::default(),
+            pc_keyboard,
             deduced_chord_name: String::new(),
             recorder: FreeplayRecorder::default(),
             recorder_status: RecorderStatus::default(),
@@ -247,17 +257,29 @@ impl Scene for FreeplayScene {
         }
 
         super::handle_nuon_window_event(&mut self.nuon, event, ctx);
-        super::handle_pc_keyboard_to_midi_event(ctx, event);
+        super::handle_pc_keyboard_to_midi_event(
+            &mut self.pc_keyboard,
+            &mut self.keyboard,
+            ctx,
+            event,
+        );
         super::handle_mouse_to_midi_event(
             &mut self.keyboard,
             &mut self.mouse_to_midi_state,
             ctx,
             event,
         );
+        super::handle_touch_to_midi_event(
+            &mut self.keyboard,
+            &mut self.touch_to_midi_state,
+            ctx,
+            event,
+        );
     }
 
-    fn midi_event(&mut self, ctx: &mut Context, channel: u8, message: &MidiMessage) {
-        self.recorder.push_event(channel, *message);
+    fn midi_event(&mut self, ctx: &mut Context, event: &MidiInputEvent) {
+        let message = &event.message;
+        self.recorder.push_event(event.channel, *message);
         self.keyboard.user_midi_event(message);
         ctx.output_manager
             .connection()
@@ -278,6 +300,17 @@ impl Scene for FreeplayScene {
             self.deduced_chord_name = chords::deduce_name(&notes);
         }
     }
+
+    fn audio_recording_stopped(
+        &mut self,
+        _ctx: &mut Context,
+        result: Result<AudioRecording, AudioRecorderError>,
+    ) {
+        self.recorder_status = match result {
+            Ok(recording) => RecorderStatus::Saved(recording.path),
+            Err(err) => RecorderStatus::Error(RecorderError::Audio(err.to_string())),
+        };
+    }
 }
 
 mod chords {
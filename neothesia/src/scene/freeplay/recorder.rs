@@ -14,6 +14,7 @@ use crate::{
     NeothesiaEvent,
     context::Context,
     icons,
+    output_manager::{self, TrackOutputs},
     scene::{
         freeplay::{FreeplayScene, on_async},
         playing_scene::{Keyboard, midi_player::MidiPlayer},
@@ -33,6 +34,8 @@ pub enum RecorderError {
     Write,
     #[error("{0}")]
     MidiFileParse(String),
+    #[error("{0}")]
+    Audio(String),
 }
 
 #[derive(Default, Debug)]
@@ -146,7 +149,7 @@ impl Preview {
         ));
 
         let mut player = MidiPlayer::new_with_lead_in(
-            ctx.output_manager.connection().clone(),
+            TrackOutputs::new(ctx.output_manager.connection().clone()),
             song,
             keyboard.layout().range.clone(),
             ctx.config.separate_channels(),
@@ -291,8 +294,11 @@ pub fn update_preview_ui(scene: &mut FreeplayScene, ctx: &mut Context) {
         .map(|s| s.player.is_paused())
         .unwrap_or(true);
 
+    let audio_recorder = ctx.output_manager.audio_recorder();
     let status_label = if scene.recorder.is_recording() {
         format!("Recording {:.1}s", scene.recorder.duration().as_secs_f32())
+    } else if let Some(recorder) = audio_recorder {
+        format!("Recording audio {:.1}s", recorder.elapsed().as_secs_f32())
     } else {
         scene.recorder_status.to_string()
     };
@@ -302,6 +308,7 @@ pub fn update_preview_ui(scene: &mut FreeplayScene, ctx: &mut Context) {
         Seek,
         GoBack,
         Record,
+        RecordAudio,
         Save,
         None,
     }
@@ -400,6 +407,33 @@ pub fn update_preview_ui(scene: &mut FreeplayScene, ctx: &mut Context) {
             {
                 msg = Msg::Record;
             }
+
+            nuon::translate().x(-30.0).add_to_current(ui);
+
+            let is_recording_audio = audio_recorder.is_some();
+            if nuon::button()
+                .size(30.0, 30.0)
+                .border_radius([5.0; 4])
+                .icon(icons::soundwave_icon())
+                .color(if is_recording_audio {
+                    [208, 18, 0, 255]
+                } else {
+                    [0, 0, 0, 0]
+                })
+                .hover_color(if is_recording_audio {
+                    [165, 47, 47]
+                } else {
+                    [97, 97, 97]
+                })
+                .preseed_color(if is_recording_audio {
+                    [145, 37, 37]
+                } else {
+                    [87, 87, 87]
+                })
+                .build(ui)
+            {
+                msg = Msg::RecordAudio;
+            }
         });
 
         if let Some(state) = scene.preview.as_ref() {
@@ -462,6 +496,9 @@ pub fn update_preview_ui(scene: &mut FreeplayScene, ctx: &mut Context) {
         Msg::Record => {
             handle_record_click(scene, ctx);
         }
+        Msg::RecordAudio => {
+            handle_record_audio_click(scene, ctx);
+        }
         Msg::Save => {
             handle_save_click(scene, ctx);
         }
@@ -486,6 +523,29 @@ fn handle_record_click(scene: &mut FreeplayScene, ctx: &Context) {
     scene.recorder.start();
 }
 
+/// Record the synth audio, independent of the MIDI recording and including the preview playback
+fn handle_record_audio_click(scene: &mut FreeplayScene, ctx: &mut Context) {
+    if let Some(res) = ctx.output_manager.stop_audio_recording() {
+        scene.recorder_status = match res {
+            Ok(recording) => RecorderStatus::Saved(recording.path),
+            Err(err) => RecorderStatus::Error(RecorderError::Audio(err.to_string())),
+        };
+        return;
+    }
+
+    let Some(path) = output_manager::recording_path("freeplay") else {
+        scene.recorder_status = RecorderStatus::Error(RecorderError::Audio(
+            "No folder to save recordings to".into(),
+        ));
+        return;
+    };
+
+    scene.recorder_status = match ctx.output_manager.start_audio_recording(path) {
+        Ok(()) => RecorderStatus::Idle,
+        Err(err) => RecorderStatus::Error(RecorderError::Audio(err.to_string())),
+    };
+}
+
 fn handle_save_click(scene: &mut FreeplayScene, ctx: &Context) {
     let mut dialog = rfd::AsyncFileDialog::new()
         .add_filter("midi", &["mid", "midi"])
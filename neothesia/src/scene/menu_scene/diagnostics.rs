@@ -0,0 +1,726 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use midi_file::midly::{
+    MidiMessage,
+    live::{LiveEvent, SystemCommon, SystemRealtime},
+};
+
+use crate::{
+    context::Context,
+    input_manager::{InputSource, MidiInputEvent, SystemMidiEvent},
+    scene::menu_scene::{icons, neo_btn_icon, settings::button, state},
+};
+use nuon::TextJustify;
+
+/// How many messages the monitor remembers
+const LOG_LEN: usize = 200;
+/// How many of the latest messages are shown
+const LOG_VISIBLE: usize = 24;
+/// How many bytes of a SysEx are shown
+const SYSEX_VISIBLE: usize = 12;
+
+const SCALE: [u8; 15] = [60, 62, 64, 65, 67, 69, 71, 72, 71, 69, 67, 65, 64, 62, 60];
+const SCALE_STEP: Duration = Duration::from_millis(250);
+
+/// Note sent through the loopback, a key and velocity that nobody plays by accident
+const PROBE_KEY: u8 = 127;
+const PROBE_VEL: u8 = 1;
+const PROBE_COUNT: usize = 5;
+const PROBE_GAP: Duration = Duration::from_millis(100);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LogMessage {
+    Channel(u8, MidiMessage),
+    /// Raw bytes of a system message
+    System(Vec<u8>),
+}
+
+struct LogEntry {
+    time: Instant,
+    direction: Direction,
+    port: String,
+    message: LogMessage,
+    /// How many times in a row the message came, system messages like the clock are merged
+    count: usize,
+}
+
+/// Plays a scale up and down, a note at a time
+#[derive(Debug, Default)]
+struct ScaleTest {
+    /// Index of the next note in `SCALE`
+    step: usize,
+    next: Option<Instant>,
+    held: Option<u8>,
+}
+
+impl ScaleTest {
+    fn is_running(&self) -> bool {
+        self.next.is_some()
+    }
+
+    fn start(&mut self, now: Instant) {
+        self.step = 0;
+        self.next = Some(now);
+    }
+
+    fn update(&mut self, now: Instant, mut send: impl FnMut(MidiMessage)) {
+        let Some(next) = self.next else {
+            return;
+        };
+        if now < next {
+            return;
+        }
+
+        if let Some(key) = self.held.take() {
+            send(MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            });
+        }
+
+        let Some(key) = SCALE.get(self.step).copied() else {
+            self.next = None;
+            return;
+        };
+
+        send(MidiMessage::NoteOn {
+            key: key.into(),
+            vel: 100.into(),
+        });
+        self.held = Some(key);
+        self.step += 1;
+        self.next = Some(next + SCALE_STEP);
+    }
+}
+
+/// Round trip time of notes sent to the output, and received back on an input
+#[derive(Debug, Default)]
+enum LatencyTest {
+    #[default]
+    Idle,
+    Running {
+        results: Vec<Duration>,
+        /// When the probe in flight was sent, or when the next one is due
+        sent: Option<Instant>,
+        next: Instant,
+    },
+    Done(Vec<Duration>),
+    /// No probe came back
+    Failed,
+}
+
+impl LatencyTest {
+    fn is_running(&self) -> bool {
+        matches!(self, Self::Running { .. })
+    }
+
+    fn start(&mut self, now: Instant) {
+        *self = Self::Running {
+            results: Vec::new(),
+            sent: None,
+            next: now,
+        };
+    }
+
+    fn update(&mut self, now: Instant, mut send: impl FnMut(MidiMessage)) {
+        let Self::Running { sent, next, .. } = self else {
+            return;
+        };
+
+        match sent {
+            Some(sent) if now.saturating_duration_since(*sent) > PROBE_TIMEOUT => {
+                send(MidiMessage::NoteOff {
+                    key: PROBE_KEY.into(),
+                    vel: 0.into(),
+                });
+                *self = Self::Failed;
+            }
+            Some(_) => {}
+            None if now >= *next => {
+                send(MidiMessage::NoteOn {
+                    key: PROBE_KEY.into(),
+                    vel: PROBE_VEL.into(),
+                });
+                *sent = Some(now);
+            }
+            None => {}
+        }
+    }
+
+    /// Returns `true` if the message was a probe coming back
+    fn midi_event(
+        &mut self,
+        message: &MidiMessage,
+        timestamp: Instant,
+        mut send: impl FnMut(MidiMessage),
+    ) -> bool {
+        let Self::Running {
+            results,
+            sent,
+            next,
+        } = self
+        else {
+            return false;
+        };
+        let Some(sent_at) = *sent else {
+            return false;
+        };
+
+        let MidiMessage::NoteOn { key, vel } = message else {
+            return false;
+        };
+        if (key.as_int(), vel.as_int()) != (PROBE_KEY, PROBE_VEL) {
+            return false;
+        }
+
+        send(MidiMessage::NoteOff {
+            key: PROBE_KEY.into(),
+            vel: 0.into(),
+        });
+
+        results.push(timestamp.saturating_duration_since(sent_at));
+        *sent = None;
+        *next = timestamp + PROBE_GAP;
+
+        if results.len() == PROBE_COUNT {
+            *self = Self::Done(std::mem::take(results));
+        }
+        true
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Self::Idle => "Connect the output to an input, and measure the round trip".into(),
+            Self::Running { results, .. } => {
+                format!("Measuring {}/{PROBE_COUNT}...", results.len() + 1)
+            }
+            Self::Done(results) => {
+                let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+                let min = results.iter().min().map(ms).unwrap_or_default();
+                let max = results.iter().max().map(ms).unwrap_or_default();
+                let avg = results.iter().map(ms).sum::<f64>() / results.len().max(1) as f64;
+                format!("{avg:.1} ms on average, {min:.1} to {max:.1} ms")
+            }
+            Self::Failed => "Nothing came back, is the output looped back to an input?".into(),
+        }
+    }
+}
+
+/// Live MIDI messages of every port, and tests of the output
+pub struct MidiMonitor {
+    started: Instant,
+    log: VecDeque<LogEntry>,
+    scale: ScaleTest,
+    latency: LatencyTest,
+}
+
+impl Default for MidiMonitor {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            log: VecDeque::new(),
+            scale: ScaleTest::default(),
+            latency: LatencyTest::default(),
+        }
+    }
+}
+
+impl MidiMonitor {
+    fn push(&mut self, entry: LogEntry) {
+        if let Some(last) = self.log.front_mut()
+            && matches!(entry.message, LogMessage::System(_))
+            && (last.direction, &last.port, &last.message)
+                == (entry.direction, &entry.port, &entry.message)
+        {
+            last.time = entry.time;
+            last.count += 1;
+            return;
+        }
+
+        if self.log.len() == LOG_LEN {
+            self.log.pop_back();
+        }
+        self.log.push_front(entry);
+    }
+
+    pub fn update(&mut self, ctx: &Context) {
+        let now = Instant::now();
+        let mut sent = Vec::new();
+
+        self.scale.update(now, |message| sent.push(message));
+        self.latency.update(now, |message| sent.push(message));
+
+        for message in sent {
+            self.send(ctx, message);
+        }
+    }
+
+    pub fn midi_event(&mut self, ctx: &Context, event: &MidiInputEvent) {
+        let mut sent = Vec::new();
+        self.latency
+            .midi_event(&event.message, event.timestamp, |message| {
+                sent.push(message)
+            });
+
+        let port = match &event.source {
+            InputSource::Midi(name) => name.to_string(),
+            InputSource::Local => "Computer Keyboard".into(),
+        };
+        self.push(LogEntry {
+            time: event.timestamp,
+            direction: Direction::In,
+            port,
+            message: LogMessage::Channel(event.channel, event.message),
+            count: 1,
+        });
+
+        for message in sent {
+            self.send(ctx, message);
+        }
+    }
+
+    pub fn system_midi_event(&mut self, event: &SystemMidiEvent) {
+        let port = match &event.source {
+            InputSource::Midi(name) => name.to_string(),
+            InputSource::Local => "Computer Keyboard".into(),
+        };
+        self.push(LogEntry {
+            time: event.timestamp,
+            direction: Direction::In,
+            port,
+            message: LogMessage::System(event.bytes.clone()),
+            count: 1,
+        });
+    }
+
+    /// Nothing else plays while the monitor is open, so this logs all of the outgoing traffic
+    fn send(&mut self, ctx: &Context, message: MidiMessage) {
+        ctx.output_manager
+            .connection()
+            .midi_event(0.into(), message);
+
+        self.push(LogEntry {
+            time: Instant::now(),
+            direction: Direction::Out,
+            port: ctx.config.output().unwrap_or("No Output").to_string(),
+            message: LogMessage::Channel(0, message),
+            count: 1,
+        });
+    }
+
+    fn entry_label(&self, entry: &LogEntry) -> String {
+        let time = entry.time.saturating_duration_since(self.started);
+        let direction = match entry.direction {
+            Direction::In => "IN ",
+            Direction::Out => "OUT",
+        };
+        let message = match &entry.message {
+            LogMessage::Channel(channel, message) => {
+                format!("ch {}  {}", channel + 1, describe(message))
+            }
+            LogMessage::System(bytes) => describe_system(bytes),
+        };
+        let count = if entry.count > 1 {
+            format!("  x{}", entry.count)
+        } else {
+            String::new()
+        };
+        format!(
+            "{:>9.3}s  {direction}  {}  {message}{count}",
+            time.as_secs_f64(),
+            entry.port,
+        )
+    }
+}
+
+fn note_name(key: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    format!("{}{} ({key})", NAMES[key as usize % 12], key as i8 / 12 - 1)
+}
+
+/// Human readable MIDI message
+fn describe(message: &MidiMessage) -> String {
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel.as_int() == 0 => {
+            format!("Note Off {}", note_name(key.as_int()))
+        }
+        MidiMessage::NoteOn { key, vel } => {
+            format!("Note On {} vel {vel}", note_name(key.as_int()))
+        }
+        MidiMessage::NoteOff { key, vel } => {
+            format!("Note Off {} vel {vel}", note_name(key.as_int()))
+        }
+        MidiMessage::Aftertouch { key, vel } => {
+            format!("Key Pressure {} {vel}", note_name(key.as_int()))
+        }
+        MidiMessage::Controller { controller, value } => {
+            format!("Control Change {controller} = {value}")
+        }
+        MidiMessage::ProgramChange { program } => format!("Program Change {program}"),
+        MidiMessage::ChannelAftertouch { vel } => format!("Channel Pressure {vel}"),
+        MidiMessage::PitchBend { bend } => format!("Pitch Bend {:+}", bend.as_int()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let shown: Vec<String> = bytes
+        .iter()
+        .take(SYSEX_VISIBLE)
+        .map(|b| format!("{b:02X}"))
+        .collect();
+    let more = if bytes.len() > SYSEX_VISIBLE {
+        " ..."
+    } else {
+        ""
+    };
+    format!("{}{more}", shown.join(" "))
+}
+
+/// Human readable system message, from its raw bytes
+fn describe_system(bytes: &[u8]) -> String {
+    let Ok(event) = LiveEvent::parse(bytes) else {
+        return format!("Unknown {}", hex(bytes));
+    };
+
+    match event {
+        LiveEvent::Midi { channel, message } => {
+            format!("ch {}  {}", channel.as_int() + 1, describe(&message))
+        }
+        LiveEvent::Common(SystemCommon::SysEx(_)) => {
+            format!("SysEx {} bytes  {}", bytes.len(), hex(bytes))
+        }
+        LiveEvent::Common(SystemCommon::MidiTimeCodeQuarterFrame(kind, value)) => {
+            format!("MTC Quarter Frame {kind:?} {value}")
+        }
+        LiveEvent::Common(SystemCommon::SongPosition(pos)) => format!("Song Position {pos}"),
+        LiveEvent::Common(SystemCommon::SongSelect(song)) => format!("Song Select {song}"),
+        LiveEvent::Common(SystemCommon::TuneRequest) => "Tune Request".into(),
+        LiveEvent::Common(SystemCommon::Undefined(..)) => format!("Undefined {}", hex(bytes)),
+        LiveEvent::Realtime(realtime) => match realtime {
+            SystemRealtime::TimingClock => "Timing Clock".into(),
+            SystemRealtime::Start => "Start".into(),
+            SystemRealtime::Continue => "Continue".into(),
+            SystemRealtime::Stop => "Stop".into(),
+            SystemRealtime::ActiveSensing => "Active Sensing".into(),
+            SystemRealtime::Reset => "Reset".into(),
+            SystemRealtime::Undefined(_) => format!("Undefined {}", hex(bytes)),
+        },
+    }
+}
+
+impl super::MenuScene {
+    pub fn diagnostics_page_ui(&mut self, ctx: &mut Context, ui: &mut nuon::Ui) {
+        let win_w = ctx.window_state.logical_size.width;
+        let win_h = ctx.window_state.logical_size.height;
+
+        let bottom_bar_h = 60.0;
+
+        nuon::translate().x(0.0).y(win_h).build(ui, |ui| {
+            let padding = 10.0;
+            let w = 80.0;
+            let h = bottom_bar_h;
+
+            nuon::translate().y(-padding).add_to_current(ui);
+            nuon::translate().y(-h).add_to_current(ui);
+
+            nuon::translate().x(padding).build(ui, |ui| {
+                if neo_btn_icon(ui, w, h, icons::left_arrow_icon()) {
+                    state::go_back(&mut self.state, ctx);
+                }
+            });
+        });
+
+        let margin_top = 40.0;
+        let body_w = 650.0;
+
+        self.diagnostics_scroll = nuon::scroll()
+            .scissor_size(win_w, (win_h - bottom_bar_h).max(0.0))
+            .scroll(self.diagnostics_scroll)
+            .build(ui, |ui| {
+                nuon::translate()
+                    .x(nuon::center_x(win_w, body_w))
+                    .add_to_current(ui);
+                nuon::translate().y(margin_top).add_to_current(ui);
+
+                nuon::settings_section("Outputs")
+                    .width(body_w)
+                    .build(ui, |ui, rows, spacer| {
+                        for (id, output) in self.state.outputs.iter().enumerate() {
+                            if id != 0 {
+                                spacer(ui);
+                            }
+
+                            let selected = self.state.selected_output.as_ref() == Some(output);
+                            nuon::settings_row()
+                                .title(output.to_string())
+                                .subtitle(if selected { "Selected" } else { "" })
+                                .build(ui, rows);
+                        }
+                    });
+
+                nuon::settings_section("Inputs")
+                    .width(body_w)
+                    .build(ui, |ui, rows, spacer| {
+                        if self.state.inputs.is_empty() {
+                            nuon::settings_row()
+                                .title("No MIDI inputs found")
+                                .subtitle("Check the cable, and that the device is turned on")
+                                .build(ui, rows);
+                        }
+
+                        for (id, input) in self.state.inputs.iter().enumerate() {
+                            if id != 0 {
+                                spacer(ui);
+                            }
+
+                            let subtitle = if ctx.input_manager.is_connected(input) {
+                                "Listening"
+                            } else {
+                                "Could not be opened"
+                            };
+                            nuon::settings_row()
+                                .title(input.to_string())
+                                .subtitle(subtitle)
+                                .build(ui, rows);
+                        }
+                    });
+
+                nuon::settings_section("Tests")
+                    .width(body_w)
+                    .build(ui, |ui, rows, spacer| {
+                        let monitor = &mut self.monitor;
+
+                        nuon::settings_row()
+                            .title("Scale")
+                            .subtitle("Play a C major scale on the selected output")
+                            .body(|ui, row_w, row_h| {
+                                let w = 93.0;
+                                let h = 31.0;
+                                if !monitor.scale.is_running()
+                                    && button()
+                                        .id("diagnostics_scale")
+                                        .x(row_w - w)
+                                        .y(nuon::center_y(row_h, h))
+                                        .size(w, h)
+                                        .label("Play")
+                                        .build(ui)
+                                {
+                                    monitor.scale.start(Instant::now());
+                                }
+                            })
+                            .build(ui, rows);
+
+                        spacer(ui);
+
+                        nuon::settings_row()
+                            .title("Loopback Latency")
+                            .subtitle(monitor.latency.label())
+                            .body(|ui, row_w, row_h| {
+                                let w = 93.0;
+                                let h = 31.0;
+                                if !monitor.latency.is_running()
+                                    && button()
+                                        .id("diagnostics_latency")
+                                        .x(row_w - w)
+                                        .y(nuon::center_y(row_h, h))
+                                        .size(w, h)
+                                        .label("Measure")
+                                        .build(ui)
+                                {
+                                    monitor.latency.start(Instant::now());
+                                }
+                            })
+                            .build(ui, rows);
+                    });
+
+                nuon::settings_section("Messages")
+                    .width(body_w)
+                    .build(ui, |ui, rows, _spacer| {
+                        if self.monitor.log.is_empty() {
+                            nuon::settings_row()
+                                .title("Nothing received yet")
+                                .subtitle("Play a few keys on the MIDI keyboard")
+                                .build(ui, rows);
+                            return;
+                        }
+
+                        nuon::settings_row()
+                            .title(format!("{} messages", self.monitor.log.len()))
+                            .body(|ui, row_w, row_h| {
+                                let w = 93.0;
+                                let h = 31.0;
+                                if button()
+                                    .id("diagnostics_clear")
+                                    .x(row_w - w)
+                                    .y(nuon::center_y(row_h, h))
+                                    .size(w, h)
+                                    .label("Clear")
+                                    .build(ui)
+                                {
+                                    self.monitor.log.clear();
+                                }
+                            })
+                            .build(ui, rows);
+
+                        let line_h = 20.0;
+                        for entry in self.monitor.log.iter().take(LOG_VISIBLE) {
+                            let color = match entry.direction {
+                                Direction::In => [1.0, 1.0, 1.0, 1.0],
+                                Direction::Out => [0.6, 0.8, 1.0, 1.0],
+                            };
+                            nuon::label()
+                                .x(15.0)
+                                .text(self.monitor.entry_label(entry))
+                                .color(color)
+                                .font_size(12.2)
+                                .text_justify(TextJustify::Left)
+                                .size(body_w - 30.0, line_h)
+                                .build(ui);
+                            nuon::translate().y(line_h).add_to_current(ui);
+                        }
+                    });
+
+                nuon::translate().y(margin_top).add_to_current(ui);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_test() {
+        let mut scale = ScaleTest::default();
+        let start = Instant::now();
+        let mut sent = Vec::new();
+
+        scale.start(start);
+        for step in 0..=SCALE.len() as u32 {
+            let time = start + SCALE_STEP * step;
+            scale.update(time, |m| sent.push(m));
+
+            // Nothing is due in between
+            let len = sent.len();
+            scale.update(time + SCALE_STEP / 2, |m| sent.push(m));
+            assert_eq!(sent.len(), len);
+        }
+
+        assert!(!scale.is_running());
+        // A note on and a note off for every note
+        assert_eq!(sent.len(), SCALE.len() * 2);
+        assert_eq!(
+            sent[0],
+            MidiMessage::NoteOn {
+                key: 60.into(),
+                vel: 100.into()
+            }
+        );
+        assert!(matches!(sent.last(), Some(MidiMessage::NoteOff { key, .. }) if *key == 60));
+    }
+
+    #[test]
+    fn latency_test() {
+        let mut latency = LatencyTest::default();
+        let mut now = Instant::now();
+        let probe = MidiMessage::NoteOn {
+            key: PROBE_KEY.into(),
+            vel: PROBE_VEL.into(),
+        };
+        let mut sent = Vec::new();
+
+        latency.start(now);
+        for _ in 0..PROBE_COUNT {
+            latency.update(now, |m| sent.push(m));
+            assert_eq!(sent.pop(), Some(probe));
+
+            now += Duration::from_millis(4);
+            // Someone playing along is not a probe
+            let note = MidiMessage::NoteOn {
+                key: 60.into(),
+                vel: 90.into(),
+            };
+            assert!(!latency.midi_event(&note, now, |m| sent.push(m)));
+            assert!(latency.midi_event(&probe, now, |m| sent.push(m)));
+            now += PROBE_GAP;
+        }
+
+        assert!(matches!(&latency, LatencyTest::Done(results) if results.len() == PROBE_COUNT));
+        assert_eq!(latency.label(), "4.0 ms on average, 4.0 to 4.0 ms");
+
+        latency.start(now);
+        latency.update(now, |_| {});
+        latency.update(now + PROBE_TIMEOUT * 2, |_| {});
+        assert!(matches!(latency, LatencyTest::Failed));
+    }
+
+    #[test]
+    fn describe_messages() {
+        let note_on = MidiMessage::NoteOn {
+            key: 61.into(),
+            vel: 100.into(),
+        };
+        assert_eq!(describe(&note_on), "Note On C#4 (61) vel 100");
+
+        let released = MidiMessage::NoteOn {
+            key: 21.into(),
+            vel: 0.into(),
+        };
+        assert_eq!(describe(&released), "Note Off A0 (21)");
+
+        let sustain = MidiMessage::Controller {
+            controller: 64.into(),
+            value: 127.into(),
+        };
+        assert_eq!(describe(&sustain), "Control Change 64 = 127");
+    }
+
+    #[test]
+    fn describe_system_messages() {
+        assert_eq!(describe_system(&[0xF8]), "Timing Clock");
+        assert_eq!(describe_system(&[0xF2, 0x10, 0x01]), "Song Position 144");
+        assert_eq!(
+            describe_system(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]),
+            "SysEx 6 bytes  F0 7E 7F 06 01 F7"
+        );
+
+        // As written back by the input thread
+        let sysex = [0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7];
+        let mut bytes = Vec::new();
+        LiveEvent::parse(&sysex).unwrap().write(&mut bytes).unwrap();
+        assert!(describe_system(&bytes).starts_with("SysEx"));
+    }
+
+    #[test]
+    fn repeated_system_messages_are_merged() {
+        let mut monitor = MidiMonitor::default();
+        let entry = |bytes: &[u8]| LogEntry {
+            time: Instant::now(),
+            direction: Direction::In,
+            port: "Keyboard".into(),
+            message: LogMessage::System(bytes.to_vec()),
+            count: 1,
+        };
+
+        for _ in 0..24 {
+            monitor.push(entry(&[0xF8]));
+        }
+        monitor.push(entry(&[0xFC]));
+        monitor.push(entry(&[0xF8]));
+
+        let counts: Vec<usize> = monitor.log.iter().map(|entry| entry.count).collect();
+        assert_eq!(counts, vec![1, 1, 24]);
+    }
+}
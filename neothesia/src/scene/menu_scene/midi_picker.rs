@@ -12,8 +12,8 @@ pub fn open_midi_file_picker(data: &mut UiState) -> BoxFuture<MsgFn> {
     data.is_loading = true;
     on_async(open_midi_file_picker_fut(), |res, data, ctx| {
         if let Some((midi, path)) = res {
+            data.song = Some(Song::with_path(midi, path.clone(), &ctx.config));
             ctx.config.set_last_opened_song(Some(path));
-            data.song = Some(Song::new(midi));
         }
         data.is_loading = false;
     })
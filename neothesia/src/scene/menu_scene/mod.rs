@@ -8,6 +8,9 @@ use midi_picker::open_midi_file_picker;
 mod neo_btn;
 use neo_btn::{neo_btn, neo_btn_icon};
 
+mod diagnostics;
+use diagnostics::MidiMonitor;
+
 mod settings;
 mod tracks;
 
@@ -21,7 +24,14 @@ use winit::{
     keyboard::{Key, NamedKey},
 };
 
-use crate::{NeothesiaEvent, context::Context, icons, scene::Scene, song::Song};
+use crate::{
+    NeothesiaEvent,
+    context::Context,
+    icons,
+    input_manager::{MidiInputEvent, SystemMidiEvent},
+    scene::Scene,
+    song::Song,
+};
 
 use super::NuonRenderer;
 
@@ -75,7 +85,10 @@ pub struct MenuScene {
 
     tracks_scroll: nuon::ScrollState,
     settings_scroll: nuon::ScrollState,
+    diagnostics_scroll: nuon::ScrollState,
     popup: Popup,
+
+    monitor: MidiMonitor,
 }
 
 impl MenuScene {
@@ -109,7 +122,10 @@ impl MenuScene {
             nuon: nuon::Ui::new(),
             tracks_scroll: nuon::ScrollState::new(),
             settings_scroll: nuon::ScrollState::new(),
+            diagnostics_scroll: nuon::ScrollState::new(),
             popup: Popup::None,
+
+            monitor: MidiMonitor::default(),
         }
     }
 
@@ -134,6 +150,7 @@ impl MenuScene {
             Page::Main => self.main_page_ui(ctx, &mut nuon),
             Page::Settings => self.settings_page_ui(ctx, &mut nuon),
             Page::TrackSelection => self.tracks_page_ui(ctx, &mut nuon),
+            Page::Diagnostics => self.diagnostics_page_ui(ctx, &mut nuon),
         }
 
         self.nuon = nuon;
@@ -299,6 +316,9 @@ impl Scene for MenuScene {
 
         self.state.tick(ctx);
 
+        // Tests keep going after leaving the page, so that they release their notes
+        self.monitor.update(ctx);
+
         self.main_ui(ctx);
 
         super::render_nuon(&mut self.nuon, &mut self.nuon_renderer, ctx);
@@ -325,10 +345,12 @@ impl Scene for MenuScene {
                     let y = y * 60.0;
                     self.settings_scroll.update(y);
                     self.tracks_scroll.update(y);
+                    self.diagnostics_scroll.update(y);
                 }
                 winit::event::MouseScrollDelta::PixelDelta(position) => {
                     self.settings_scroll.update(position.y as f32);
                     self.tracks_scroll.update(position.y as f32);
+                    self.diagnostics_scroll.update(position.y as f32);
                 }
             }
         }
@@ -343,7 +365,7 @@ impl Scene for MenuScene {
         } else if event.left_mouse_released() {
             self.nuon.mouse_up();
         } else if event.back_mouse_pressed() {
-            self.state.go_back();
+            state::go_back(&mut self.state, ctx);
         }
 
         match self.state.current() {
@@ -381,9 +403,9 @@ impl Scene for MenuScene {
                     state::freeplay(&self.state, ctx);
                 }
             }
-            Page::Settings => {
+            Page::Settings | Page::Diagnostics => {
                 if event.key_pressed(Key::Named(NamedKey::Escape)) {
-                    self.state.go_back();
+                    state::go_back(&mut self.state, ctx);
                 }
             }
             Page::TrackSelection => {
@@ -397,4 +419,16 @@ impl Scene for MenuScene {
             }
         }
     }
+
+    fn midi_event(&mut self, ctx: &mut Context, event: &MidiInputEvent) {
+        if *self.state.current() == Page::Diagnostics {
+            self.monitor.midi_event(ctx, event);
+        }
+    }
+
+    fn system_midi_event(&mut self, _ctx: &mut Context, event: &SystemMidiEvent) {
+        if *self.state.current() == Page::Diagnostics {
+            self.monitor.system_midi_event(event);
+        }
+    }
 }
@@ -1,15 +1,21 @@
-use std::path::PathBuf;
+use std::{
+    hash::Hash,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     context::Context,
-    scene::menu_scene::{MsgFn, Popup, icons, neo_btn_icon, on_async},
+    scene::menu_scene::{MsgFn, Popup, icons, neo_btn_icon, on_async, state},
     utils::BoxFuture,
 };
+use neothesia_core::config::{
+    ChorusConfigV1, InterpolationV1, ReverbConfigV1, SplitZoneV1, VelocityCurveV1,
+};
 use nuon::TextJustify;
 
 use super::UiState;
 
-fn button() -> nuon::Button {
+pub(super) fn button() -> nuon::Button {
     nuon::button()
         .color([74, 68, 88])
         .preseed_color([74, 68, 88])
@@ -145,6 +151,49 @@ impl super::MenuScene {
                             ctx.config.set_note_labels(!ctx.config.note_labels());
                         }
                     });
+
+                nuon::settings_section("Loop Practice").width(body_w).build(
+                    ui,
+                    |ui, rows, spacer| {
+                        self::update_loop_practice_target(
+                            ctx,
+                            nuon::settings_row_spin()
+                                .title("Target")
+                                .subtitle(format!(
+                                    "{} clean passes",
+                                    ctx.config.loop_practice_target()
+                                ))
+                                .id("loop-practice-target")
+                                .build(ui, rows),
+                        );
+
+                        spacer(ui);
+
+                        self::update_loop_practice_speed_step(
+                            ctx,
+                            nuon::settings_row_spin()
+                                .title("Speed Step")
+                                .subtitle(format!(
+                                    "+{}% after each clean pass",
+                                    (ctx.config.loop_practice_speed_step() * 100.0).round()
+                                ))
+                                .id("loop-practice-speed-step")
+                                .build(ui, rows),
+                        );
+
+                        spacer(ui);
+
+                        if nuon::settings_row_toggler()
+                            .title("Advance")
+                            .subtitle("Move the loop to the next section when target is reached")
+                            .value(ctx.config.loop_practice_advance())
+                            .build(ui, rows)
+                        {
+                            ctx.config
+                                .set_loop_practice_advance(!ctx.config.loop_practice_advance());
+                        }
+                    },
+                );
             });
     }
 }
@@ -235,8 +284,7 @@ impl super::MenuScene {
                 .subtitle(
                     ctx.config
                         .soundfont_path()
-                        .and_then(|path| path.file_name())
-                        .map(|name| name.to_string_lossy().to_string())
+                        .map(|path| file_name(path))
                         .unwrap_or_default(),
                 )
                 .body(|ui, row_w, row_h| {
@@ -255,6 +303,8 @@ impl super::MenuScene {
                 })
                 .build(ui, rows);
 
+            self.settings_soundfont_layers(ctx, ui, rows, spacer);
+
             spacer(ui);
 
             self::update_audio_gain(
@@ -265,6 +315,132 @@ impl super::MenuScene {
                     .id("gain")
                     .build(ui, rows),
             );
+
+            spacer(ui);
+
+            let reverb = *ctx.config.reverb();
+            self::update_reverb(
+                ctx,
+                |reverb, kind| reverb.level = spin_f32(kind, reverb.level, 0.1),
+                nuon::settings_row_spin()
+                    .title("Reverb")
+                    .subtitle(effect_level_label(reverb.level))
+                    .id("reverb-level")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_reverb(
+                ctx,
+                |reverb, kind| reverb.room_size = spin_f32(kind, reverb.room_size, 0.1),
+                nuon::settings_row_spin()
+                    .title("Reverb Room Size")
+                    .subtitle(format!("{:.1}", reverb.room_size))
+                    .id("reverb-room-size")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            let chorus = *ctx.config.chorus();
+            self::update_chorus(
+                ctx,
+                |chorus, kind| chorus.level = spin_f32(kind, chorus.level, 0.1),
+                nuon::settings_row_spin()
+                    .title("Chorus")
+                    .subtitle(effect_level_label(chorus.level))
+                    .id("chorus-level")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_chorus(
+                ctx,
+                |chorus, kind| chorus.depth = spin_f32(kind, chorus.depth, 0.5),
+                nuon::settings_row_spin()
+                    .title("Chorus Depth")
+                    .subtitle(format!("{:.1} ms", chorus.depth))
+                    .id("chorus-depth")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_polyphony(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Polyphony")
+                    .subtitle(format!("{} voices", ctx.config.polyphony()))
+                    .id("polyphony")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_interpolation(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Interpolation")
+                    .subtitle(interpolation_label(ctx.config.interpolation()))
+                    .id("interpolation")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_audio_host(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Audio Host")
+                    .subtitle(ctx.config.audio_host().unwrap_or("Default").to_string())
+                    .id("audio-host")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_audio_device(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Audio Device")
+                    .subtitle(ctx.config.audio_device().unwrap_or("Default").to_string())
+                    .id("audio-device")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_sample_rate(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Sample Rate")
+                    .subtitle(
+                        ctx.config
+                            .sample_rate()
+                            .map(|rate| format!("{rate} Hz"))
+                            .unwrap_or_else(|| "Device Default".into()),
+                    )
+                    .id("sample-rate")
+                    .build(ui, rows),
+            );
+
+            spacer(ui);
+
+            self::update_buffer_size(
+                ctx,
+                nuon::settings_row_spin()
+                    .title("Buffer Size")
+                    .subtitle(
+                        ctx.config
+                            .buffer_size()
+                            .map(|size| format!("{size} frames"))
+                            .unwrap_or_else(|| "Device Default".into()),
+                    )
+                    .id("buffer-size")
+                    .build(ui, rows),
+            );
         } else if is_midi {
             spacer(ui);
 
@@ -277,6 +453,109 @@ impl super::MenuScene {
                 ctx.config
                     .set_separate_channels(!ctx.config.separate_channels());
             }
+
+            spacer(ui);
+
+            if nuon::settings_row_toggler()
+                .title("Send MIDI Clock")
+                .subtitle("Let other devices follow the playback")
+                .value(ctx.config.clock_output())
+                .build(ui, rows)
+            {
+                ctx.config.set_clock_output(!ctx.config.clock_output());
+            }
+        }
+    }
+}
+
+enum LayerAction {
+    Raise(usize),
+    Remove(usize),
+}
+
+impl super::MenuScene {
+    fn settings_soundfont_layers(
+        &mut self,
+        ctx: &mut Context,
+        ui: &mut nuon::Ui,
+        rows: &dyn Fn(&mut nuon::Ui, nuon::SettingsRow<'_>),
+        spacer: &dyn Fn(&mut nuon::Ui),
+    ) {
+        let mut action = None;
+        let layers = ctx.config.soundfont_layers();
+
+        // Highest priority first
+        for (id, layer) in layers.iter().enumerate().rev() {
+            let is_top = id + 1 == layers.len();
+
+            spacer(ui);
+
+            nuon::settings_row()
+                .title(format!("Layer {}", id + 1))
+                .subtitle(file_name(layer))
+                .body(|ui, row_w, row_h| {
+                    let w = 70.0;
+                    let h = 31.0;
+                    let gap = 10.0;
+                    let y = nuon::center_y(row_h, h);
+
+                    if button()
+                        .id(nuon::Id::hash_with(|h| {
+                            "layer_remove".hash(h);
+                            id.hash(h);
+                        }))
+                        .x(row_w - w)
+                        .y(y)
+                        .size(w, h)
+                        .label("Remove")
+                        .build(ui)
+                    {
+                        action = Some(LayerAction::Remove(id));
+                    }
+
+                    if !is_top
+                        && button()
+                            .id(nuon::Id::hash_with(|h| {
+                                "layer_raise".hash(h);
+                                id.hash(h);
+                            }))
+                            .x(row_w - w * 2.0 - gap)
+                            .y(y)
+                            .size(w, h)
+                            .label("Raise")
+                            .build(ui)
+                    {
+                        action = Some(LayerAction::Raise(id));
+                    }
+                })
+                .build(ui, rows);
+        }
+
+        spacer(ui);
+
+        nuon::settings_row()
+            .title("SoundFont Layers")
+            .subtitle("Stacked over the SoundFont, the top layer takes priority")
+            .body(|ui, row_w, row_h| {
+                let w = 93.0;
+                let h = 31.0;
+                if button()
+                    .x(row_w - w)
+                    .y(nuon::center_y(row_h, h))
+                    .size(w, h)
+                    .label("Add Layer")
+                    .build(ui)
+                {
+                    self.futures
+                        .push(self::open_soundfont_layer_picker(&mut self.state));
+                }
+            })
+            .build(ui, rows);
+
+        match action {
+            Some(LayerAction::Raise(id)) => ctx.config.raise_soundfont_layer(id),
+            Some(LayerAction::Remove(id)) => ctx.config.remove_soundfont_layer(id),
+            None => {}
         }
     }
 }
@@ -344,12 +623,199 @@ impl super::MenuScene {
         ctx: &mut Context,
         ui: &mut nuon::Ui,
         rows: &dyn Fn(&mut nuon::Ui, nuon::SettingsRow<'_>),
-        _spacer: &dyn Fn(&mut nuon::Ui),
+        spacer: &dyn Fn(&mut nuon::Ui),
     ) {
         nuon::settings_row()
             .title("Input")
             .body(|ui, row_w, row_h| self.settings_input_picker(ui, ctx, row_w, row_h))
             .build(ui, rows);
+
+        spacer(ui);
+
+        nuon::settings_row()
+            .title("MIDI Monitor")
+            .subtitle("See what every input receives, and test the output")
+            .body(|ui, row_w, row_h| {
+                let w = 93.0;
+                let h = 31.0;
+                if button()
+                    .id("open_monitor")
+                    .x(row_w - w)
+                    .y(nuon::center_y(row_h, h))
+                    .size(w, h)
+                    .label("Open")
+                    .build(ui)
+                {
+                    state::monitor(&mut self.state, ctx);
+                }
+            })
+            .build(ui, rows);
+
+        // Other devices, like a pedal unit or pad controller, can be played along with the main input
+        let others = self
+            .state
+            .inputs
+            .iter()
+            .filter(|input| Some(*input) != self.state.selected_input.as_ref());
+
+        for input in others {
+            let name = input.to_string();
+            let enabled = ctx.config.extra_inputs().contains(&name);
+
+            spacer(ui);
+
+            if nuon::settings_row_toggler()
+                .id(nuon::Id::hash_with(|h| {
+                    "extra_input".hash(h);
+                    name.hash(h);
+                }))
+                .title(name.as_str())
+                .subtitle("Merge with the main input")
+                .value(enabled)
+                .build(ui, rows)
+            {
+                ctx.config.set_extra_input(&name, !enabled);
+            }
+        }
+
+        spacer(ui);
+
+        let network_port = ctx.config.network_input_port();
+        if nuon::settings_row_toggler()
+            .title("Network Input")
+            .subtitle(format!(
+                "Receive OSC on UDP port {}",
+                network_port.unwrap_or(DEFAULT_NETWORK_PORT)
+            ))
+            .value(network_port.is_some())
+            .build(ui, rows)
+        {
+            let port = match network_port {
+                Some(_) => None,
+                None => Some(DEFAULT_NETWORK_PORT),
+            };
+            ctx.config.set_network_input_port(port);
+            ctx.input_manager.set_network_input_port(port);
+        }
+
+        spacer(ui);
+
+        let network_output = ctx.config.network_output().map(str::to_owned);
+        if nuon::settings_row_toggler()
+            .title("Network Output")
+            .subtitle(format!(
+                "Send OSC to {}",
+                network_output.as_deref().unwrap_or(DEFAULT_NETWORK_OUTPUT)
+            ))
+            .value(network_output.is_some())
+            .build(ui, rows)
+        {
+            let addr = match network_output {
+                Some(_) => None,
+                None => Some(DEFAULT_NETWORK_OUTPUT.to_owned()),
+            };
+            ctx.output_manager.set_network_output(addr.as_deref());
+            ctx.config.set_network_output(addr);
+        }
+
+        spacer(ui);
+
+        self::update_input_channel(
+            ctx,
+            nuon::settings_row_spin()
+                .title("Input Channel")
+                .subtitle(
+                    ctx.config
+                        .input_channel()
+                        .map(|channel| format!("Channel {}", channel + 1))
+                        .unwrap_or_else(|| "All".into()),
+                )
+                .id("input-channel")
+                .build(ui, rows),
+        );
+
+        spacer(ui);
+
+        self::update_input_transpose(
+            ctx,
+            nuon::settings_row_spin()
+                .title("Transpose")
+                .subtitle(format!("{:+} semitones", ctx.config.input_transpose()))
+                .id("input-transpose")
+                .build(ui, rows),
+        );
+
+        spacer(ui);
+
+        self::update_input_octave(
+            ctx,
+            nuon::settings_row_spin()
+                .title("Octave Shift")
+                .subtitle(format!("{:+}", ctx.config.input_octave()))
+                .id("input-octave")
+                .build(ui, rows),
+        );
+
+        spacer(ui);
+
+        self::update_velocity_curve(
+            ctx,
+            nuon::settings_row_spin()
+                .title("Velocity Curve")
+                .subtitle(velocity_curve_label(ctx.config.velocity_curve()))
+                .id("velocity-curve")
+                .build(ui, rows),
+        );
+
+        spacer(ui);
+
+        self::update_keyboard_split(
+            ctx,
+            nuon::settings_row_spin()
+                .title("Keyboard Split")
+                .subtitle(keyboard_split_label(&ctx.config.split_zones()))
+                .id("keyboard-split")
+                .build(ui, rows),
+        );
+
+        spacer(ui);
+
+        let kind = nuon::settings_row_spin()
+            .title("Follow MIDI Clock")
+            .subtitle(ctx.config.clock_input().unwrap_or("Off").to_string())
+            .id("clock-input")
+            .build(ui, rows);
+        if !matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+            let names: Vec<String> = self.state.inputs.iter().map(|i| i.to_string()).collect();
+            let input = spin_list(&kind, &name_list(&names), ctx.config.clock_input());
+            ctx.config.set_clock_input(input.map(str::to_string));
+        }
+
+        spacer(ui);
+
+        let pc_keyboard = ctx.config.pc_keyboard();
+        let (enabled, hints) = (pc_keyboard.enabled, pc_keyboard.hints);
+        if nuon::settings_row_toggler()
+            .title("Computer Keyboard Piano")
+            .subtitle("Play with the A and Z rows, F5/F6 change the octave, F7/F8 the velocity")
+            .value(enabled)
+            .build(ui, rows)
+        {
+            ctx.config.set_pc_keyboard_enabled(!enabled);
+        }
+
+        if enabled {
+            spacer(ui);
+
+            if nuon::settings_row_toggler()
+                .title("Computer Keyboard Hints")
+                .subtitle("Show which computer key plays which piano key")
+                .value(hints)
+                .build(ui, rows)
+            {
+                ctx.config.set_pc_keyboard_hints(!hints);
+            }
+        }
     }
 }
 
@@ -415,6 +881,317 @@ pub fn update_audio_gain(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
 
     ctx.config
         .set_audio_gain((ctx.config.audio_gain() * 10.0).round() / 10.0);
+
+    if !matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        ctx.output_manager.set_synth_config(ctx.config.synth());
+    }
+}
+
+const SAMPLE_RATES: &[Option<u32>] = &[None, Some(22050), Some(44100), Some(48000), Some(96000)];
+
+const BUFFER_SIZES: &[Option<u32>] = &[
+    None,
+    Some(64),
+    Some(128),
+    Some(256),
+    Some(512),
+    Some(1024),
+    Some(2048),
+];
+
+/// UDP port that network MIDI is received on, unless changed in the settings file
+const DEFAULT_NETWORK_PORT: u16 = 9000;
+/// Address that network MIDI is sent to, unless changed in the settings file.
+/// Not the input port, so enabling both doesn't loop back into ourselves.
+const DEFAULT_NETWORK_OUTPUT: &str = "127.0.0.1:9001";
+
+const VELOCITY_CURVES: &[VelocityCurveV1] = &[
+    VelocityCurveV1::Linear,
+    VelocityCurveV1::Soft,
+    VelocityCurveV1::Hard,
+    VelocityCurveV1::Fixed(64),
+    VelocityCurveV1::Fixed(96),
+    VelocityCurveV1::Fixed(127),
+];
+
+/// Lowest and highest key that the keyboard can be split at
+const SPLIT_KEYS: (u8, u8) = (36, 84);
+
+/// Channel of the keys below the split point, the upper keys keep the channel of the input
+const SPLIT_LOWER_CHANNEL: u8 = 1;
+
+const INTERPOLATIONS: &[InterpolationV1] = &[
+    InterpolationV1::None,
+    InterpolationV1::Linear,
+    InterpolationV1::FourthOrder,
+    InterpolationV1::SeventhOrder,
+];
+
+/// Step `value` up or down, rounded to a multiple of `step`
+fn spin_f32(kind: &nuon::SettingsRowSpinResult, value: f32, step: f32) -> f32 {
+    let value = match kind {
+        nuon::SettingsRowSpinResult::Plus => value + step,
+        nuon::SettingsRowSpinResult::Minus => value - step,
+        nuon::SettingsRowSpinResult::Idle => value,
+    };
+    (value / step).round() * step
+}
+
+/// Move to the next or previous item of `list`, staying at the ends
+fn spin_list<T: PartialEq + Copy>(kind: &nuon::SettingsRowSpinResult, list: &[T], value: T) -> T {
+    let id = list.iter().position(|v| *v == value).unwrap_or(0);
+    let id = match kind {
+        nuon::SettingsRowSpinResult::Plus => (id + 1).min(list.len() - 1),
+        nuon::SettingsRowSpinResult::Minus => id.saturating_sub(1),
+        nuon::SettingsRowSpinResult::Idle => id,
+    };
+    list[id]
+}
+
+fn effect_level_label(level: f32) -> String {
+    if level > 0.0 {
+        format!("{level:.1}")
+    } else {
+        "Off".into()
+    }
+}
+
+fn velocity_curve_label(curve: VelocityCurveV1) -> String {
+    match curve {
+        VelocityCurveV1::Linear => "Linear".into(),
+        VelocityCurveV1::Soft => "Soft".into(),
+        VelocityCurveV1::Hard => "Hard".into(),
+        VelocityCurveV1::Fixed(vel) => format!("Fixed ({vel})"),
+    }
+}
+
+/// Split point of the zones set up by the "Keyboard Split" row
+fn split_point(zones: &[SplitZoneV1]) -> Option<u8> {
+    match zones {
+        [
+            SplitZoneV1 {
+                start: 0,
+                end,
+                channel: SPLIT_LOWER_CHANNEL,
+            },
+        ] => Some(end + 1),
+        _ => None,
+    }
+}
+
+fn keyboard_split_label(zones: &[SplitZoneV1]) -> String {
+    match split_point(zones) {
+        Some(key) => format!(
+            "At key {key}, lower keys on channel {}",
+            SPLIT_LOWER_CHANNEL + 1
+        ),
+        None if zones.is_empty() => "Off".into(),
+        // Zones edited by hand in the settings file
+        None => format!("{} zones", zones.len()),
+    }
+}
+
+fn interpolation_label(interpolation: InterpolationV1) -> &'static str {
+    match interpolation {
+        InterpolationV1::None => "None",
+        InterpolationV1::Linear => "Linear",
+        InterpolationV1::FourthOrder => "4th Order",
+        InterpolationV1::SeventhOrder => "7th Order",
+    }
+}
+
+pub fn update_reverb(
+    ctx: &mut Context,
+    f: impl FnOnce(&mut ReverbConfigV1, &nuon::SettingsRowSpinResult),
+    kind: nuon::SettingsRowSpinResult,
+) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    let mut reverb = *ctx.config.reverb();
+    f(&mut reverb, &kind);
+    ctx.config.set_reverb(reverb);
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_chorus(
+    ctx: &mut Context,
+    f: impl FnOnce(&mut ChorusConfigV1, &nuon::SettingsRowSpinResult),
+    kind: nuon::SettingsRowSpinResult,
+) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    let mut chorus = *ctx.config.chorus();
+    f(&mut chorus, &kind);
+    ctx.config.set_chorus(chorus);
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_polyphony(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    let polyphony = ctx.config.polyphony();
+    match kind {
+        nuon::SettingsRowSpinResult::Plus => {
+            ctx.config
+                .set_polyphony(polyphony.saturating_add(32).min(1024));
+        }
+        nuon::SettingsRowSpinResult::Minus => {
+            ctx.config
+                .set_polyphony(polyphony.saturating_sub(32).max(32));
+        }
+        nuon::SettingsRowSpinResult::Idle => return,
+    }
+
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_interpolation(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    ctx.config
+        .set_interpolation(spin_list(&kind, INTERPOLATIONS, ctx.config.interpolation()));
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_input_channel(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    let channels: Vec<Option<u8>> = std::iter::once(None).chain((0..16).map(Some)).collect();
+    ctx.config
+        .set_input_channel(spin_list(&kind, &channels, ctx.config.input_channel()));
+}
+
+pub fn update_input_transpose(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    let step = match kind {
+        nuon::SettingsRowSpinResult::Plus => 1,
+        nuon::SettingsRowSpinResult::Minus => -1,
+        nuon::SettingsRowSpinResult::Idle => return,
+    };
+    ctx.config
+        .set_input_transpose(ctx.config.input_transpose() + step);
+}
+
+pub fn update_input_octave(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    let step = match kind {
+        nuon::SettingsRowSpinResult::Plus => 1,
+        nuon::SettingsRowSpinResult::Minus => -1,
+        nuon::SettingsRowSpinResult::Idle => return,
+    };
+    ctx.config
+        .set_input_octave(ctx.config.input_octave() + step);
+}
+
+pub fn update_velocity_curve(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    ctx.config.set_velocity_curve(spin_list(
+        &kind,
+        VELOCITY_CURVES,
+        ctx.config.velocity_curve(),
+    ));
+}
+
+pub fn update_keyboard_split(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    let (min, max) = SPLIT_KEYS;
+    let points: Vec<Option<u8>> = std::iter::once(None).chain((min..=max).map(Some)).collect();
+    let point = spin_list(&kind, &points, split_point(&ctx.config.split_zones()));
+
+    let zones = point
+        .map(|key| SplitZoneV1 {
+            start: 0,
+            end: key - 1,
+            channel: SPLIT_LOWER_CHANNEL,
+        })
+        .into_iter()
+        .collect();
+    ctx.config.set_split_zones(zones);
+}
+
+/// `None` followed by the names, as spin list of an audio host or device
+fn name_list(names: &[String]) -> Vec<Option<&str>> {
+    std::iter::once(None)
+        .chain(names.iter().map(|name| Some(name.as_str())))
+        .collect()
+}
+
+pub fn update_audio_host(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    // Hosts are only listed on click, enumerating them every frame is too slow
+    let hosts = ctx.output_manager.audio_hosts();
+    let host = spin_list(&kind, &name_list(&hosts), ctx.config.audio_host()).map(str::to_string);
+    ctx.config.set_audio_host(host);
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_audio_device(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    let devices = ctx.output_manager.audio_devices(ctx.config.audio_host());
+    let device =
+        spin_list(&kind, &name_list(&devices), ctx.config.audio_device()).map(str::to_string);
+    ctx.config.set_audio_device(device);
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_sample_rate(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    ctx.config
+        .set_sample_rate(spin_list(&kind, SAMPLE_RATES, ctx.config.sample_rate()));
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_buffer_size(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    if matches!(kind, nuon::SettingsRowSpinResult::Idle) {
+        return;
+    }
+
+    ctx.config
+        .set_buffer_size(spin_list(&kind, BUFFER_SIZES, ctx.config.buffer_size()));
+    ctx.output_manager.set_synth_config(ctx.config.synth());
+}
+
+pub fn update_loop_practice_target(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    match kind {
+        nuon::SettingsRowSpinResult::Plus => {
+            ctx.config
+                .set_loop_practice_target(ctx.config.loop_practice_target() + 1);
+        }
+        nuon::SettingsRowSpinResult::Minus => {
+            ctx.config
+                .set_loop_practice_target(ctx.config.loop_practice_target().saturating_sub(1));
+        }
+        nuon::SettingsRowSpinResult::Idle => {}
+    }
+}
+
+pub fn update_loop_practice_speed_step(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
+    match kind {
+        nuon::SettingsRowSpinResult::Plus => {
+            ctx.config
+                .set_loop_practice_speed_step(ctx.config.loop_practice_speed_step() + 0.05);
+        }
+        nuon::SettingsRowSpinResult::Minus => {
+            ctx.config
+                .set_loop_practice_speed_step(ctx.config.loop_practice_speed_step() - 0.05);
+        }
+        nuon::SettingsRowSpinResult::Idle => {}
+    }
+
+    ctx.config.set_loop_practice_speed_step(
+        (ctx.config.loop_practice_speed_step() * 100.0).round() / 100.0,
+    );
 }
 
 pub fn update_range_start(ctx: &mut Context, kind: nuon::SettingsRowSpinResult) {
@@ -459,9 +1236,25 @@ pub fn open_soundfont_picker(data: &mut UiState) -> BoxFuture<MsgFn> {
     })
 }
 
+pub fn open_soundfont_layer_picker(data: &mut UiState) -> BoxFuture<MsgFn> {
+    data.is_loading = true;
+    on_async(open_sondfont_picker_fut(), |res, data, ctx| {
+        if let Some(font) = res {
+            ctx.config.add_soundfont_layer(font);
+        }
+        data.is_loading = false;
+    })
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
 async fn open_sondfont_picker_fut() -> Option<PathBuf> {
     let file = rfd::AsyncFileDialog::new()
-        .add_filter("SoundFont2", &["sf2"])
+        .add_filter("SoundFont", &["sf2", "sf3", "sfz"])
         .pick_file()
         .await;
 
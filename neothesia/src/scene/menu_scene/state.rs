@@ -1,6 +1,9 @@
 use std::collections::VecDeque;
 
-use crate::{NeothesiaEvent, context::Context, output_manager::OutputDescriptor, song::Song};
+use crate::{
+    NeothesiaEvent, context::Context, input_manager::InputProcessing,
+    output_manager::OutputDescriptor, song::Song,
+};
 
 type InputDescriptor = midi_io::MidiInputPort;
 
@@ -85,16 +88,20 @@ impl UiState {
             }
         }
 
-        if self.selected_input.is_none() {
-            if let Some(input) = self
-                .inputs
-                .iter()
-                .find(|input| Some(input.to_string().as_str()) == ctx.config.input())
-            {
-                self.selected_input = Some(input.clone());
-            } else {
-                self.selected_input = self.inputs.first().cloned();
-            }
+        // The configured input takes over again once it is plugged back in
+        let configured = self
+            .inputs
+            .iter()
+            .find(|input| Some(input.to_string().as_str()) == ctx.config.input());
+        let unplugged = self
+            .selected_input
+            .as_ref()
+            .is_some_and(|input| !self.inputs.contains(input));
+
+        if let Some(input) = configured {
+            self.selected_input = Some(input.clone());
+        } else if self.selected_input.is_none() || unplugged {
+            self.selected_input = self.inputs.first().cloned();
         }
     }
 }
@@ -105,27 +112,60 @@ pub enum Page {
     Main,
     Settings,
     TrackSelection,
+    Diagnostics,
+}
+
+fn resolve_output(out: OutputDescriptor, ctx: &Context) -> OutputDescriptor {
+    match out {
+        #[cfg(feature = "synth")]
+        OutputDescriptor::Synth(_) => OutputDescriptor::Synth(ctx.config.soundfont_stack()),
+        o => o,
+    }
 }
 
 fn connect_io(data: &UiState, ctx: &mut Context) {
-    if let Some(out) = data.selected_output.clone() {
-        let out = match out {
-            #[cfg(feature = "synth")]
-            OutputDescriptor::Synth(_) => {
-                OutputDescriptor::Synth(ctx.config.soundfont_path().cloned())
-            }
-            o => o,
-        };
+    // Before connecting, so that new synth streams open with the right sample rate
+    ctx.output_manager.set_synth_config(ctx.config.synth());
 
+    if let Some(out) = data.selected_output.clone() {
+        let out = resolve_output(out, ctx);
         ctx.output_manager.connect(out);
-        ctx.output_manager
-            .connection()
-            .set_gain(ctx.config.audio_gain());
     }
 
-    if let Some(port) = data.selected_input.clone() {
-        ctx.input_manager.connect_input(port);
+    // Open outputs that tracks are routed to, and close the ones no longer in use
+    let routed: Vec<&str> = data
+        .song
+        .iter()
+        .flat_map(|song| song.config.tracks.iter())
+        .filter_map(|track| track.output.as_deref())
+        .collect();
+
+    ctx.output_manager
+        .retain_extra(|desc| routed.contains(&desc.to_string().as_str()));
+
+    for out in data.outputs.iter() {
+        if out.is_not_dummy() && routed.contains(&out.to_string().as_str()) {
+            let out = resolve_output(out.clone(), ctx);
+            ctx.output_manager.connect_extra(out);
+        }
     }
+
+    // Extra inputs that are plugged in, merged with the main one
+    let extra = data.inputs.iter().filter(|input| {
+        Some(*input) != data.selected_input.as_ref()
+            && ctx.config.extra_inputs().contains(&input.to_string())
+    });
+    let mut ports: Vec<_> = data.selected_input.iter().chain(extra).cloned().collect();
+    // The input that playback follows the clock of, if it's not one of the above
+    let clock = data.inputs.iter().find(|input| {
+        ctx.config.clock_input() == Some(input.to_string().as_str()) && !ports.contains(*input)
+    });
+    ports.extend(clock.cloned());
+
+    ctx.input_manager.connect_inputs(ports);
+    ctx.input_manager.set_clock_source(ctx.config.clock_input());
+    ctx.input_manager
+        .set_processing(InputProcessing::from_config(&ctx.config));
 }
 
 pub fn play(data: &UiState, ctx: &mut Context) {
@@ -147,3 +187,23 @@ pub fn freeplay(data: &UiState, ctx: &mut Context) {
         .send_event(NeothesiaEvent::FreePlay(data.song.clone()))
         .ok();
 }
+
+/// Open the MIDI monitor, listening to every input as it comes from the device
+pub fn monitor(data: &mut UiState, ctx: &mut Context) {
+    connect_io(data, ctx);
+
+    ctx.input_manager.connect_inputs(data.inputs.clone());
+    ctx.input_manager.set_processing(InputProcessing::default());
+    ctx.input_manager.set_monitor_system_messages(true);
+
+    data.go_to(Page::Diagnostics);
+}
+
+/// Go to the previous page, putting the inputs back as configured when leaving the MIDI monitor
+pub fn go_back(data: &mut UiState, ctx: &mut Context) {
+    if *data.current() == Page::Diagnostics {
+        ctx.input_manager.set_monitor_system_messages(false);
+        connect_io(data, ctx);
+    }
+    data.go_back();
+}
@@ -1,16 +1,20 @@
 use midi_file::MidiTrack;
 use nuon::TextJustify;
-use std::hash::Hash;
+use std::{
+    hash::Hash,
+    path::{Path, PathBuf},
+};
 
 use crate::{
     context::Context,
+    output_manager::OutputDescriptor,
     song::{PlayerConfig, TrackConfig},
 };
 
 use super::{icons, neo_btn_icon, state};
 
 pub const CARD_W: f32 = 344.0;
-pub const CARD_H: f32 = 126.0;
+pub const CARD_H: f32 = 202.0;
 
 impl super::MenuScene {
     pub fn tracks_page_ui(&mut self, ctx: &mut Context, ui: &mut nuon::Ui) {
@@ -48,6 +52,8 @@ impl super::MenuScene {
         });
 
         if let Some(song) = self.state.song.as_mut() {
+            let mut tracks_changed = false;
+
             self.tracks_scroll = nuon::scroll()
                 .scissor_size(win_w, (win_h - bottom_bar_h).max(0.0))
                 .scroll(self.tracks_scroll)
@@ -89,12 +95,35 @@ impl super::MenuScene {
                                         }),
                                         track,
                                         config,
+                                        &self.state.outputs,
                                     ) {
                                         TrackCardEvent::PlayerConfig(player) => {
                                             song.config.tracks[track.track_id].player = player;
+                                            tracks_changed = true;
                                         }
                                         TrackCardEvent::SetVisible(visible) => {
                                             song.config.tracks[track.track_id].visible = visible;
+                                            tracks_changed = true;
+                                        }
+                                        TrackCardEvent::SetOutput(output) => {
+                                            song.config.tracks[track.track_id].output = output;
+                                            tracks_changed = true;
+                                        }
+                                        TrackCardEvent::SetVolume(volume) => {
+                                            song.config.tracks[track.track_id].volume = volume;
+                                            tracks_changed = true;
+                                        }
+                                        TrackCardEvent::SetProgram(program) => {
+                                            song.config.tracks[track.track_id].program = program;
+                                            tracks_changed = true;
+                                        }
+                                        TrackCardEvent::SetSolo(solo) => {
+                                            song.config.tracks[track.track_id].solo = solo;
+                                            tracks_changed = true;
+                                        }
+                                        TrackCardEvent::SetSoundFont(font) => {
+                                            song.config.tracks[track.track_id].soundfont = font;
+                                            tracks_changed = true;
                                         }
                                         TrackCardEvent::Idle => {}
                                     }
@@ -110,6 +139,10 @@ impl super::MenuScene {
                         }
                     }
                 });
+
+            if tracks_changed {
+                song.save_tracks_config(&mut ctx.config);
+            }
         }
     }
 }
@@ -152,15 +185,93 @@ impl CardsLayout {
 enum TrackCardEvent {
     PlayerConfig(PlayerConfig),
     SetVisible(bool),
+    SetOutput(Option<String>),
+    SetVolume(f32),
+    SetProgram(Option<u8>),
+    SetSolo(bool),
+    SetSoundFont(Option<PathBuf>),
     Idle,
 }
 
+/// Program override cycle: `None` (program from the file) -> 0 -> ... -> 127 -> `None`
+fn step_program(program: Option<u8>, forward: bool) -> Option<u8> {
+    match (program, forward) {
+        (None, true) => Some(0),
+        (None, false) => Some(127),
+        (Some(127), true) | (Some(0), false) => None,
+        (Some(p), true) => Some(p + 1),
+        (Some(p), false) => Some(p - 1),
+    }
+}
+
+/// Move volume by 10% steps, snapping to the grid so that float errors don't accumulate
+fn step_volume(volume: f32, direction: f32) -> f32 {
+    (((volume * 10.0).round() + direction) / 10.0).clamp(0.0, 1.0)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn small_button(
+    ui: &mut nuon::Ui,
+    id: nuon::Id,
+    name: &str,
+    x: f32,
+    w: f32,
+    label: impl Into<std::borrow::Cow<'static, str>>,
+    color: nuon::Color,
+    hover_color: nuon::Color,
+) -> bool {
+    nuon::button()
+        .id(nuon::Id::hash_with(|h| {
+            id.as_raw().hash(h);
+            name.hash(h);
+        }))
+        .x(x)
+        .size(w, 28.0)
+        .color(color)
+        .hover_color(hover_color)
+        .preseed_color(color)
+        .border_radius([8.0; 4])
+        .label(label)
+        .build(ui)
+}
+
+/// Output that comes after `current` in the selector, `None` standing for the default output
+fn next_output(outputs: &[OutputDescriptor], current: Option<&str>) -> Option<String> {
+    let names: Vec<String> = outputs
+        .iter()
+        .filter(|o| o.is_not_dummy())
+        .map(|o| o.to_string())
+        .collect();
+
+    match current {
+        None => names.first().cloned(),
+        Some(current) => names
+            .iter()
+            .position(|name| name == current)
+            .and_then(|id| names.get(id + 1))
+            .cloned(),
+    }
+}
+
+/// SoundFont that comes after `current` in the stack, `None` standing for the whole stack
+fn next_soundfont(fonts: &[PathBuf], current: Option<&Path>) -> Option<PathBuf> {
+    match current {
+        None => fonts.first().cloned(),
+        Some(current) => fonts
+            .iter()
+            .position(|font| font == current)
+            .and_then(|id| fonts.get(id + 1))
+            .cloned(),
+    }
+}
+
 fn track_card(
     ctx: &Context,
     ui: &mut nuon::Ui,
     id: impl Into<nuon::Id>,
     track: &MidiTrack,
     config: &TrackConfig,
+    outputs: &[OutputDescriptor],
 ) -> TrackCardEvent {
     let card_w = CARD_W;
     let card_h = CARD_H;
@@ -180,11 +291,13 @@ fn track_card(
     let title = if track.has_drums && !track.has_other_than_drums {
         "Percussion"
     } else {
-        let instrument_id = track
-            .programs
-            .last()
-            .map(|p| p.program as usize)
-            .unwrap_or(0);
+        let instrument_id = config.program.map(|p| p as usize).unwrap_or_else(|| {
+            track
+                .programs
+                .last()
+                .map(|p| p.program as usize)
+                .unwrap_or(0)
+        });
         midi_file::INSTRUMENT_NAMES[instrument_id]
     };
 
@@ -232,10 +345,29 @@ fn track_card(
 
         let btn_w = inner_card_w / 3.0;
 
+        let output_w = 110.0;
+        let output_label = config.output.as_deref().unwrap_or("Default").to_string();
+        if nuon::button()
+            .id(nuon::Id::hash_with(|h| {
+                id.as_raw().hash(h);
+                "output".hash(h);
+            }))
+            .x(inner_card_w - output_w)
+            .size(output_w, 24.0)
+            .color(regular)
+            .hover_color(regular_hover)
+            .preseed_color(regular)
+            .border_radius([12.0; 4])
+            .label(output_label)
+            .build(ui)
+        {
+            res = TrackCardEvent::SetOutput(next_output(outputs, config.output.as_deref()));
+        }
+
         let labels_x = icon_size + 15.0;
         nuon::translate().x(labels_x).build(ui, |ui| {
             let label_h = icon_size / 2.0;
-            let label_w = inner_card_w - labels_x;
+            let label_w = inner_card_w - labels_x - output_w;
 
             nuon::label()
                 .size(label_w, label_h)
@@ -316,6 +448,88 @@ fn track_card(
                 res = TrackCardEvent::PlayerConfig(PlayerConfig::Human);
             }
         });
+
+        nuon::translate()
+            .y(icon_size + 15.0 + 40.0 + 10.0)
+            .build(ui, |ui| {
+                let gap = 8.0;
+                let arrow_w = 28.0;
+
+                let solo_color = if config.solo { accent } else { regular };
+                let solo_hover = if config.solo {
+                    accent_hover
+                } else {
+                    regular_hover
+                };
+                let solo_w = 60.0;
+                if small_button(ui, id, "solo", 0.0, solo_w, "Solo", solo_color, solo_hover) {
+                    res = TrackCardEvent::SetSolo(!config.solo);
+                }
+
+                let x = solo_w + gap;
+                let volume_label_w = 70.0;
+                if small_button(ui, id, "vol_down", x, arrow_w, "-", regular, regular_hover) {
+                    res = TrackCardEvent::SetVolume(step_volume(config.volume, -1.0));
+                }
+                nuon::label()
+                    .x(x + arrow_w)
+                    .size(volume_label_w, 28.0)
+                    .text(format!("Vol {}%", (config.volume * 100.0).round()))
+                    .font_size(13.0)
+                    .build(ui);
+                let x = x + arrow_w + volume_label_w;
+                if small_button(ui, id, "vol_up", x, arrow_w, "+", regular, regular_hover) {
+                    res = TrackCardEvent::SetVolume(step_volume(config.volume, 1.0));
+                }
+
+                let x = x + arrow_w + gap;
+                let program_label_w = inner_card_w - x - arrow_w * 2.0;
+                if small_button(ui, id, "prog_prev", x, arrow_w, "<", regular, regular_hover) {
+                    res = TrackCardEvent::SetProgram(step_program(config.program, false));
+                }
+                nuon::label()
+                    .x(x + arrow_w)
+                    .size(program_label_w, 28.0)
+                    .text(match config.program {
+                        Some(p) => format!("#{p}"),
+                        None => "File".to_string(),
+                    })
+                    .font_size(13.0)
+                    .build(ui);
+                let x = x + arrow_w + program_label_w;
+                if small_button(ui, id, "prog_next", x, arrow_w, ">", regular, regular_hover) {
+                    res = TrackCardEvent::SetProgram(step_program(config.program, true));
+                }
+            });
+
+        nuon::translate()
+            .y(icon_size + 15.0 + 40.0 + 10.0 + 28.0 + 10.0)
+            .build(ui, |ui| {
+                let font_label = match config.soundfont.as_deref() {
+                    Some(font) => format!(
+                        "Font: {}",
+                        font.file_name()
+                            .map(|name| name.to_string_lossy())
+                            .unwrap_or_default()
+                    ),
+                    None => "Font: All Layers".to_string(),
+                };
+                if small_button(
+                    ui,
+                    id,
+                    "soundfont",
+                    0.0,
+                    inner_card_w,
+                    font_label,
+                    regular,
+                    regular_hover,
+                ) {
+                    res = TrackCardEvent::SetSoundFont(next_soundfont(
+                        &ctx.config.soundfont_stack(),
+                        config.soundfont.as_deref(),
+                    ));
+                }
+            });
     });
 
     res
@@ -2,82 +2,53 @@ pub mod freeplay;
 pub mod menu_scene;
 pub mod playing_scene;
 
+mod pc_keyboard;
+
 use crate::{
-    NeothesiaEvent, context::Context, scene::playing_scene::Keyboard, utils::window::WinitEvent,
+    MidiDeviceEvent, NeothesiaEvent,
+    context::Context,
+    input_manager::{MidiInputEvent, SystemMidiEvent, TransportEvent},
+    output_manager::{AudioRecorderError, AudioRecording},
+    scene::playing_scene::Keyboard,
+    utils::window::WinitEvent,
 };
 use midi_file::midly::MidiMessage;
 use neothesia_core::render::{Image, ImageIdentifier, ImageRenderer, QuadRenderer, TextRenderer};
 use std::{collections::HashMap, time::Duration};
 use winit::{
     dpi::{LogicalPosition, LogicalSize},
-    event::{ElementState, KeyEvent, WindowEvent},
-    keyboard::Key,
+    event::{TouchPhase, WindowEvent},
 };
 
 pub trait Scene {
     fn update(&mut self, ctx: &mut Context, delta: Duration);
     fn render<'pass>(&'pass mut self, rpass: &mut wgpu_jumpstart::RenderPass<'pass>);
     fn window_event(&mut self, _ctx: &mut Context, _event: &WindowEvent) {}
-    fn midi_event(&mut self, _ctx: &mut Context, _channel: u8, _message: &MidiMessage) {}
+    fn midi_event(&mut self, _ctx: &mut Context, _event: &MidiInputEvent) {}
+    fn transport_event(&mut self, _ctx: &mut Context, _event: &TransportEvent) {}
+    fn system_midi_event(&mut self, _ctx: &mut Context, _event: &SystemMidiEvent) {}
+    fn midi_device_event(&mut self, _ctx: &mut Context, _event: &MidiDeviceEvent) {}
+    /// The audio recording was finished without the user asking for it
+    fn audio_recording_stopped(
+        &mut self,
+        _ctx: &mut Context,
+        _result: Result<AudioRecording, AudioRecorderError>,
+    ) {
+    }
 }
 
-pub fn handle_pc_keyboard_to_midi_event(ctx: &mut Context, event: &WindowEvent) {
-    let WindowEvent::KeyboardInput {
-        event:
-            KeyEvent {
-                state,
-                logical_key: Key::Character(ch),
-                repeat: false,
-                ..
-            },
-        ..
-    } = event
-    else {
-        return;
-    };
-
-    let mut note: u8 = match ch.as_str() {
-        "a" => 0,
-        "w" => 1,
-        "s" => 2,
-        "e" => 3,
-        "d" => 4,
-        "f" => 5,
-        "t" => 6,
-        "g" => 7,
-        "y" => 8,
-        "h" => 9,
-        "u" => 10,
-        "j" => 11,
-        "k" => 12,
-        "o" => 13,
-        "l" => 14,
-        "p" => 15,
-        ";" => 16,
-        "'" => 17,
-        _ => return,
-    };
-
-    note += 21; // Start of 88 keyboard
-    note += 3; // Offset to C
-    note += 12 * 3; // Move 3oct up
-
-    let message = match state {
-        ElementState::Pressed => MidiMessage::NoteOn {
-            key: note.into(),
-            vel: 100.into(),
-        },
-        ElementState::Released => MidiMessage::NoteOff {
-            key: note.into(),
-            vel: 0.into(),
-        },
-    };
-    ctx.proxy
-        .send_event(NeothesiaEvent::MidiInput {
-            channel: 0,
-            message,
-        })
-        .ok();
+fn handle_pc_keyboard_to_midi_event(
+    pc_keyboard: &mut pc_keyboard::PcKeyboard,
+    keyboard: &mut Keyboard,
+    ctx: &mut Context,
+    event: &WindowEvent,
+) -> pc_keyboard::PcKeyboardInput {
+    let input = pc_keyboard.handle_window_event(ctx, keyboard.range(), event);
+    if input != pc_keyboard::PcKeyboardInput::Ignored {
+        // Octave changed, or a key showed its real label
+        keyboard.set_key_hints(pc_keyboard.hints());
+    }
+    input
 }
 
 #[derive(Default, Debug)]
@@ -85,47 +56,41 @@ struct MouseToMidiEventState {
     mouse_key_press: Option<u8>,
 }
 
-fn handle_mouse_to_midi_event(
-    keyboard: &mut Keyboard,
-    state: &mut MouseToMidiEventState,
-    ctx: &Context,
-    event: &WindowEvent,
-) {
-    if !(event.left_mouse_pressed() || event.left_mouse_released() || event.cursor_moved()) {
-        return;
-    }
-
-    fn cancel_mouse_key_press(state: &mut MouseToMidiEventState, ctx: &Context) {
-        let Some(key) = state.mouse_key_press else {
-            return;
-        };
-
-        state.mouse_key_press = None;
+fn send_local_midi_event(ctx: &Context, message: MidiMessage) {
+    ctx.proxy
+        .send_event(NeothesiaEvent::MidiInput(MidiInputEvent::local(0, message)))
+        .ok();
+}
 
-        let message = MidiMessage::NoteOff {
+fn note_off(ctx: &Context, key: u8) {
+    send_local_midi_event(
+        ctx,
+        MidiMessage::NoteOff {
             key: key.into(),
             vel: 0.into(),
-        };
-        ctx.proxy
-            .send_event(NeothesiaEvent::MidiInput {
-                channel: 0,
-                message,
-            })
-            .ok();
-    }
+        },
+    );
+}
+
+fn note_on(ctx: &Context, key: u8, vel: u8) {
+    send_local_midi_event(
+        ctx,
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        },
+    );
+}
 
+/// Piano key under `pos`, and how far down the key it is (0.0 at the top, 1.0 at the front edge)
+fn keyboard_key_at(keyboard: &Keyboard, pos: nuon::Point) -> Option<(u8, f32)> {
     let bbox = nuon::Rect::new(
         (keyboard.pos().x, keyboard.pos().y).into(),
         (keyboard.layout().width, keyboard.layout().height).into(),
     );
-    let mouse_pos = nuon::Point::new(
-        ctx.window_state.cursor_logical_position.x,
-        ctx.window_state.cursor_logical_position.y,
-    );
 
-    if !bbox.contains(mouse_pos) || !ctx.window_state.left_mouse_btn {
-        cancel_mouse_key_press(state, ctx);
-        return;
+    if !bbox.contains(pos) {
+        return None;
     }
 
     let sharp = keyboard
@@ -140,33 +105,130 @@ fn handle_mouse_to_midi_event(
         .filter(|key| key.kind().is_neutral());
 
     for key in sharp.chain(neutral) {
-        let pos = nuon::Point::new(key.x(), keyboard.pos().y);
+        let key_pos = nuon::Point::new(key.x(), keyboard.pos().y);
         let size = nuon::Size::from(key.size());
-        let rect = nuon::Rect::new(pos, size);
-        if !rect.contains(mouse_pos) {
+        let rect = nuon::Rect::new(key_pos, size);
+        if !rect.contains(pos) {
             continue;
         }
 
-        let key = keyboard.layout().range.start() + key.id() as u8;
+        let depth = ((pos.y - key_pos.y) / size.height).clamp(0.0, 1.0);
+        return Some((keyboard.layout().range.start() + key.id() as u8, depth));
+    }
+
+    None
+}
 
-        if Some(key) == state.mouse_key_press {
-            return;
+fn handle_mouse_to_midi_event(
+    keyboard: &mut Keyboard,
+    state: &mut MouseToMidiEventState,
+    ctx: &Context,
+    event: &WindowEvent,
+) {
+    if !(event.left_mouse_pressed() || event.left_mouse_released() || event.cursor_moved()) {
+        return;
+    }
+
+    fn cancel_mouse_key_press(state: &mut MouseToMidiEventState, ctx: &Context) {
+        if let Some(key) = state.mouse_key_press.take() {
+            note_off(ctx, key);
         }
+    }
+
+    let mouse_pos = nuon::Point::new(
+        ctx.window_state.cursor_logical_position.x,
+        ctx.window_state.cursor_logical_position.y,
+    );
 
+    let key = keyboard_key_at(keyboard, mouse_pos).map(|(key, _)| key);
+    let (Some(key), true) = (key, ctx.window_state.left_mouse_btn) else {
         cancel_mouse_key_press(state, ctx);
-        state.mouse_key_press = Some(key);
+        return;
+    };
 
-        let message = MidiMessage::NoteOn {
-            key: key.into(),
-            vel: 100.into(),
+    if Some(key) == state.mouse_key_press {
+        return;
+    }
+
+    cancel_mouse_key_press(state, ctx);
+    state.mouse_key_press = Some(key);
+    note_on(ctx, key, 100);
+}
+
+/// Softest note of a touch, at the top of the key
+const TOUCH_MIN_VELOCITY: f32 = 30.0;
+
+/// Key held by each finger on the on-screen keyboard
+#[derive(Default, Debug)]
+struct TouchToMidiEventState {
+    touches: HashMap<u64, u8>,
+}
+
+impl TouchToMidiEventState {
+    /// Move a finger, returning the notes to send.
+    ///
+    /// `key_at` looks up the key under the finger, and how far down the key it is.
+    fn touch(
+        &mut self,
+        id: u64,
+        phase: TouchPhase,
+        key_at: impl FnOnce() -> Option<(u8, f32)>,
+    ) -> Vec<MidiMessage> {
+        let key = match phase {
+            TouchPhase::Started | TouchPhase::Moved => key_at(),
+            TouchPhase::Ended | TouchPhase::Cancelled => None,
         };
-        ctx.proxy
-            .send_event(NeothesiaEvent::MidiInput {
-                channel: 0,
-                message,
-            })
-            .ok();
+
+        let held = self.touches.get(&id).copied();
+        if held.is_some() && held == key.map(|(key, _)| key) {
+            return Vec::new();
+        }
+
+        let mut messages = Vec::new();
+        if let Some(held) = held {
+            self.touches.remove(&id);
+            // Another finger can still be holding the same key
+            if !self.touches.values().any(|key| *key == held) {
+                messages.push(MidiMessage::NoteOff {
+                    key: held.into(),
+                    vel: 0.into(),
+                });
+            }
+        }
+
+        if let Some((key, depth)) = key {
+            let vel = TOUCH_MIN_VELOCITY + depth * (127.0 - TOUCH_MIN_VELOCITY);
+            self.touches.insert(id, key);
+            messages.push(MidiMessage::NoteOn {
+                key: key.into(),
+                vel: (vel.round() as u8).into(),
+            });
+        }
+        messages
+    }
+}
+
+/// Every touch plays its own key, so chords can be played on touchscreens.
+///
+/// Touches further down the key play louder, like hitting a real key closer to its front edge.
+/// Sliding a finger to another key plays a glissando.
+fn handle_touch_to_midi_event(
+    keyboard: &mut Keyboard,
+    state: &mut TouchToMidiEventState,
+    ctx: &Context,
+    event: &WindowEvent,
+) {
+    let WindowEvent::Touch(touch) = event else {
         return;
+    };
+
+    let pos = touch
+        .location
+        .to_logical::<f32>(ctx.window_state.scale_factor);
+    let pos = nuon::Point::new(pos.x, pos.y);
+
+    for message in state.touch(touch.id, touch.phase, || keyboard_key_at(keyboard, pos)) {
+        send_local_midi_event(ctx, message);
     }
 }
 
@@ -326,3 +388,105 @@ fn render_nuon(ui: &mut nuon::Ui, nuon_renderer: &mut NuonRenderer, ctx: &mut Co
 
     ui.done();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(key: u8, vel: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        }
+    }
+
+    fn note_off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        }
+    }
+
+    #[test]
+    fn touches_are_tracked_separately() {
+        let mut state = TouchToMidiEventState::default();
+
+        assert_eq!(
+            state.touch(1, TouchPhase::Started, || Some((60, 1.0))),
+            vec![note_on(60, 127)]
+        );
+        assert_eq!(
+            state.touch(2, TouchPhase::Started, || Some((64, 1.0))),
+            vec![note_on(64, 127)]
+        );
+        // Staying on the same key doesn't retrigger it
+        assert!(
+            state
+                .touch(1, TouchPhase::Moved, || Some((60, 0.5)))
+                .is_empty()
+        );
+
+        assert_eq!(
+            state.touch(2, TouchPhase::Ended, || Some((64, 1.0))),
+            vec![note_off(64)]
+        );
+        assert_eq!(
+            state.touch(1, TouchPhase::Cancelled, || None),
+            vec![note_off(60)]
+        );
+        assert!(state.touches.is_empty());
+    }
+
+    #[test]
+    fn key_shared_by_two_fingers() {
+        let mut state = TouchToMidiEventState::default();
+
+        state.touch(1, TouchPhase::Started, || Some((60, 1.0)));
+        state.touch(2, TouchPhase::Started, || Some((60, 1.0)));
+
+        // The key is released with the last finger on it
+        assert!(state.touch(1, TouchPhase::Ended, || None).is_empty());
+        assert_eq!(
+            state.touch(2, TouchPhase::Ended, || None),
+            vec![note_off(60)]
+        );
+    }
+
+    #[test]
+    fn glissando() {
+        let mut state = TouchToMidiEventState::default();
+
+        state.touch(1, TouchPhase::Started, || Some((60, 1.0)));
+        assert_eq!(
+            state.touch(1, TouchPhase::Moved, || Some((62, 1.0))),
+            vec![note_off(60), note_on(62, 127)]
+        );
+        // Sliding off the keyboard releases the key
+        assert_eq!(
+            state.touch(1, TouchPhase::Moved, || None),
+            vec![note_off(62)]
+        );
+        assert_eq!(
+            state.touch(1, TouchPhase::Moved, || Some((64, 1.0))),
+            vec![note_on(64, 127)]
+        );
+    }
+
+    #[test]
+    fn velocity_from_depth() {
+        let mut state = TouchToMidiEventState::default();
+
+        assert_eq!(
+            state.touch(1, TouchPhase::Started, || Some((60, 0.0))),
+            vec![note_on(60, TOUCH_MIN_VELOCITY as u8)]
+        );
+        assert_eq!(
+            state.touch(2, TouchPhase::Started, || Some((62, 0.5))),
+            vec![note_on(62, 79)]
+        );
+        assert_eq!(
+            state.touch(3, TouchPhase::Started, || Some((64, 1.0))),
+            vec![note_on(64, 127)]
+        );
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{BTreeMap, HashMap};
+
+use neothesia_core::{config::PcKeyboardConfigV1, piano_layout::KeyboardRange};
+use winit::{
+    event::{ElementState, KeyEvent, WindowEvent},
+    keyboard::{Key, KeyCode, PhysicalKey},
+};
+
+use crate::context::Context;
+
+const VELOCITY_STEP: u8 = 10;
+
+/// Key codes that can be used in the config, matched by their `Debug` name
+const KEY_CODES: &[KeyCode] = &[
+    KeyCode::Backquote,
+    KeyCode::Backslash,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::Comma,
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::Equal,
+    KeyCode::IntlBackslash,
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Quote,
+    KeyCode::Semicolon,
+    KeyCode::Slash,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::NumpadAdd,
+    KeyCode::NumpadSubtract,
+];
+
+fn parse_key_code(name: &str) -> Option<KeyCode> {
+    // A gap in a row
+    if name.is_empty() {
+        return None;
+    }
+
+    let code = KEY_CODES
+        .iter()
+        .find(|code| format!("{code:?}") == name)
+        .copied();
+    if code.is_none() {
+        log::warn!("Unknown key code in the computer keyboard config: {name}");
+    }
+    code
+}
+
+/// Label of a key on a US keyboard, used until the key is pressed and its real label is known
+fn default_label(code: KeyCode) -> String {
+    let name = format!("{code:?}");
+    let label = match code {
+        KeyCode::Backquote => "`",
+        KeyCode::Backslash | KeyCode::IntlBackslash => "\\",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Comma => ",",
+        KeyCode::Equal => "=",
+        KeyCode::Minus => "-",
+        KeyCode::Period => ".",
+        KeyCode::Quote => "'",
+        KeyCode::Semicolon => ";",
+        KeyCode::Slash => "/",
+        _ => name
+            .strip_prefix("Key")
+            .or_else(|| name.strip_prefix("Digit"))
+            .unwrap_or(&name),
+    };
+    label.to_string()
+}
+
+/// What a window event did to the computer keyboard piano
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcKeyboardInput {
+    Ignored,
+    /// A note key was pressed or released, other shortcuts on it should be skipped
+    Note,
+    /// Note played by the first key of the upper row
+    Octave(u8),
+    Velocity(u8),
+}
+
+/// Plays the piano with the computer keyboard, matching on physical keys
+pub struct PcKeyboard {
+    enabled: bool,
+    hints: bool,
+    base_note: u8,
+    velocity: u8,
+    /// Semitones from the base note of each key, upper row first
+    keys: Vec<(KeyCode, i8)>,
+    octave_down: Option<KeyCode>,
+    octave_up: Option<KeyCode>,
+    velocity_down: Option<KeyCode>,
+    velocity_up: Option<KeyCode>,
+    /// Note that each held key plays, so that it's released even if the octave changed since
+    held: HashMap<KeyCode, u8>,
+    labels: HashMap<KeyCode, String>,
+}
+
+impl PcKeyboard {
+    pub fn new(config: &PcKeyboardConfigV1) -> Self {
+        let rows = [(&config.upper_row, 0), (&config.lower_row, -12)];
+
+        let mut keys: Vec<(KeyCode, i8)> = Vec::new();
+        for (row, offset) in rows {
+            for (semitone, name) in row.iter().enumerate() {
+                if let Some(code) = parse_key_code(name)
+                    && !keys.iter().any(|(c, _)| *c == code)
+                {
+                    keys.push((code, offset + semitone as i8));
+                }
+            }
+        }
+
+        let labels = keys
+            .iter()
+            .map(|(code, _)| (*code, default_label(*code)))
+            .collect();
+
+        Self {
+            enabled: config.enabled,
+            hints: config.hints,
+            base_note: config.base_note,
+            velocity: config.velocity,
+            keys,
+            octave_down: parse_key_code(&config.octave_down),
+            octave_up: parse_key_code(&config.octave_up),
+            velocity_down: parse_key_code(&config.velocity_down),
+            velocity_up: parse_key_code(&config.velocity_up),
+            held: HashMap::new(),
+            labels,
+        }
+    }
+
+    /// Piano keys that the computer keys play, labeled with the computer keys
+    pub fn hints(&self) -> Vec<(u8, String)> {
+        if !self.enabled || !self.hints {
+            return Vec::new();
+        }
+
+        let mut hints: BTreeMap<u8, String> = BTreeMap::new();
+        for (code, offset) in self.keys.iter() {
+            let Some(note) = self.note(*offset) else {
+                continue;
+            };
+            let label = hints.entry(note).or_default();
+            if !label.is_empty() {
+                label.push(' ');
+            }
+            label.push_str(&self.labels[code]);
+        }
+        hints.into_iter().collect()
+    }
+
+    fn note(&self, offset: i8) -> Option<u8> {
+        self.base_note
+            .checked_add_signed(offset)
+            .filter(|note| *note <= 127)
+    }
+
+    pub fn handle_window_event(
+        &mut self,
+        ctx: &mut Context,
+        range: &KeyboardRange,
+        event: &WindowEvent,
+    ) -> PcKeyboardInput {
+        if !self.enabled {
+            return PcKeyboardInput::Ignored;
+        }
+
+        if let WindowEvent::Focused(false) = event {
+            // Key releases won't reach us anymore
+            for (_, note) in self.held.drain() {
+                super::note_off(ctx, note);
+            }
+            return PcKeyboardInput::Ignored;
+        }
+
+        let WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key: PhysicalKey::Code(code),
+                    logical_key,
+                    state,
+                    repeat,
+                    ..
+                },
+            ..
+        } = event
+        else {
+            return PcKeyboardInput::Ignored;
+        };
+        let code = *code;
+
+        if let Some((_, offset)) = self.keys.iter().find(|(c, _)| *c == code).copied() {
+            // Keys with modifiers are left to the other shortcuts (eg. Ctrl+B for bookmarks),
+            // unless they release a held note
+            let modifiers = ctx.window_state.modifiers_state;
+            if (modifiers.control_key() || modifiers.alt_key() || modifiers.super_key())
+                && !self.held.contains_key(&code)
+            {
+                return PcKeyboardInput::Ignored;
+            }
+
+            if *repeat {
+                return PcKeyboardInput::Note;
+            }
+
+            match state {
+                ElementState::Pressed => {
+                    if let Key::Character(ch) = logical_key
+                        && modifiers.is_empty()
+                    {
+                        self.labels.insert(code, ch.to_uppercase());
+                    }
+
+                    if let Some(note) = self.note(offset) {
+                        if let Some(prev) = self.held.insert(code, note) {
+                            super::note_off(ctx, prev);
+                        }
+                        super::note_on(ctx, note, self.velocity);
+                    }
+                }
+                ElementState::Released => {
+                    if let Some(note) = self.held.remove(&code) {
+                        super::note_off(ctx, note);
+                    }
+                }
+            }
+            return PcKeyboardInput::Note;
+        }
+
+        if *state != ElementState::Pressed {
+            return PcKeyboardInput::Ignored;
+        }
+
+        let code = Some(code);
+        if code == self.octave_down || code == self.octave_up {
+            let base_note = if code == self.octave_up {
+                // Keep the first key of the upper row on the piano
+                self.base_note
+                    .checked_add(12)
+                    .filter(|note| *note <= range.end())
+            } else {
+                // Keep the lowest key on the piano
+                let lowest = self.keys.iter().map(|(_, offset)| *offset).min();
+                self.base_note.checked_sub(12).filter(|note| {
+                    note.checked_add_signed(lowest.unwrap_or(0))
+                        .is_some_and(|n| n >= range.start())
+                })
+            };
+
+            if let Some(base_note) = base_note {
+                self.base_note = base_note;
+                ctx.config.set_pc_keyboard_base_note(base_note);
+            }
+            PcKeyboardInput::Octave(self.base_note)
+        } else if code == self.velocity_down || code == self.velocity_up {
+            self.velocity = if code == self.velocity_up {
+                self.velocity.saturating_add(VELOCITY_STEP).min(127)
+            } else {
+                self.velocity.saturating_sub(VELOCITY_STEP).max(1)
+            };
+            ctx.config.set_pc_keyboard_velocity(self.velocity);
+            PcKeyboardInput::Velocity(self.velocity)
+        } else {
+            PcKeyboardInput::Ignored
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_mapping() {
+        let keyboard = PcKeyboard::new(&PcKeyboardConfigV1 {
+            hints: true,
+            ..Default::default()
+        });
+
+        assert_eq!(keyboard.keys[0], (KeyCode::KeyA, 0));
+        assert_eq!(keyboard.keys[1], (KeyCode::KeyW, 1));
+        assert!(keyboard.keys.contains(&(KeyCode::Quote, 17)));
+        assert!(keyboard.keys.contains(&(KeyCode::KeyZ, -12)));
+        assert!(keyboard.keys.contains(&(KeyCode::KeyB, -5)));
+        assert_eq!(keyboard.keys.last(), Some(&(KeyCode::Slash, 4)));
+        assert_eq!(keyboard.octave_up, Some(KeyCode::F6));
+
+        let hints = keyboard.hints();
+        assert!(hints.contains(&(70, "U".to_string())));
+        assert!(hints.contains(&(72, "K".to_string())));
+        assert_eq!(hints.first(), Some(&(48, "Z".to_string())));
+        // Both rows reach the base note
+        assert!(hints.contains(&(60, "A ,".to_string())));
+
+        let mut config = PcKeyboardConfigV1 {
+            hints: true,
+            ..Default::default()
+        };
+        config.lower_row = vec!["KeyZ".into(), "KeyS".into()];
+        let keyboard = PcKeyboard::new(&config);
+        // Keys already used by the upper row are skipped
+        assert!(keyboard.keys.contains(&(KeyCode::KeyZ, -12)));
+        assert!(!keyboard.keys.contains(&(KeyCode::KeyS, -11)));
+        assert!(keyboard.hints().contains(&(48, "Z".to_string())));
+    }
+
+    #[test]
+    fn default_mapping_leaves_scene_shortcuts_alone() {
+        // Keys of the playing scene shortcuts on a US layout: bookmarks, offset,
+        // speed, animation speed, pause, exit and the debug overlay. `B` plays a note,
+        // its bookmark shortcut is left to Ctrl+B
+        let shortcuts = [
+            KeyCode::Digit0,
+            KeyCode::Digit1,
+            KeyCode::Digit2,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit5,
+            KeyCode::Digit6,
+            KeyCode::Digit7,
+            KeyCode::Digit8,
+            KeyCode::Digit9,
+            KeyCode::Minus,
+            KeyCode::Equal,
+            KeyCode::NumpadAdd,
+            KeyCode::NumpadSubtract,
+            KeyCode::ArrowUp,
+            KeyCode::ArrowDown,
+            KeyCode::ArrowLeft,
+            KeyCode::ArrowRight,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Space,
+            KeyCode::Escape,
+            KeyCode::F3,
+        ];
+
+        let keyboard = PcKeyboard::new(&PcKeyboardConfigV1::default());
+        let used = keyboard.keys.iter().map(|(code, _)| Some(*code)).chain([
+            keyboard.octave_down,
+            keyboard.octave_up,
+            keyboard.velocity_down,
+            keyboard.velocity_up,
+        ]);
+
+        for code in used {
+            let code = code.unwrap();
+            assert!(!shortcuts.contains(&code), "{code:?} is a scene shortcut");
+        }
+    }
+
+    #[test]
+    fn unknown_keys_and_gaps_are_skipped() {
+        let keyboard = PcKeyboard::new(&PcKeyboardConfigV1 {
+            upper_row: vec!["KeyA".into(), "Nope".into(), "KeyB".into()],
+            lower_row: vec!["KeyA".into(), "".into(), "KeyZ".into()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            keyboard.keys,
+            vec![(KeyCode::KeyA, 0), (KeyCode::KeyB, 2), (KeyCode::KeyZ, -10)]
+        );
+    }
+}
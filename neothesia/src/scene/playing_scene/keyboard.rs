@@ -106,6 +106,11 @@ impl Keyboard {
         self.renderer.update(quads, brush)
     }
 
+    /// Label these notes, eg. with the computer keys that play them
+    pub fn set_key_hints(&mut self, hints: Vec<(u8, String)>) {
+        self.renderer.set_key_hints(hints)
+    }
+
     pub fn reset_notes(&mut self) {
         self.renderer.reset_notes()
     }
@@ -137,9 +142,9 @@ impl Keyboard {
                 continue;
             }
 
-            let (is_on, key) = match e.message {
-                MidiMessage::NoteOn { key, .. } => (true, key.as_int()),
-                MidiMessage::NoteOff { key, .. } => (false, key.as_int()),
+            let (is_on, key) = match e.message.as_midi() {
+                Some(MidiMessage::NoteOn { key, .. }) => (true, key.as_int()),
+                Some(MidiMessage::NoteOff { key, .. }) => (false, key.as_int()),
                 _ => continue,
             };
 
@@ -0,0 +1,90 @@
+/// Result of a single pass through the looper region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassOutcome {
+    Clean,
+    Mistakes,
+    TargetReached,
+}
+
+/// "Play it N times without mistakes" practice mode built on top of the looper
+#[derive(Debug, Default)]
+pub struct LoopPractice {
+    active: bool,
+    /// Consecutive clean passes
+    streak: u32,
+    /// `PlayAlong` mistake count at the start of the current pass
+    pass_start_mistakes: usize,
+}
+
+impl LoopPractice {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn streak(&self) -> u32 {
+        self.streak
+    }
+
+    pub fn toggle(&mut self, mistakes: usize) {
+        self.active = !self.active;
+        self.streak = 0;
+        self.pass_start_mistakes = mistakes;
+    }
+
+    /// Start counting the pass from scratch, used when the user seeks outside of the loop
+    pub fn restart_pass(&mut self, mistakes: usize) {
+        self.pass_start_mistakes = mistakes;
+    }
+
+    /// Called every time the playback wraps from the loop end back to the loop start
+    pub fn finish_pass(&mut self, mistakes: usize, target: u32) -> PassOutcome {
+        let is_clean = mistakes == self.pass_start_mistakes;
+        self.pass_start_mistakes = mistakes;
+
+        if !is_clean {
+            self.streak = 0;
+            return PassOutcome::Mistakes;
+        }
+
+        self.streak += 1;
+
+        if self.streak >= target {
+            self.streak = 0;
+            PassOutcome::TargetReached
+        } else {
+            PassOutcome::Clean
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_passes_reach_target() {
+        let mut practice = LoopPractice::new();
+        practice.toggle(0);
+
+        assert_eq!(practice.finish_pass(0, 3), PassOutcome::Clean);
+        assert_eq!(practice.finish_pass(0, 3), PassOutcome::Clean);
+        assert_eq!(practice.streak(), 2);
+        assert_eq!(practice.finish_pass(0, 3), PassOutcome::TargetReached);
+        assert_eq!(practice.streak(), 0);
+    }
+
+    #[test]
+    fn mistake_resets_streak() {
+        let mut practice = LoopPractice::new();
+        practice.toggle(5);
+
+        assert_eq!(practice.finish_pass(5, 3), PassOutcome::Clean);
+        assert_eq!(practice.finish_pass(6, 3), PassOutcome::Mistakes);
+        assert_eq!(practice.streak(), 0);
+        assert_eq!(practice.finish_pass(6, 3), PassOutcome::Clean);
+    }
+}
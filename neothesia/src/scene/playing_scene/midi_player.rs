@@ -1,32 +1,48 @@
-use midi_file::midly::{MidiMessage, num::u4};
+use midi_file::{
+    EventMessage,
+    channel_state::ChannelStateTrack,
+    midly::{MidiMessage, num::u4},
+};
 
+use super::midi_sync::{ClockMaster, ClockSlave, SlaveCommand};
 use crate::{
-    output_manager::OutputConnection,
-    song::{PlayerConfig, Song},
+    input_manager::TransportMessage,
+    output_manager::{OutputConnection, TrackOutputs},
+    song::{PlayerConfig, Song, TrackConfig},
 };
 use neothesia_core::piano_layout;
 use std::{
     collections::{HashMap, HashSet},
+    path::PathBuf,
     time::{Duration, Instant},
 };
 
 pub struct MidiPlayer {
     playback: midi_file::PlaybackState,
-    output: OutputConnection,
+    outputs: TrackOutputs,
     song: Song,
     play_along: PlayAlong,
     separate_channels: bool,
+    /// HashMap<Channel, Program> of programs overridden by the track config
+    program_overrides: HashMap<u8, u8>,
+    /// HashMap<Channel, SoundFont> of fonts selected by the track config
+    channel_fonts: HashMap<u8, PathBuf>,
+    channel_state: ChannelStateTrack,
+    /// Sends MIDI clock of the playback to the outputs
+    clock_master: Option<ClockMaster>,
+    /// Drives the playback from the MIDI clock of an input
+    clock_slave: Option<ClockSlave>,
 }
 
 impl MidiPlayer {
     pub fn new(
-        output: OutputConnection,
+        outputs: TrackOutputs,
         song: Song,
         user_keyboard_range: piano_layout::KeyboardRange,
         separate_channels: bool,
     ) -> Self {
         Self::new_with_lead_in(
-            output,
+            outputs,
             song,
             user_keyboard_range,
             separate_channels,
@@ -35,7 +51,7 @@ impl MidiPlayer {
     }
 
     pub fn new_with_lead_in(
-        output: OutputConnection,
+        outputs: TrackOutputs,
         song: Song,
         user_keyboard_range: piano_layout::KeyboardRange,
         separate_channels: bool,
@@ -43,15 +59,26 @@ impl MidiPlayer {
     ) -> Self {
         let mut player = Self {
             playback: midi_file::PlaybackState::new(lead_in, song.file.tracks.clone()),
-            output,
+            outputs,
             play_along: PlayAlong::new(user_keyboard_range),
+            program_overrides: program_overrides(&song, separate_channels),
+            channel_fonts: channel_fonts(&song, separate_channels),
+            channel_state: if separate_channels {
+                ChannelStateTrack::with_channel_map(&song.file.tracks, |event| {
+                    event.track_color_id as u8
+                })
+            } else {
+                ChannelStateTrack::new(&song.file.tracks)
+            },
             song,
             separate_channels,
+            clock_master: None,
+            clock_slave: None,
         };
-        // Let's reset programs,
-        // for timestamp 0 most likely all programs will be 0, so this should clean any leftovers
-        // from previous songs
-        player.send_midi_programs_for_timestamp(&player.playback.time());
+        // Let's reset channels,
+        // for timestamp 0 most likely everything will be at defaults, so this should clean any
+        // leftovers from previous songs
+        player.send_channel_state_for_timestamp(&player.playback.time());
         player.update(Duration::ZERO);
 
         player
@@ -67,30 +94,85 @@ impl MidiPlayer {
     pub fn update(&mut self, delta: Duration) -> Vec<&midi_file::MidiEvent> {
         self.play_along.update();
 
+        // The followed device sets the pace, ignoring the speed of our own clock
+        let delta = match &self.clock_slave {
+            Some(slave) => {
+                let target = slave.time(&self.song.file.tempo_track, Instant::now())
+                    + *self.playback.leed_in();
+                target.saturating_sub(self.playback.time())
+            }
+            None => delta,
+        };
+
+        if !self.playback.is_paused() {
+            self.send_clock(self.playback.time() + delta);
+        }
+
         let events = self.playback.update(delta);
+        let has_solo = self.song.config.has_solo();
 
         events.iter().for_each(|event| {
             let config = &self.song.config.tracks[event.track_id];
+            let output = self.outputs.for_track(event.track_id);
+
+            let file_message = match &event.message {
+                EventMessage::Midi(message) => *message,
+                EventMessage::SysEx(data) => {
+                    if config.player != PlayerConfig::Mute {
+                        output.sysex(data);
+                    }
+                    return;
+                }
+                EventMessage::Escape(data) => {
+                    if config.player != PlayerConfig::Mute {
+                        output.escape(data);
+                    }
+                    return;
+                }
+            };
 
             let channel = if self.separate_channels {
                 event.track_color_id as u8
             } else {
                 event.channel
             };
+
+            // Drum kits are selected through program changes, let the output know ahead of it
+            if let MidiMessage::ProgramChange { .. } = file_message {
+                let is_drum = self
+                    .song
+                    .file
+                    .program_track
+                    .is_drum(event.channel, &event.timestamp);
+                output.set_drum_channel(u4::new(channel), is_drum);
+            }
+
+            let message =
+                apply_track_config(config, &self.program_overrides, channel, file_message);
+            // When any track is soloed, the rest of them is silenced
+            let audible = !has_solo || config.solo;
+
             match config.player {
                 PlayerConfig::Auto => {
-                    self.output // TODO: Send to multiple outputs
-                        .midi_event(u4::new(channel), event.message);
+                    if audible && let Some(message) = message {
+                        output.midi_event(u4::new(channel), message);
+                    }
                 }
                 PlayerConfig::Human => {
-                    self.play_along
-                        .midi_event(MidiEventSource::File, &event.message);
+                    self.play_along.midi_event(
+                        MidiEventSource::File,
+                        &file_message,
+                        Instant::now(),
+                    );
 
                     // In Human mode note events from the file are targets for the player,
                     // not notes to be played by the synthesizer. Keep forwarding controller
                     // and other non-note events so the track still sounds as intended.
-                    if should_forward_human_event(&event.message) {
-                        self.output.midi_event(u4::new(channel), event.message);
+                    if audible
+                        && let Some(message) = message
+                        && should_forward_human_event(&message)
+                    {
+                        output.midi_event(u4::new(channel), message);
                     }
                 }
                 PlayerConfig::Mute => {}
@@ -101,7 +183,82 @@ impl MidiPlayer {
     }
 
     fn clear(&mut self) {
-        self.output.stop_all();
+        let outputs = self.outputs.unique();
+        for output in outputs.iter() {
+            output.stop_all();
+        }
+
+        // Followers stop along, the next update moves them to the new position
+        if let Some(master) = self.clock_master.as_mut() {
+            master.stop(|data| outputs.iter().for_each(|output| output.transport(data)));
+        }
+    }
+
+    /// Send MIDI clock and transport to the outputs, so that other devices can play along
+    pub fn enable_clock_output(&mut self) {
+        self.clock_master = Some(ClockMaster::default());
+    }
+
+    /// Wait at the start of the song, until the input that sends MIDI clock starts playing
+    pub fn follow_clock(&mut self) {
+        self.clock_slave = Some(ClockSlave::default());
+        self.set_time(*self.playback.leed_in());
+        self.pause();
+    }
+
+    /// Clock or transport message of the followed input, returns `true` if the playback jumped
+    pub fn transport_event(&mut self, message: TransportMessage, timestamp: Instant) -> bool {
+        let Some(slave) = self.clock_slave.as_mut() else {
+            return false;
+        };
+
+        let lead_in = *self.playback.leed_in();
+        match slave.handle(&self.song.file.tempo_track, message, timestamp) {
+            Some(SlaveCommand::Start) => {
+                self.set_time(lead_in);
+                self.resume();
+                true
+            }
+            Some(SlaveCommand::Continue) => {
+                self.resume();
+                false
+            }
+            Some(SlaveCommand::Stop) => {
+                self.pause();
+                false
+            }
+            Some(SlaveCommand::Locate(time)) => {
+                self.set_time(time + lead_in);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Clocks up to `time`, the outputs only hear of the song once the lead-in is over
+    fn send_clock(&mut self, time: Duration) {
+        let Some(master) = self.clock_master.as_mut() else {
+            return;
+        };
+        let Some(time) = time.checked_sub(*self.playback.leed_in()) else {
+            return;
+        };
+
+        let outputs = self.outputs.unique();
+        master.update(&self.song.file.tempo_track, time, |data| {
+            outputs.iter().for_each(|output| output.transport(data))
+        });
+    }
+
+    /// Output that user input should be played on, the one used by human tracks
+    fn user_output(&self) -> &OutputConnection {
+        self.song
+            .config
+            .tracks
+            .iter()
+            .find(|track| track.player == PlayerConfig::Human)
+            .map(|track| self.outputs.for_track(track.track_id))
+            .unwrap_or(self.outputs.default_output())
     }
 }
 
@@ -130,14 +287,59 @@ impl MidiPlayer {
         self.play_along.clear();
     }
 
-    fn send_midi_programs_for_timestamp(&self, time: &Duration) {
-        for (&channel, &p) in self.song.file.program_track.program_for_timestamp(time) {
-            self.output.midi_event(
-                u4::new(channel),
-                midi_file::midly::MidiMessage::ProgramChange {
-                    program: midi_file::midly::num::u7::new(p),
-                },
-            );
+    /// Bring every channel to the state it would be in, if the song was played from the start
+    fn send_channel_state_for_timestamp(&self, time: &Duration) {
+        let outputs = self.outputs.unique();
+
+        // Playback time includes lead-in, file events don't
+        let time = time.saturating_sub(*self.playback.leed_in());
+
+        let drums = self.drum_channels_for_timestamp(&time);
+
+        for (channel, state) in self
+            .channel_state
+            .state_for_timestamp(&time)
+            .iter()
+            .enumerate()
+        {
+            let font = self.channel_fonts.get(&(channel as u8));
+            for output in outputs.iter() {
+                output.set_drum_channel(u4::new(channel as u8), drums[channel]);
+                output.set_channel_font(u4::new(channel as u8), font.map(PathBuf::as_path));
+            }
+
+            let channel = channel as u8;
+            for message in state.messages() {
+                let message = match message {
+                    MidiMessage::ProgramChange { .. } => match self.program_overrides.get(&channel)
+                    {
+                        Some(&program) => MidiMessage::ProgramChange {
+                            program: program.into(),
+                        },
+                        None => message,
+                    },
+                    message => message,
+                };
+
+                for output in outputs.iter() {
+                    output.midi_event(u4::new(channel), message);
+                }
+            }
+        }
+    }
+
+    /// Which output channels play drum kits
+    fn drum_channels_for_timestamp(&self, time: &Duration) -> [bool; 16] {
+        if self.separate_channels {
+            let mut drums = [false; 16];
+            for track in self.song.file.tracks.iter() {
+                if track.has_drums && !track.has_other_than_drums {
+                    drums[track.track_color_id % 16] = true;
+                }
+            }
+            drums
+        } else {
+            std::array::from_fn(|ch| self.song.file.program_track.is_drum(ch as u8, time))
         }
     }
 
@@ -149,7 +351,7 @@ impl MidiPlayer {
         std::mem::drop(events);
 
         self.clear();
-        self.send_midi_programs_for_timestamp(&time);
+        self.send_channel_state_for_timestamp(&time);
     }
 
     pub fn rewind(&mut self, delta: i64) {
@@ -212,9 +414,11 @@ impl MidiPlayer {
         &self.play_along
     }
 
-    pub fn user_midi_event(&mut self, channel: u8, message: &MidiMessage) {
-        self.output.midi_event(u4::new(channel), *message);
-        self.play_along.midi_event(MidiEventSource::User, message);
+    /// Note played by the user, `timestamp` is when the key was hit
+    pub fn user_midi_event(&mut self, channel: u8, message: &MidiMessage, timestamp: Instant) {
+        self.user_output().midi_event(u4::new(channel), *message);
+        self.play_along
+            .midi_event(MidiEventSource::User, message, timestamp);
     }
 }
 
@@ -223,6 +427,88 @@ pub enum MidiEventSource {
     User,
 }
 
+/// Output channels that events of a track are played on
+fn track_channels(song: &Song, track_id: usize, separate_channels: bool) -> HashSet<u8> {
+    let track = &song.file.tracks[track_id];
+
+    if separate_channels {
+        HashSet::from([track.track_color_id as u8])
+    } else {
+        track
+            .events
+            .iter()
+            .filter(|event| event.message.as_midi().is_some())
+            .map(|event| event.channel)
+            .collect()
+    }
+}
+
+/// Channels whose program is overridden by the track config
+fn program_overrides(song: &Song, separate_channels: bool) -> HashMap<u8, u8> {
+    let mut overrides = HashMap::new();
+
+    for config in song.config.tracks.iter() {
+        let Some(program) = config.program else {
+            continue;
+        };
+
+        for channel in track_channels(song, config.track_id, separate_channels) {
+            overrides.insert(channel, program);
+        }
+    }
+
+    overrides
+}
+
+/// Channels that take presets from a SoundFont selected in the track config.
+///
+/// Tracks sharing a channel share the font as well, the last one wins.
+fn channel_fonts(song: &Song, separate_channels: bool) -> HashMap<u8, PathBuf> {
+    let mut fonts = HashMap::new();
+
+    for config in song.config.tracks.iter() {
+        let Some(font) = config.soundfont.as_ref() else {
+            continue;
+        };
+
+        for channel in track_channels(song, config.track_id, separate_channels) {
+            fonts.insert(channel, font.clone());
+        }
+    }
+
+    fonts
+}
+
+/// Apply track volume and program override to the event.
+///
+/// Returns `None` if the event should not be played at all.
+fn apply_track_config(
+    config: &TrackConfig,
+    program_overrides: &HashMap<u8, u8>,
+    channel: u8,
+    message: MidiMessage,
+) -> Option<MidiMessage> {
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel > 0 => {
+            if config.volume <= 0.0 {
+                return None;
+            }
+            let vel = (vel.as_int() as f32 * config.volume.min(1.0)).round() as u8;
+            Some(MidiMessage::NoteOn {
+                key,
+                vel: vel.max(1).into(),
+            })
+        }
+        MidiMessage::ProgramChange { .. } => match program_overrides.get(&channel) {
+            Some(&program) => Some(MidiMessage::ProgramChange {
+                program: program.into(),
+            }),
+            None => Some(message),
+        },
+        message => Some(message),
+    }
+}
+
 fn should_forward_human_event(message: &MidiMessage) -> bool {
     !matches!(
         message,
@@ -262,6 +548,11 @@ impl PlayerStats {
         Self::count_with_threshold(&self.played_late, Duration::from_millis(160))
     }
 
+    /// Every mistake that breaks a clean run: wrong notes and notes played too early or too late
+    fn mistakes(&self) -> usize {
+        self.wrong_notes + self.count_too_early() + self.count_too_late()
+    }
+
     fn count_with_threshold(events: &[Duration], threshold: Duration) -> usize {
         events
             .iter()
@@ -316,15 +607,13 @@ impl PlayAlong {
         self.stats.wrong_notes += count_before - self.user_pressed_recently.len();
     }
 
-    fn user_press_key(&mut self, note_id: u8, active: bool) {
-        let timestamp = Instant::now();
-
+    fn user_press_key(&mut self, note_id: u8, active: bool, timestamp: Instant) {
         if active {
             // Check if note has already been played by a file
             if let Some(required_press) = self.required_notes.remove(&note_id) {
                 self.stats
                     .played_late
-                    .push(timestamp.duration_since(required_press.timestamp));
+                    .push(timestamp.saturating_duration_since(required_press.timestamp));
             } else {
                 // This note was not played by file yet, place it in recents
                 let got_replaced = self
@@ -339,14 +628,13 @@ impl PlayAlong {
         }
     }
 
-    fn file_press_key(&mut self, note_id: u8, active: bool) {
-        let timestamp = Instant::now();
+    fn file_press_key(&mut self, note_id: u8, active: bool, timestamp: Instant) {
         if active {
             // Check if note got pressed earlier 500ms (user_pressed_recently)
             if let Some(press) = self.user_pressed_recently.remove(&note_id) {
                 self.stats
                     .played_early
-                    .push(timestamp.duration_since(press.timestamp));
+                    .push(timestamp.saturating_duration_since(press.timestamp));
             } else {
                 // Player never pressed that note, let it reach required_notes
 
@@ -364,21 +652,30 @@ impl PlayAlong {
         }
     }
 
-    fn press_key(&mut self, src: MidiEventSource, note_id: u8, active: bool) {
+    fn press_key(&mut self, src: MidiEventSource, note_id: u8, active: bool, timestamp: Instant) {
         if !self.user_keyboard_range.contains(note_id) {
             return;
         }
 
         match src {
-            MidiEventSource::User => self.user_press_key(note_id, active),
-            MidiEventSource::File => self.file_press_key(note_id, active),
+            MidiEventSource::User => self.user_press_key(note_id, active, timestamp),
+            MidiEventSource::File => self.file_press_key(note_id, active, timestamp),
         }
     }
 
-    pub fn midi_event(&mut self, source: MidiEventSource, message: &MidiMessage) {
+    pub fn midi_event(
+        &mut self,
+        source: MidiEventSource,
+        message: &MidiMessage,
+        timestamp: Instant,
+    ) {
         match message {
-            MidiMessage::NoteOn { key, .. } => self.press_key(source, key.as_int(), true),
-            MidiMessage::NoteOff { key, .. } => self.press_key(source, key.as_int(), false),
+            MidiMessage::NoteOn { key, .. } => {
+                self.press_key(source, key.as_int(), true, timestamp)
+            }
+            MidiMessage::NoteOff { key, .. } => {
+                self.press_key(source, key.as_int(), false, timestamp)
+            }
             _ => {}
         }
     }
@@ -392,4 +689,9 @@ impl PlayAlong {
     pub fn are_required_keys_pressed(&self) -> bool {
         self.required_notes.is_empty()
     }
+
+    /// Total count of mistakes made since the song started
+    pub fn mistakes(&self) -> usize {
+        self.stats.mistakes()
+    }
 }
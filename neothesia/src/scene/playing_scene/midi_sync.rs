@@ -0,0 +1,243 @@
+use std::time::{Duration, Instant};
+
+use midi_file::tempo_track::TempoTrack;
+
+use crate::input_manager::TransportMessage;
+
+const CLOCKS_PER_QUARTER: u64 = 24;
+/// Song position pointer counts sixteenth notes
+const CLOCKS_PER_SIXTEENTH: u64 = CLOCKS_PER_QUARTER / 4;
+
+const CLOCK: u8 = 0xF8;
+const START: u8 = 0xFA;
+const CONTINUE: u8 = 0xFB;
+const STOP: u8 = 0xFC;
+const SONG_POSITION: u8 = 0xF2;
+
+/// Sends MIDI clock and transport of the playback, so that other devices can follow it.
+///
+/// Clocks are sent from the frame loop, so they jitter by up to a frame.
+#[derive(Default)]
+pub struct ClockMaster {
+    /// Next clock to send, counted from the start of the song, `None` while stopped
+    next_clock: Option<u64>,
+}
+
+impl ClockMaster {
+    /// Send the clocks up to `time`, starting the transport if it was stopped
+    pub fn update(&mut self, tempo: &TempoTrack, time: Duration, mut send: impl FnMut(&[u8])) {
+        let clock = (tempo.duration_to_quarters(time) * CLOCKS_PER_QUARTER as f64) as u64;
+
+        let next = match self.next_clock {
+            Some(next) => next,
+            None => {
+                // Devices can only be moved to a sixteenth note, so start from the last one and catch up
+                let sixteenth = (clock / CLOCKS_PER_SIXTEENTH).min(0x3FFF);
+                if sixteenth == 0 {
+                    send(&[START]);
+                } else {
+                    send(&[
+                        SONG_POSITION,
+                        (sixteenth & 0x7F) as u8,
+                        (sixteenth >> 7) as u8,
+                    ]);
+                    send(&[CONTINUE]);
+                }
+                sixteenth * CLOCKS_PER_SIXTEENTH
+            }
+        };
+
+        for _ in next..=clock {
+            send(&[CLOCK]);
+        }
+        self.next_clock = Some(next.max(clock + 1));
+    }
+
+    /// Stop the transport, the next update starts it again from the current position
+    pub fn stop(&mut self, mut send: impl FnMut(&[u8])) {
+        if self.next_clock.take().is_some() {
+            send(&[STOP]);
+        }
+    }
+}
+
+/// What the playback should do, after a transport message of the followed device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SlaveCommand {
+    /// Play from the start of the song
+    Start,
+    Continue,
+    Stop,
+    /// Jump to this time of the song, lead-in excluded
+    Locate(Duration),
+}
+
+/// Follows MIDI clock and transport of another device
+pub struct ClockSlave {
+    /// Clocks since the start of the song, of the last one received. `-1` before the first one.
+    clock: i64,
+    running: bool,
+    last_clock: Option<Instant>,
+    /// Time between clocks, smoothed over the last few of them
+    interval: Option<Duration>,
+}
+
+impl Default for ClockSlave {
+    fn default() -> Self {
+        Self {
+            clock: -1,
+            running: false,
+            last_clock: None,
+            interval: None,
+        }
+    }
+}
+
+impl ClockSlave {
+    pub fn handle(
+        &mut self,
+        tempo: &TempoTrack,
+        message: TransportMessage,
+        timestamp: Instant,
+    ) -> Option<SlaveCommand> {
+        match message {
+            TransportMessage::Clock => {
+                if !self.running {
+                    return None;
+                }
+
+                if let Some(last) = self.last_clock {
+                    let interval = timestamp.saturating_duration_since(last);
+                    // A long gap means that the clock was paused, don't let it skew the tempo
+                    if interval < Duration::from_millis(500) {
+                        self.interval = Some(match self.interval {
+                            Some(prev) => prev.mul_f64(0.8) + interval.mul_f64(0.2),
+                            None => interval,
+                        });
+                    }
+                }
+                self.last_clock = Some(timestamp);
+                self.clock += 1;
+                None
+            }
+            TransportMessage::Start => {
+                self.running = true;
+                self.clock = -1;
+                self.last_clock = None;
+                Some(SlaveCommand::Start)
+            }
+            TransportMessage::Continue => {
+                self.running = true;
+                self.last_clock = None;
+                Some(SlaveCommand::Continue)
+            }
+            TransportMessage::Stop => {
+                self.running = false;
+                Some(SlaveCommand::Stop)
+            }
+            TransportMessage::SongPosition(sixteenth) => {
+                let clock = sixteenth as u64 * CLOCKS_PER_SIXTEENTH;
+                // The clock at the new position is the first one after continue
+                self.clock = clock as i64 - 1;
+                let quarters = clock as f64 / CLOCKS_PER_QUARTER as f64;
+                Some(SlaveCommand::Locate(tempo.quarters_to_duration(quarters)))
+            }
+        }
+    }
+
+    /// Time of the song that the device is at, lead-in excluded
+    pub fn time(&self, tempo: &TempoTrack, now: Instant) -> Duration {
+        if self.clock < 0 {
+            return tempo.quarters_to_duration(0.0);
+        }
+
+        // Move on smoothly between clocks, up to where the next one is expected
+        let progress = match (self.running, self.last_clock, self.interval) {
+            (true, Some(last), Some(interval)) if !interval.is_zero() => {
+                (now.saturating_duration_since(last).as_secs_f64() / interval.as_secs_f64())
+                    .min(1.0)
+            }
+            _ => 0.0,
+        };
+
+        let quarters = (self.clock as f64 + progress) / CLOCKS_PER_QUARTER as f64;
+        tempo.quarters_to_duration(quarters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tempo() -> TempoTrack {
+        TempoTrack::build(&[], 96)
+    }
+
+    #[test]
+    fn master_starts_and_sends_clocks() {
+        let tempo = tempo();
+        let mut master = ClockMaster::default();
+        let mut sent = Vec::new();
+
+        // 120 BPM, a clock every 500ms / 24
+        master.update(&tempo, Duration::ZERO, |data| sent.push(data.to_vec()));
+        assert_eq!(sent, vec![vec![START], vec![CLOCK]]);
+
+        sent.clear();
+        master.update(&tempo, Duration::from_millis(500), |data| {
+            sent.push(data.to_vec())
+        });
+        assert_eq!(sent, vec![vec![CLOCK]; 24]);
+
+        sent.clear();
+        master.stop(|data| sent.push(data.to_vec()));
+        master.stop(|data| sent.push(data.to_vec()));
+        assert_eq!(sent, vec![vec![STOP]]);
+
+        // Continues from the last sixteenth note, 1.1 quarter notes in
+        sent.clear();
+        master.update(&tempo, Duration::from_millis(550), |data| {
+            sent.push(data.to_vec())
+        });
+        assert_eq!(sent[0], vec![SONG_POSITION, 4, 0]);
+        assert_eq!(sent[1], vec![CONTINUE]);
+        assert_eq!(sent.len(), 2 + 3);
+    }
+
+    #[test]
+    fn slave_follows_clock() {
+        let tempo = tempo();
+        let mut slave = ClockSlave::default();
+        let start = Instant::now();
+        let tick = Duration::from_millis(500) / 24;
+
+        assert_eq!(
+            slave.handle(&tempo, TransportMessage::Start, start),
+            Some(SlaveCommand::Start)
+        );
+        for i in 0..=24 {
+            slave.handle(&tempo, TransportMessage::Clock, start + tick * i);
+        }
+
+        let last = start + tick * 24;
+        assert_eq!(slave.time(&tempo, last), Duration::from_millis(500));
+        // Half way to the next clock
+        let time = slave.time(&tempo, last + tick / 2);
+        assert!(time > Duration::from_millis(510) && time < Duration::from_millis(511));
+
+        assert_eq!(
+            slave.handle(&tempo, TransportMessage::SongPosition(8), last),
+            Some(SlaveCommand::Locate(Duration::from_secs(1)))
+        );
+        assert_eq!(
+            slave.handle(&tempo, TransportMessage::Stop, last),
+            Some(SlaveCommand::Stop)
+        );
+        // Clocks while stopped don't move the song
+        slave.handle(&tempo, TransportMessage::Clock, last + tick);
+        assert_eq!(
+            slave.time(&tempo, last + tick * 4),
+            slave.time(&tempo, last)
+        );
+    }
+}
@@ -1,8 +1,7 @@
-use midi_file::midly::MidiMessage;
 use neothesia_core::render::{
     GlowRenderer, GuidelineRenderer, NoteLabels, QuadRenderer, TextRenderer,
 };
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 use winit::{
     event::WindowEvent,
     keyboard::{Key, NamedKey},
@@ -12,8 +11,17 @@ use self::top_bar::TopBar;
 
 use super::{NuonRenderer, Scene};
 use crate::{
-    NeothesiaEvent, context::Context, render::WaterfallRenderer, scene::MouseToMidiEventState,
-    song::Song, utils::window::WinitEvent,
+    MidiDeviceEvent, NeothesiaEvent,
+    context::Context,
+    input_manager::{MidiInputEvent, TransportEvent},
+    output_manager::{self, AudioRecorderError, AudioRecording, TrackOutputs},
+    render::WaterfallRenderer,
+    scene::{
+        MouseToMidiEventState, TouchToMidiEventState,
+        pc_keyboard::{PcKeyboard, PcKeyboardInput},
+    },
+    song::Song,
+    utils::window::WinitEvent,
 };
 
 mod keyboard;
@@ -22,12 +30,17 @@ pub use keyboard::Keyboard;
 pub(crate) mod midi_player;
 use midi_player::MidiPlayer;
 
+mod midi_sync;
+
 mod rewind_controller;
 use rewind_controller::RewindController;
 
 mod toast_manager;
 use toast_manager::ToastManager;
 
+mod loop_practice;
+use loop_practice::{LoopPractice, PassOutcome};
+
 mod animation;
 mod top_bar;
 
@@ -49,13 +62,28 @@ pub struct PlayingScene {
 
     nuon: nuon::Ui,
     mouse_to_midi_state: MouseToMidiEventState,
+    touch_to_midi_state: TouchToMidiEventState,
+    pc_keyboard: PcKeyboard,
 
     top_bar: TopBar,
+    loop_practice: LoopPractice,
+    bookmarks: Vec<Duration>,
+    /// Speed from the global config, put back when the scene closes,
+    /// as the song's own speed is only kept in its saved state.
+    ///
+    /// Taken by the first save, so that later ones don't overwrite the song's speed with it.
+    global_speed_multiplier: Option<f32>,
+
+    /// FPS and audio latency info, toggled with F3
+    debug_overlay: bool,
 }
 
 impl PlayingScene {
     pub fn new(ctx: &mut Context, song: Song) -> Self {
-        let keyboard = Keyboard::new(ctx, song.config.clone());
+        let mut keyboard = Keyboard::new(ctx, song.config.clone());
+
+        let pc_keyboard = PcKeyboard::new(ctx.config.pc_keyboard());
+        keyboard.set_key_hints(pc_keyboard.hints());
 
         let keyboard_layout = keyboard.layout();
 
@@ -92,12 +120,29 @@ impl PlayingScene {
             ctx.text_renderer_factory.new_renderer(),
         ));
 
-        let player = MidiPlayer::new(
-            ctx.output_manager.connection().clone(),
+        let mut outputs = TrackOutputs::new(ctx.output_manager.connection().clone());
+        for track in song.config.tracks.iter() {
+            if let Some(conn) = track
+                .output
+                .as_deref()
+                .and_then(|name| ctx.output_manager.connection_by_name(name))
+            {
+                outputs.route(track.track_id, conn.clone());
+            }
+        }
+
+        let mut player = MidiPlayer::new(
+            outputs,
             song,
             keyboard_layout.range.clone(),
             ctx.config.separate_channels(),
         );
+        if ctx.config.clock_output() {
+            player.enable_clock_output();
+        }
+        if ctx.config.clock_input().is_some() {
+            player.follow_clock();
+        }
         waterfall.update(player.time_without_lead_in());
 
         let quad_renderer_bg = ctx.quad_renderer_factory.new_renderer();
@@ -109,7 +154,7 @@ impl PlayingScene {
             keyboard.layout(),
         ));
 
-        Self {
+        let mut scene = Self {
             keyboard,
             guidelines,
             note_labels,
@@ -126,8 +171,151 @@ impl PlayingScene {
 
             nuon: nuon::Ui::new(),
             mouse_to_midi_state: MouseToMidiEventState::default(),
+            touch_to_midi_state: TouchToMidiEventState::default(),
+            pc_keyboard,
 
             top_bar: TopBar::new(),
+            loop_practice: LoopPractice::new(),
+            bookmarks: Vec::new(),
+            global_speed_multiplier: Some(ctx.config.speed_multiplier()),
+
+            debug_overlay: cfg!(debug_assertions),
+        };
+
+        scene.restore_song_state(ctx);
+        scene
+    }
+
+    /// Resume where the user left off the last time this song was played
+    fn restore_song_state(&mut self, ctx: &mut Context) {
+        let Some(path) = self.player.song().path.as_ref() else {
+            return;
+        };
+        let Some(state) = ctx.config.song_state(path).cloned() else {
+            return;
+        };
+
+        ctx.config.set_speed_multiplier(state.speed_multiplier);
+
+        if let Some((start, end)) = state.loop_region {
+            self.top_bar
+                .set_loop(Duration::from_secs_f32(start), Duration::from_secs_f32(end));
+            self.top_bar.set_looper_active(true);
+        }
+
+        self.bookmarks = state
+            .bookmarks
+            .iter()
+            .map(|b| Duration::from_secs_f32(*b))
+            .collect();
+
+        let position = Duration::from_secs_f32(state.position);
+        if position < self.player.length() {
+            self.player.set_time(position);
+        }
+    }
+
+    fn save_song_state(&mut self, ctx: &mut Context) {
+        let song = self.player.song();
+        let Some(path) = song.path.as_ref() else {
+            return;
+        };
+        // Already saved when the scene started closing
+        let Some(global_speed_multiplier) = self.global_speed_multiplier.take() else {
+            return;
+        };
+
+        song.save_tracks_config(&mut ctx.config);
+
+        let speed_multiplier = ctx.config.speed_multiplier();
+        let state = ctx.config.song_state_mut(path);
+
+        state.position = if self.player.is_finished() {
+            0.0
+        } else {
+            self.player.time().as_secs_f32()
+        };
+        state.speed_multiplier = speed_multiplier;
+        state.loop_region = self.top_bar.is_looper_active().then(|| {
+            (
+                self.top_bar.loop_start_timestamp().as_secs_f32(),
+                self.top_bar.loop_end_timestamp().as_secs_f32(),
+            )
+        });
+        state.bookmarks = self.bookmarks.iter().map(|b| b.as_secs_f32()).collect();
+
+        ctx.config.set_speed_multiplier(global_speed_multiplier);
+    }
+
+    pub fn go_to_main_menu(&mut self, ctx: &mut Context) {
+        self.save_song_state(ctx);
+        ctx.proxy
+            .send_event(NeothesiaEvent::MainMenu(Some(self.player.song().clone())))
+            .ok();
+    }
+
+    pub fn bookmarks(&self) -> &[Duration] {
+        &self.bookmarks
+    }
+
+    /// Start or stop recording the synth audio to a WAV file
+    pub fn toggle_audio_recording(&mut self, ctx: &mut Context) {
+        if let Some(res) = ctx.output_manager.stop_audio_recording() {
+            match res {
+                Ok(recording) => self
+                    .toast_manager
+                    .toast(format!("Saved audio to {}", recording.path.display())),
+                Err(err) => self.toast_manager.toast(err.to_string()),
+            }
+            return;
+        }
+
+        let name = &self.player.song().file.name;
+        let name = Path::new(name)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("recording");
+        let Some(path) = output_manager::recording_path(name) else {
+            self.toast_manager.toast("No folder to save recordings to");
+            return;
+        };
+
+        match ctx.output_manager.start_audio_recording(path) {
+            Ok(()) => self.toast_manager.toast("Recording audio"),
+            Err(err) => self.toast_manager.toast(err.to_string()),
+        }
+    }
+
+    fn handle_bookmarks_input(&mut self, event: &WindowEvent) {
+        if event.key_released(Key::Character("b")) {
+            let time = self.player.time();
+
+            // Toggle bookmarks that are close enough to the current position
+            let threshold = Duration::from_millis(500);
+            let len = self.bookmarks.len();
+            self.bookmarks.retain(|b| b.abs_diff(time) > threshold);
+
+            if self.bookmarks.len() == len {
+                self.bookmarks.push(time);
+                self.bookmarks.sort();
+                self.toast_manager.toast("Bookmark added");
+            } else {
+                self.toast_manager.toast("Bookmark removed");
+            }
+            return;
+        }
+
+        let Some(n) = event
+            .character_released()
+            .and_then(|ch| ch.parse::<usize>().ok())
+        else {
+            return;
+        };
+
+        if let Some(time) = n.checked_sub(1).and_then(|id| self.bookmarks.get(id)) {
+            self.player.set_time(*time);
+            self.keyboard.reset_notes();
+            self.toast_manager.toast(format!("Bookmark {n}"));
         }
     }
 
@@ -158,11 +346,21 @@ impl PlayingScene {
     }
 
     #[profiling::function]
-    fn update_midi_player(&mut self, ctx: &Context, delta: Duration) -> f32 {
+    fn update_midi_player(&mut self, ctx: &mut Context, delta: Duration) -> f32 {
         if self.top_bar.is_looper_active() && self.player.time() > self.top_bar.loop_end_timestamp()
         {
+            if self.loop_practice.is_active() {
+                self.on_loop_pass_finished(ctx);
+            }
+
             self.player.set_time(self.top_bar.loop_start_timestamp());
             self.keyboard.reset_notes();
+        } else if self.loop_practice.is_active()
+            && self.player.time() < self.top_bar.loop_start_timestamp()
+        {
+            // User went back before the loop, so the current pass no longer counts
+            self.loop_practice
+                .restart_pass(self.player.play_along().mistakes());
         }
 
         if self.player.play_along().are_required_keys_pressed() {
@@ -174,6 +372,42 @@ impl PlayingScene {
         self.player.time_without_lead_in() + ctx.config.animation_offset()
     }
 
+    fn on_loop_pass_finished(&mut self, ctx: &mut Context) {
+        let target = ctx.config.loop_practice_target();
+        let outcome = self
+            .loop_practice
+            .finish_pass(self.player.play_along().mistakes(), target);
+
+        match outcome {
+            PassOutcome::Clean => {
+                let step = ctx.config.loop_practice_speed_step();
+                if step > 0.0 {
+                    ctx.config
+                        .set_speed_multiplier(ctx.config.speed_multiplier() + step);
+                }
+                self.toast_manager
+                    .loop_practice_toast(self.loop_practice.streak(), target);
+            }
+            PassOutcome::Mistakes => {
+                self.toast_manager.toast("Mistake, streak reset");
+            }
+            PassOutcome::TargetReached => {
+                let start = self.top_bar.loop_start_timestamp();
+                let end = self.top_bar.loop_end_timestamp();
+                let length = end.saturating_sub(start);
+
+                if ctx.config.loop_practice_advance() && end < self.player.length() {
+                    let next_end = (end + length).min(self.player.length());
+                    self.top_bar.set_loop(end, next_end);
+                    self.toast_manager.toast("Section complete, next section");
+                } else {
+                    self.player.pause();
+                    self.toast_manager.toast("Section complete");
+                }
+            }
+        }
+    }
+
     #[profiling::function]
     fn resize(&mut self, ctx: &mut Context) {
         self.keyboard.resize(ctx);
@@ -232,22 +466,21 @@ impl Scene for PlayingScene {
             glow.prepare();
         }
 
-        #[cfg(debug_assertions)]
-        self.text_renderer.queue_fps(
-            ctx.fps_ticker.avg(),
-            self.top_bar
-                .topbar_expand_animation
-                .animate_bool(5.0, 80.0, ctx.frame_timestamp),
-        );
+        if self.debug_overlay {
+            self.text_renderer.queue_debug_text(
+                &debug_overlay_text(ctx),
+                self.top_bar
+                    .topbar_expand_animation
+                    .animate_bool(5.0, 80.0, ctx.frame_timestamp),
+            );
+        }
         self.text_renderer.update(
             ctx.window_state.physical_size,
             ctx.window_state.scale_factor as f32,
         );
 
         if self.player.is_finished() && !self.player.is_paused() {
-            ctx.proxy
-                .send_event(NeothesiaEvent::MainMenu(Some(self.player.song().clone())))
-                .ok();
+            self.go_to_main_menu(ctx);
         }
     }
 
@@ -276,23 +509,56 @@ impl Scene for PlayingScene {
         }
 
         if event.back_mouse_pressed() || event.key_released(Key::Named(NamedKey::Escape)) {
-            ctx.proxy
-                .send_event(NeothesiaEvent::MainMenu(Some(self.player.song().clone())))
-                .ok();
+            self.go_to_main_menu(ctx);
+        }
+
+        if let WindowEvent::CloseRequested = event {
+            self.save_song_state(ctx);
         }
 
         if event.key_released(Key::Named(NamedKey::Space)) {
             self.player.pause_resume();
         }
 
-        handle_settings_input(ctx, &mut self.toast_manager, &mut self.waterfall, event);
-        super::handle_pc_keyboard_to_midi_event(ctx, event);
+        if event.key_released(Key::Named(NamedKey::F3)) {
+            self.debug_overlay = !self.debug_overlay;
+        }
+
+        let pc_keyboard_input = super::handle_pc_keyboard_to_midi_event(
+            &mut self.pc_keyboard,
+            &mut self.keyboard,
+            ctx,
+            event,
+        );
+        match pc_keyboard_input {
+            PcKeyboardInput::Ignored => {
+                self.handle_bookmarks_input(event);
+                handle_settings_input(ctx, &mut self.toast_manager, &mut self.waterfall, event);
+            }
+            // The key is a piano key, don't trigger the shortcuts on it
+            PcKeyboardInput::Note => {}
+            PcKeyboardInput::Octave(base_note) => {
+                self.toast_manager
+                    .toast(format!("Keyboard Octave: C{}", base_note as i8 / 12 - 1));
+            }
+            PcKeyboardInput::Velocity(velocity) => {
+                self.toast_manager
+                    .toast(format!("Keyboard Velocity: {velocity}"));
+            }
+        }
+
         super::handle_mouse_to_midi_event(
             &mut self.keyboard,
             &mut self.mouse_to_midi_state,
             ctx,
             event,
         );
+        super::handle_touch_to_midi_event(
+            &mut self.keyboard,
+            &mut self.touch_to_midi_state,
+            ctx,
+            event,
+        );
 
         if event.window_resized() || event.scale_factor_changed() {
             self.resize(ctx)
@@ -301,9 +567,43 @@ impl Scene for PlayingScene {
         super::handle_nuon_window_event(&mut self.nuon, event, ctx);
     }
 
-    fn midi_event(&mut self, _ctx: &mut Context, channel: u8, message: &MidiMessage) {
-        self.player.user_midi_event(channel, message);
-        self.keyboard.user_midi_event(message);
+    fn midi_event(&mut self, _ctx: &mut Context, event: &MidiInputEvent) {
+        self.player
+            .user_midi_event(event.channel, &event.message, event.timestamp);
+        self.keyboard.user_midi_event(&event.message);
+    }
+
+    fn transport_event(&mut self, _ctx: &mut Context, event: &TransportEvent) {
+        if self.player.transport_event(event.message, event.timestamp) {
+            self.keyboard.reset_notes();
+        }
+    }
+
+    fn audio_recording_stopped(
+        &mut self,
+        _ctx: &mut Context,
+        result: Result<AudioRecording, AudioRecorderError>,
+    ) {
+        match result {
+            Ok(recording) => self.toast_manager.toast(format!(
+                "Audio output changed, saved audio to {}",
+                recording.path.display()
+            )),
+            Err(err) => self.toast_manager.toast(err.to_string()),
+        }
+    }
+
+    fn midi_device_event(&mut self, _ctx: &mut Context, event: &MidiDeviceEvent) {
+        match event {
+            MidiDeviceEvent::Disconnected(name) => {
+                // Keys held on the device will never be released
+                self.keyboard.reset_notes();
+                self.toast_manager.toast(format!("Disconnected: {name}"));
+            }
+            MidiDeviceEvent::Reconnected(name) => {
+                self.toast_manager.toast(format!("Reconnected: {name}"));
+            }
+        }
     }
 }
 
@@ -376,3 +676,21 @@ fn handle_settings_input(
         toast_manager.offset_toast(ctx.config.animation_offset());
     }
 }
+
+fn debug_overlay_text(ctx: &Context) -> String {
+    let mut text = format!("FPS: {}", ctx.fps_ticker.avg().round() as u32);
+
+    if let Some(latency) = ctx.output_manager.connection().latency() {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        text += &format!(
+            "\nAudio: {} frames @ {} Hz ({:.1} ms)\nOutput latency: {:.1} ms\nEvent latency: {:.1} ms",
+            latency.buffer_frames,
+            latency.sample_rate,
+            ms(latency.buffer_duration()),
+            ms(latency.output_latency),
+            ms(latency.event_latency),
+        );
+    }
+
+    text
+}
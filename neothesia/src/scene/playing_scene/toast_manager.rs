@@ -29,6 +29,10 @@ impl ToastManager {
         self.toast(format!("Animation Speed: {speed}"));
     }
 
+    pub fn loop_practice_toast(&mut self, streak: u32, target: u32) {
+        self.toast(format!("Clean pass {streak}/{target}"));
+    }
+
     pub fn offset_toast(&mut self, offset: f32) {
         self.toast(format!("Offset: {}", (offset * 100.0).round() / 100.0));
     }
@@ -1,6 +1,6 @@
 use std::time::{Duration, Instant};
 
-use crate::{NeothesiaEvent, context::Context, icons};
+use crate::{context::Context, icons};
 
 use super::{
     PlayingScene,
@@ -45,6 +45,10 @@ impl TopBar {
         self.looper_active
     }
 
+    pub fn set_looper_active(&mut self, active: bool) {
+        self.looper_active = active;
+    }
+
     pub fn loop_start_timestamp(&self) -> Duration {
         self.loop_start
     }
@@ -53,6 +57,11 @@ impl TopBar {
         self.loop_end
     }
 
+    pub fn set_loop(&mut self, start: Duration, end: Duration) {
+        self.loop_start = start;
+        self.loop_end = end;
+    }
+
     #[profiling::function]
     pub fn update(scene: &mut PlayingScene, ctx: &mut Context) {
         let PlayingScene { top_bar, .. } = scene;
@@ -116,9 +125,7 @@ impl TopBar {
 
     fn panel_left(this: &mut PlayingScene, ctx: &mut Context, ui: &mut nuon::Ui) {
         if Self::button().icon(icons::left_arrow_icon()).build(ui) {
-            ctx.proxy
-                .send_event(NeothesiaEvent::MainMenu(Some(this.player.song().clone())))
-                .ok();
+            this.go_to_main_menu(ctx);
         }
     }
 
@@ -200,6 +207,16 @@ impl TopBar {
                         this.top_bar.loop_start = this.player.time();
                         this.top_bar.loop_end = this.player.time() + Duration::from_secs(5);
                     }
+
+                    // Practice mode makes no sense without a loop
+                    if !this.top_bar.looper_active && this.loop_practice.is_active() {
+                        this.loop_practice
+                            .toggle(this.player.play_along().mistakes());
+                    }
+                }
+
+                if this.top_bar.looper_active {
+                    Self::loop_practice_button(this, ctx, ui);
                 }
 
                 nuon::translate().x(-30.0).add_to_current(ui);
@@ -214,9 +231,58 @@ impl TopBar {
                 {
                     this.player.pause_resume();
                 }
+
+                nuon::translate().x(-30.0).add_to_current(ui);
+
+                let (color, hover_color) = if ctx.output_manager.audio_recorder().is_some() {
+                    ([208, 18, 0], [165, 47, 47])
+                } else {
+                    ([37, 35, 42], [87, 87, 87])
+                };
+
+                if Self::button()
+                    .icon(icons::soundwave_icon())
+                    .color(color)
+                    .hover_color(hover_color)
+                    .preseed_color(color)
+                    .build(ui)
+                {
+                    this.toggle_audio_recording(ctx);
+                }
             });
     }
 
+    fn loop_practice_button(this: &mut PlayingScene, ctx: &mut Context, ui: &mut nuon::Ui) {
+        let w = 50.0;
+        nuon::translate().x(-w).add_to_current(ui);
+
+        let practice = &this.loop_practice;
+        let label = format!(
+            "{}/{}",
+            practice.streak(),
+            ctx.config.loop_practice_target()
+        );
+
+        let (color, hover_color) = if practice.is_active() {
+            ([255, 56, 187], [255, 86, 207])
+        } else {
+            ([37, 35, 42], [87, 87, 87])
+        };
+
+        if Self::button()
+            .id("loop_practice")
+            .width(w)
+            .label(label)
+            .color(color)
+            .hover_color(hover_color)
+            .preseed_color(color)
+            .build(ui)
+        {
+            this.loop_practice
+                .toggle(this.player.play_along().mistakes());
+        }
+    }
+
     fn proggress_bar(this: &mut PlayingScene, ctx: &mut Context, ui: &mut nuon::Ui) {
         let h = 45.0;
         let w = ctx.window_state.logical_size.width;
@@ -279,6 +345,15 @@ impl TopBar {
 
             nuon::quad().x(x).size(1.0, h).color(color).build(ui);
         }
+
+        for bookmark in this.bookmarks() {
+            let x = this.player.time_to_percentage(bookmark) * w;
+            nuon::quad()
+                .x(x - 1.0)
+                .size(3.0, h)
+                .color([255, 210, 60])
+                .build(ui);
+        }
     }
 
     fn proggress_bar_looper<'a>(
@@ -1,4 +1,7 @@
+use std::path::PathBuf;
+
 use midi_file::MidiTrack;
+use neothesia_core::config::{Config, TrackPlayerV1, TrackStateV1};
 
 use crate::context::Context;
 
@@ -9,11 +12,40 @@ pub enum PlayerConfig {
     Human,
 }
 
+impl From<TrackPlayerV1> for PlayerConfig {
+    fn from(player: TrackPlayerV1) -> Self {
+        match player {
+            TrackPlayerV1::Mute => Self::Mute,
+            TrackPlayerV1::Auto => Self::Auto,
+            TrackPlayerV1::Human => Self::Human,
+        }
+    }
+}
+
+impl From<PlayerConfig> for TrackPlayerV1 {
+    fn from(player: PlayerConfig) -> Self {
+        match player {
+            PlayerConfig::Mute => Self::Mute,
+            PlayerConfig::Auto => Self::Auto,
+            PlayerConfig::Human => Self::Human,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TrackConfig {
     pub track_id: usize,
     pub player: PlayerConfig,
     pub visible: bool,
+    /// Name of the output this track is routed to, `None` for the default output
+    pub output: Option<String>,
+    /// Velocity multiplier in `0.0..=1.0` range
+    pub volume: f32,
+    /// Program that replaces `ProgramChange` events from the file
+    pub program: Option<u8>,
+    pub solo: bool,
+    /// SoundFont the track presets are taken from, `None` for the whole font stack
+    pub soundfont: Option<PathBuf>,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -31,6 +63,11 @@ impl SongConfig {
                     track_id: t.track_id,
                     player: PlayerConfig::Auto,
                     visible: !is_drums,
+                    output: None,
+                    volume: 1.0,
+                    program: None,
+                    solo: false,
+                    soundfont: None,
                 }
             })
             .collect();
@@ -38,30 +75,91 @@ impl SongConfig {
             tracks: tracks.into(),
         }
     }
+
+    fn restore(&mut self, saved: &[TrackStateV1]) {
+        for state in saved {
+            if let Some(track) = self.tracks.get_mut(state.track_id) {
+                track.player = state.player.into();
+                track.visible = state.visible;
+                track.output = state.output.clone();
+                track.volume = state.volume;
+                track.program = state.program;
+                track.solo = state.solo;
+                track.soundfont = state.soundfont.clone();
+            }
+        }
+    }
+
+    pub fn has_solo(&self) -> bool {
+        self.tracks.iter().any(|track| track.solo)
+    }
+
+    fn to_state(&self) -> Vec<TrackStateV1> {
+        self.tracks
+            .iter()
+            .map(|track| TrackStateV1 {
+                track_id: track.track_id,
+                player: track.player.into(),
+                visible: track.visible,
+                output: track.output.clone(),
+                volume: track.volume,
+                program: track.program,
+                solo: track.solo,
+                soundfont: track.soundfont.clone(),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Song {
     pub file: midi_file::MidiFile,
     pub config: SongConfig,
+    /// Path of the file on disk, `None` for songs that were never saved (eg. freeplay recordings)
+    pub path: Option<PathBuf>,
 }
 
 impl Song {
     pub fn new(file: midi_file::MidiFile) -> Self {
         let config = SongConfig::new(&file.tracks);
-        Self { file, config }
+        Self {
+            file,
+            config,
+            path: None,
+        }
+    }
+
+    /// Create a song for a file on disk, restoring track config saved in the history
+    pub fn with_path(file: midi_file::MidiFile, path: PathBuf, config: &Config) -> Self {
+        let mut song = Self::new(file);
+
+        if let Some(state) = config.song_state(&path)
+            && state.tracks.len() == song.config.tracks.len()
+        {
+            song.config.restore(&state.tracks);
+        }
+
+        song.path = Some(path);
+        song
+    }
+
+    /// Remember current track config in the history
+    pub fn save_tracks_config(&self, config: &mut Config) {
+        if let Some(path) = self.path.as_ref() {
+            config.song_state_mut(path).tracks = self.config.to_state();
+        }
     }
 
     pub fn from_env(ctx: &Context) -> Option<Self> {
         let args: Vec<String> = std::env::args().collect();
-        let midi_file = if args.len() > 1 {
-            midi_file::MidiFile::new(&args[1]).ok()
-        } else if let Some(last) = ctx.config.last_opened_song() {
-            midi_file::MidiFile::new(last).ok()
+        let path = if args.len() > 1 {
+            PathBuf::from(&args[1])
         } else {
-            None
+            ctx.config.last_opened_song()?.clone()
         };
 
-        Some(Self::new(midi_file?))
+        let midi_file = midi_file::MidiFile::new(&path).ok()?;
+
+        Some(Self::with_path(midi_file, path, &ctx.config))
     }
 }
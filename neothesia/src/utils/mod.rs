@@ -1,7 +1,5 @@
 use std::{future::Future, pin::Pin, task::Waker};
 
-pub use neothesia_core::utils::*;
-
 pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
 
 pub mod task;